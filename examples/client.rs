@@ -2,7 +2,7 @@ extern crate tunm;
 
 use std::thread;
 
-use tunm::{GlobalConfig, LuaEngine, register_custom_func, MioEventMgr, FileUtils, DbPool, RedisPool};
+use tunm::{GlobalConfig, LuaEngine, register_custom_func, MioEventMgr, FileUtils, DbPool, RedisPool, ProtocolMgr};
 
 use std::env;
 
@@ -25,6 +25,9 @@ fn main() {
     let success = RedisPool::instance().set_url_list(global_config.get_redis_url_list());
     assert_eq!(success, true);
 
+    let success = ProtocolMgr::instance().load_config(global_config);
+    assert_eq!(success, true);
+
     let lua = LuaEngine::instance().get_lua();
     for (key, value) in &global_config.lua_macros {
         let value = &**value;
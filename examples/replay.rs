@@ -0,0 +1,91 @@
+extern crate tunm;
+extern crate commander;
+
+use commander::Commander;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use tunm::{CaptureMgr, CAPTURE_IN};
+
+/// replay the client frames of a capture file to the server as a fake client
+/// cargo run --example replay -- -f capture.tncp -a 127.0.0.1:11329 -u NM:12 -s 2
+fn main() {
+    let command = Commander::new()
+                .version(&env!("CARGO_PKG_VERSION").to_string())
+                .usage("replay")
+                .usage_desc("replay the capture file to tunm server.")
+                .option_str("-f, --file [value]", "capture file ", Some("capture.tncp".to_string()))
+                .option_str("-a, --addr [value]", "server addr ", Some("127.0.0.1:11329".to_string()))
+                .option_str("-u, --unique [value]", "replay unique, default the first in file ", None)
+                .option_float("-s, --speed [value]", "replay speed, 0 means no wait ", Some(1.0))
+                .parse_env_or_exit()
+                ;
+
+    let file = command.get_str("f").unwrap();
+    let addr = command.get_str("a").unwrap();
+    let speed = command.get_float("s").unwrap_or(1.0);
+
+    let records = match CaptureMgr::read_capture_file(&*file) {
+        Ok(records) => records,
+        Err(err) => {
+            println!("read capture file {} failed {:?}", file, err);
+            return;
+        }
+    };
+
+    let unique = match command.get_str("u") {
+        Some(unique) => unique,
+        None => {
+            match records.iter().find(|r| r.direction == CAPTURE_IN) {
+                Some(record) => record.unique.clone(),
+                None => {
+                    println!("no client frame in capture file {}", file);
+                    return;
+                }
+            }
+        }
+    };
+
+    let records: Vec<_> = records.into_iter().filter(|r| r.direction == CAPTURE_IN && r.unique == unique).collect();
+    println!("replay {} frames of {} to {} speed {}", records.len(), unique, addr, speed);
+
+    let mut stream = match TcpStream::connect(&*addr) {
+        Ok(stream) => stream,
+        Err(err) => {
+            println!("connect server {} failed {:?}", addr, err);
+            return;
+        }
+    };
+
+    let mut reader = stream.try_clone().unwrap();
+    thread::spawn(move || {
+        let mut data = [0u8; 65536];
+        let mut total = 0;
+        loop {
+            match reader.read(&mut data) {
+                Ok(0) | Err(_) => {
+                    println!("server closed, recv total {} bytes", total);
+                    break;
+                }
+                Ok(n) => total += n,
+            }
+        }
+    });
+
+    let mut last_time = records.first().map(|r| r.time).unwrap_or(0);
+    for (i, record) in records.iter().enumerate() {
+        if speed > 0.0 && record.time > last_time {
+            let wait = (record.time - last_time) as f64 / speed;
+            thread::sleep(Duration::from_millis(wait as u64));
+        }
+        last_time = record.time;
+        if let Err(err) = stream.write_all(&record.data) {
+            println!("send frame {} failed {:?}", i, err);
+            return;
+        }
+    }
+    println!("replay finish, wait server respone");
+    thread::sleep(Duration::from_secs(1));
+}
@@ -9,7 +9,7 @@ extern crate commander;
 use commander::Commander;
 use std::thread;
 use log::{warn};
-use tunm::{GlobalConfig, LuaEngine, register_custom_func, MioEventMgr, FileUtils, DbPool, RedisPool, TelnetUtils, LogUtils};
+use tunm::{GlobalConfig, LuaEngine, register_custom_func, MioEventMgr, FileUtils, DbPool, RedisPool, ProtocolMgr, TelnetUtils, LogUtils};
 
 use std::env;
 
@@ -67,6 +67,9 @@ fn main() {
     let success = RedisPool::instance().set_url_list(global_config.get_redis_url_list());
     assert_eq!(success, true);
 
+    let success = ProtocolMgr::instance().load_config(global_config);
+    assert_eq!(success, true);
+
     let lua = LuaEngine::instance().get_lua();
     for (key, value) in &global_config.lua_macros {
         let value = &**value;
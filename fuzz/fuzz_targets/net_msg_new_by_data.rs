@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use tunm::NetMsg;
+
+// the whole frame recv from the tcp client or websocket server
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut net_msg) = NetMsg::new_by_data(data) {
+        let _ = net_msg.read_head();
+        net_msg.set_read_data();
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use tunm::NetMsg;
+
+// the websocket client recv frame without the head
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut net_msg) = NetMsg::new_by_proto_data(data) {
+        let _ = net_msg.read_head();
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::io::Write;
+use tunm::NetMsg;
+
+// forward_to_port and send_netmsg read the head of the message packed by lua
+fuzz_target!(|data: &[u8]| {
+    let mut net_msg = NetMsg::new();
+    net_msg.set_wpos(0);
+    let _ = net_msg.write(data);
+    let _ = net_msg.read_head();
+});
@@ -0,0 +1,14 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use td_rlua::Lua;
+use tunm::{EngineProtocol, NetMsg, ProtoRt};
+
+// the tunm_proto decoder push the decoded message to lua
+fuzz_target!(|data: &[u8]| {
+    let mut lua = Lua::new();
+    let mut proto = ProtoRt {};
+    if let Ok(mut net_msg) = NetMsg::new_by_proto_data(data) {
+        let _ = proto.unpack_protocol(lua.state(), &mut net_msg);
+        let _ = proto.convert_string(lua.state(), &mut net_msg);
+    }
+});
@@ -0,0 +1,22 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::io::Write;
+use tunm::{MioEventMgr, NetMsg};
+use tunm_proto::Buffer;
+
+// the tcp stream may split or merge the frames, so feed the data by the first byte as chunk size
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+    let chunk = data[0] as usize + 1;
+    let mut buffer = Buffer::new();
+    for part in data[1..].chunks(chunk) {
+        let _ = buffer.write(part);
+        while let Some(message) = MioEventMgr::get_next_message(&mut buffer) {
+            if NetMsg::new_by_data(&message[..]).is_err() {
+                return;
+            }
+        }
+    }
+});
@@ -62,8 +62,9 @@ function unregister_new_connection_callback(cookie)
 end
 
 -- 连接断开的回调
-function cmd_lost_connection(port_no, reason)
-    TRACE("断开连接(%d) 原因(%o)", port_no, reason)
+-- err_kind 为系统错误类型, 如 ConnectionReset, BrokenPipe, 正常关闭时为空字符串
+function cmd_lost_connection(port_no, reason, err_kind)
+    TRACE("断开连接(%o) 原因(%o) 错误(%o)", port_no, reason, err_kind)
     -- 取得该端口对应的 agent
     local agent = find_agent_by_port(port_no)
     if agent == nil then
@@ -74,6 +75,16 @@ function cmd_lost_connection(port_no, reason)
     agent:connection_lost()
 end
 
+-- 发起连接失败的回调
+-- reconnecting 为 true 时稍后会自动重连, 否则以 fd 为 -1 通知发起连接的回调
+function cmd_connect_failed(cookie, reason, reconnecting)
+    TRACE("连接失败(%o) 原因(%o) 重连(%o)", cookie, reason, reconnecting)
+    if reconnecting or not socket_cookie_map[cookie] then
+        do return end
+    end
+    socket_connect_callback(cookie, -1)
+end
+
 function get_server_type(client_ip, server_port)
     if SERVER_TYPE == "logic" then
         -- for _,value in ipairs(GATE_SERVER) do
@@ -406,10 +417,11 @@ function http_server_msg_recv(cookie, url, body, remote_host)
     http_server_respone(cookie, "hello world from lua")
 end
 
-function http_client_msg_respone(cookie, success, body)
+-- headers and err only from http_request, err is set if status is 0
+function http_client_msg_respone(cookie, status, body, headers, err)
     cookie = tonumber(cookie)
-    success = success == "true" or success == "1"
-    TRACE("http_client_msg_respone args is %o", {cookie, success, body})
+    status = tonumber(status)
+    TRACE("http_client_msg_respone args is %o", {cookie, status, body, headers, err})
 end
 
 function set_max_online_num(num)
@@ -0,0 +1,71 @@
+extern crate tunm;
+extern crate commander;
+
+use commander::Commander;
+
+use tunm::{ProtoSchema, ProtoCompat};
+
+/// check the new protocol.txt can talk with the old one before deploy,
+/// exit 1 if has the breaking change, exit 2 if the protocol load failed, the duplicate key of
+/// the old protocol is only the warning
+/// cargo run --bin proto_compat -- -o old/protocol.txt -n config/protocol.txt
+fn main() {
+    let command = Commander::new()
+                .version(&env!("CARGO_PKG_VERSION").to_string())
+                .usage("proto_compat")
+                .usage_desc("diff two protocol.txt and report the breaking changes.")
+                .option_str("-o, --old [value]", "old protocol file ", None)
+                .option_str("-n, --new [value]", "new protocol file ", Some("config/protocol.txt".to_string()))
+                .option("-q, --quiet", "only print the breaking changes ", None)
+                .parse_env_or_exit()
+                ;
+
+    let old = match command.get_str("o") {
+        Some(old) => old,
+        None => {
+            println!("the old protocol file is required, use -o");
+            ::std::process::exit(2);
+        }
+    };
+    let new = command.get_str("n").unwrap();
+    let quiet = command.get("q").unwrap_or(false);
+
+    // the old protocol is only the baseline, its definition error is the warning
+    let load = |path: &str, lenient: bool| {
+        let loaded = if lenient {
+            ProtoSchema::load_lenient(path)
+        } else {
+            ProtoSchema::load(path).map(|schema| (schema, vec![]))
+        };
+        let (schema, duplicates) = match loaded {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                println!("load protocol {} failed {:?}", path, err);
+                ::std::process::exit(2);
+            }
+        };
+        for duplicate in &duplicates {
+            println!("warning: protocol {} {}, the last one used", path, duplicate);
+        }
+        if let Err(err) = schema.check() {
+            if !lenient {
+                println!("check protocol {} failed {}", path, err);
+                ::std::process::exit(2);
+            }
+            println!("warning: protocol {} {}", path, err);
+        }
+        schema
+    };
+    let report = ProtoCompat::diff(&load(&*old, true), &load(&*new, false));
+
+    for change in &report.changes {
+        if !quiet || change.breaking {
+            println!("{}", change);
+        }
+    }
+    let breaking = report.get_breaking().len();
+    println!("{} changes, {} breaking", report.changes.len(), breaking);
+    if breaking > 0 {
+        ::std::process::exit(1);
+    }
+}
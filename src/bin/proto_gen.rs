@@ -0,0 +1,61 @@
+extern crate tunm;
+extern crate commander;
+
+use commander::Commander;
+use std::fs;
+use std::path::Path;
+
+use tunm::{ProtoSchema, ProtoCodegen};
+
+/// generate the client sdk of the td protocol from protocol.txt
+/// cargo run --bin proto_gen -- -p config/protocol.txt -l all -o sdk
+fn main() {
+    let command = Commander::new()
+                .version(&env!("CARGO_PKG_VERSION").to_string())
+                .usage("proto_gen")
+                .usage_desc("generate the client sdk(ts, cs, lua) from protocol.txt.")
+                .option_str("-p, --protocol [value]", "protocol file ", Some("config/protocol.txt".to_string()))
+                .option_str("-l, --lang [value]", "ts, cs, lua or all ", Some("all".to_string()))
+                .option_str("-o, --out [value]", "output dir ", Some(".".to_string()))
+                .parse_env_or_exit()
+                ;
+
+    let protocol = command.get_str("p").unwrap();
+    let lang = command.get_str("l").unwrap();
+    let out = command.get_str("o").unwrap();
+
+    let langs: Vec<&str> = if lang == "all" {
+        ProtoCodegen::get_langs().to_vec()
+    } else {
+        lang.split(',').map(|l| l.trim()).collect()
+    };
+
+    let schema = match ProtoSchema::load(&*protocol) {
+        Ok(schema) => schema,
+        Err(err) => {
+            println!("load protocol {} failed {:?}", protocol, err);
+            ::std::process::exit(1);
+        }
+    };
+
+    if let Err(err) = fs::create_dir_all(&*out) {
+        println!("create dir {} failed {:?}", out, err);
+        ::std::process::exit(1);
+    }
+
+    for lang in langs {
+        let code = match ProtoCodegen::generate(&schema, lang) {
+            Ok(code) => code,
+            Err(err) => {
+                println!("generate lang {} failed {:?}", lang, err);
+                ::std::process::exit(1);
+            }
+        };
+        let path = Path::new(&*out).join(ProtoCodegen::get_file_name(lang).unwrap_or(lang));
+        if let Err(err) = fs::write(&path, code) {
+            println!("write {:?} failed {:?}", path, err);
+            ::std::process::exit(1);
+        }
+        println!("generate {} protos to {:?}", schema.get_protos().len(), path);
+    }
+}
@@ -1,6 +1,6 @@
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
-use FileUtils;
+use {FileUtils, SchemaConfig, HandshakeConfig, WebsocketConfig, HttpConfig};
 /// it will read config for file
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GlobalConfig {
@@ -8,6 +8,22 @@ pub struct GlobalConfig {
     pub start_lua: String,
     pub db_info: HashMap<String, String>,
     pub telnet_addr: Option<String>,
+    /// the msg_type to the built-in protocol name(td, json, bin, text, protobuf, msgpack)
+    pub protocols: Option<HashMap<u8, String>>,
+    /// the msg_type to the schema path, such as the .proto file or directory for protobuf
+    pub protocol_schemas: Option<HashMap<u8, String>>,
+    /// encode the empty lua table as the json array `[]`, default the object `{}`
+    pub json_empty_array: Option<bool>,
+    /// validate the incoming td message by the protocol.txt before dispatch to lua
+    pub proto_schema: Option<SchemaConfig>,
+    /// negotiate the protocol version by the first frame before dispatch to lua
+    pub handshake: Option<HandshakeConfig>,
+    /// the websocket listen port to the settings
+    pub websockets: Option<HashMap<u16, WebsocketConfig>>,
+    /// the pem ca file the wss client trust, such as the self-signed certificate, default the system ca
+    pub wss_ca: Option<String>,
+    /// the limits of the http server requests waiting the lua response
+    pub http: Option<HttpConfig>,
 }
 static mut EL: *mut GlobalConfig = 0 as *mut _;
 
@@ -20,6 +36,14 @@ impl GlobalConfig {
                     db_info: HashMap::new(),
                     start_lua: "main.lua".to_string(),
                     telnet_addr: None,
+                    protocols: None,
+                    protocol_schemas: None,
+                    json_empty_array: None,
+                    proto_schema: None,
+                    handshake: None,
+                    websockets: None,
+                    wss_ca: None,
+                    http: None,
                 };
                 EL = Box::into_raw(Box::new(config));
             }
@@ -21,7 +21,17 @@ extern crate mio;
 extern crate tunm_timer;
 extern crate serde;
 extern crate serde_yaml;
+extern crate serde_json;
 extern crate rand;
+extern crate prost;
+extern crate prost_reflect;
+extern crate protox;
+extern crate rmpv;
+extern crate openssl;
+extern crate base64;
+extern crate httparse;
+extern crate flate2;
+extern crate percent_encoding;
 
 #[macro_use] extern crate log;
 
@@ -42,15 +52,21 @@ mod game;
 pub use global_config::GlobalConfig;
 pub use db::{DbTrait, DbMysql, DbPool, PoolTrait, RedisPool};
 pub use values::{ErrorKind, NetResult, make_extension_error};
-pub use utils::{FileUtils, TimeUtils, ThreadUtils, NetUtils, TelnetUtils, LogUtils, log_utils, LuaUtils};
+pub use utils::{FileUtils, TimeUtils, ThreadUtils, NetUtils, TelnetUtils, LogUtils, log_utils, LuaUtils, JsonUtils};
 pub use rp_wrapper::{LuaWrapperValue, LuaWrapperVecValue, LuaWrapperTableValue};
 pub use redis_wrapper::{RedisWrapperResult, RedisWrapperCmd, RedisWrapperMsg,
                         RedisWrapperVecVec};
 pub use lua_engine::LuaEngine;
-pub use mgr::{HttpMgr, CommandMgr, MioEventMgr, ProtocolMgr, WebSocketMgr, TcpMgr, WebsocketClient};
+pub use mgr::{HttpMgr, HttpConfig, HttpStats, HttpClientOptions, HttpRouter, HttpRequest, HttpRouteMatch, CommandMgr, MioEventMgr, ProtocolMgr, WebSocketMgr, TcpMgr, WebsocketClient, WebsocketConnectOptions, WebsocketConfig,
+              WebsocketRequest, WebsocketAccept, WebsocketHook,
+              CaptureMgr, CaptureRecord, CAPTURE_IN, CAPTURE_OUT};
 pub use lua_custom::register_custom_func;
-pub use net::{NetMsg, AsSocket, SocketEvent, AcceptCb, ReadCb, WriteCb, EndCb, MSG_TYPE_TD, MSG_TYPE_JSON, MSG_TYPE_BIN, MSG_TYPE_TEXT};
-pub use protocol::{EngineProtocol, ProtoRt, ProtoJson, ProtoBin, ProtoText};
+pub use net::{NetMsg, AsSocket, SocketEvent, SocketStats, LoopbackStream, LoopbackPeer, WsFrame, WsUpgrade, WsSession, ws_deflate, ws_inflate, AcceptCb, ReadCb, WriteCb, EndCb, MSG_TYPE_TD, MSG_TYPE_JSON, MSG_TYPE_BIN, MSG_TYPE_TEXT, MSG_TYPE_PROTOBUF, MSG_TYPE_MSGPACK,
+    HandshakeConfig, HandshakeRequest, HandshakeInfo, HandshakeStep, MSG_TYPE_HANDSHAKE,
+    HANDSHAKE_ACCEPT, HANDSHAKE_DOWNGRADE, HANDSHAKE_REJECT};
+pub use protocol::{EngineProtocol, ProtoRt, ProtoJson, ProtoBin, ProtoText, ProtoProtobuf, ProtoMsgpack,
+    ProtoSchema, SchemaConfig, SchemaType, SchemaPattern, SchemaProto, ProtoCodegen,
+    ProtoCompat, CompatReport, CompatChange, CompatKind};
 pub use game::{MaJiang, KindItem};
 
 pub use td_rthreadpool::ThreadPool;
@@ -1,10 +1,12 @@
 use libc;
+use std::collections::HashMap;
 use td_rlua::{self, Lua, LuaPush};
-use ws;
+use tunm_proto::Value;
+use serde_json::{self, Value as JsonValue};
 use mio::net::TcpStream;
-use {MioEventMgr, ProtocolMgr, NetMsg, ThreadUtils, 
-    HttpMgr, WebSocketMgr, SocketEvent,
-    LuaUtils, WebsocketClient};
+use {MioEventMgr, ProtocolMgr, NetMsg, ThreadUtils, LuaEngine,
+    HttpMgr, HttpClientOptions, WebSocketMgr, SocketEvent, SocketStats,
+    LuaUtils, WebsocketClient, WebsocketConnectOptions, LuaWrapperValue, CaptureMgr, JsonUtils, CompatKind, LogUtils, log_utils};
 
 static LUA_POOL_NAME: &'static str = "lua";
 static TEST_WEBSOCKET_POOL_NAME: &'static str = "test_webscoket";
@@ -13,6 +15,16 @@ fn close_fd(unique: String) {
     MioEventMgr::instance().close_fd(&unique, "Script Close".to_string());
 }
 
+/// shutdown the write after the message sent, the connection closed when the peer close,
+/// or after timeout ms, default 5000
+extern "C" fn shutdown_fd(lua: *mut td_rlua::lua_State) -> libc::c_int {
+    let unique: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 1), return 0);
+    let timeout: Option<u64> = td_rlua::LuaRead::lua_read_at_position(lua, 2);
+    let success = MioEventMgr::instance().shutdown_fd(&unique, "Script Shutdown".to_string(), timeout.unwrap_or(5000));
+    success.push_to_lua(lua);
+    1
+}
+
 fn forward_to_port(unique: String, net_msg: &mut NetMsg) -> i32 {
     if net_msg.len() < NetMsg::min_len() {
         println!("forward_to_port {:?}", net_msg.len());
@@ -42,6 +54,64 @@ fn send_msg_to_port(unique: String, net_msg: &mut NetMsg) -> i32 {
     }
 }
 
+/// load_protocol_schema(msg_type, path) return true or false and the error
+extern "C" fn load_protocol_schema(lua: *mut td_rlua::lua_State) -> libc::c_int {
+    let msg_type: u8 = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 1), return 0);
+    let path: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 2), return 0);
+    match ProtocolMgr::instance().load_schema(msg_type, &path) {
+        Ok(_) => {
+            true.push_to_lua(lua);
+            1
+        }
+        Err(err) => {
+            false.push_to_lua(lua);
+            format!("{}", err).push_to_lua(lua);
+            2
+        }
+    }
+}
+
+/// reload_protocol(path) return true and the changes {added, changed, removed, fields, breaking},
+/// the path nil reload the loaded protocol.txt, return false and the error when failed
+extern "C" fn reload_protocol(lua: *mut td_rlua::lua_State) -> libc::c_int {
+    let path: Option<String> = if unsafe { td_rlua::lua_isstring(lua, 1) } == 0 {
+        None
+    } else {
+        td_rlua::LuaRead::lua_read_at_position(lua, 1)
+    };
+    let report = match ProtocolMgr::instance().reload_proto_schema(path) {
+        Ok(report) => report,
+        Err(err) => {
+            false.push_to_lua(lua);
+            format!("{}", err).push_to_lua(lua);
+            return 2;
+        }
+    };
+    let mut changes: HashMap<&str, Vec<String>> = HashMap::new();
+    for key in &["added", "changed", "removed", "fields", "breaking"] {
+        changes.insert(key, vec![]);
+    }
+    for change in &report.changes {
+        let key = match change.kind {
+            CompatKind::ProtoAdded => "added",
+            CompatKind::ProtoRemoved => "removed",
+            CompatKind::ProtoMsgTypeChanged | CompatKind::ProtoArgAdded
+                | CompatKind::ProtoArgRemoved | CompatKind::ProtoArgTypeChanged => "changed",
+            _ => "fields",
+        };
+        let names = changes.get_mut(key).unwrap();
+        if !names.contains(&change.name) {
+            names.push(change.name.clone());
+        }
+        if change.breaking {
+            changes.get_mut("breaking").unwrap().push(change.to_string());
+        }
+    }
+    true.push_to_lua(lua);
+    changes.push_to_lua(lua);
+    2
+}
+
 extern "C" fn pack_message(lua: *mut td_rlua::lua_State) -> libc::c_int {
 
     let msg_type: u8 = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 1), return 0);
@@ -110,19 +180,37 @@ fn new_connect(ip: String, port: u16, _timeout: i32, cookie: u32) -> i32 {
 }
 
 
-fn new_websocket_connect(ip: String, port: u16, _timeout: i32, cookie: u32) -> i32 {
+/// new_websocket_connect(ip, port, timeout, cookie, options) the ip with the `wss://` scheme connect
+/// by the tls, such as `new_websocket_connect("wss://127.0.0.1", 443, 0, cookie)`, the timeout ms
+/// limit the connect and the upgrade, 0 no limit, the options is `WebsocketConnectOptions`
+extern "C" fn new_websocket_connect(lua: *mut td_rlua::lua_State) -> libc::c_int {
+    let ip: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 1), return 0);
+    let port: u16 = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 2), return 0);
+    let timeout: Option<u64> = td_rlua::LuaRead::lua_read_at_position(lua, 3);
+    let cookie: u32 = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 4), return 0);
+    let options = match JsonUtils::lua_to_json(lua, 5, false) {
+        Some(JsonValue::Null) | None => WebsocketConnectOptions::default(),
+        Some(value) => match serde_json::from_value(value) {
+            Ok(options) => options,
+            Err(err) => {
+                let info = format!("new_websocket_connect invalid options {}", err);
+                println!("{}", info);
+                LogUtils::instance().append(log_utils::LOG_ERROR, &info);
+                return 0;
+            }
+        },
+    };
     let pool = ThreadUtils::instance().get_default_pool(&TEST_WEBSOCKET_POOL_NAME.to_string(), 100);
     pool.execute(move || {
         let ip = ip.trim_matches('\"');
-        ws::connect(&format!("ws://{}:{}", ip.trim_matches('\"'), port)[..], |sender| {
-            WebsocketClient {
-                out: sender,
-                port: port,
-                unique: String::new(),
-                cookie: cookie,
-            }
-        }).unwrap();
+        let url = if ip.starts_with("wss://") || ip.starts_with("ws://") {
+            format!("{}:{}", ip, port)
+        } else {
+            format!("ws://{}:{}", ip, port)
+        };
+        WebsocketClient::connect(url, port, timeout.unwrap_or(0), cookie, options);
     });
+    1.push_to_lua(lua);
     1
 }
 
@@ -130,6 +218,65 @@ fn http_server_respone(cookie: u32, content: String) {
     HttpMgr::instance().http_server_respone(cookie, content);
 }
 
+/// http_server_response(cookie, status, headers, body) the headers such as
+/// `{["Content-Type"] = "image/png", ["Set-Cookie"] = {"a=1", "b=2"}}`, the body is the binary
+/// string, or the table encoded to json with the default `application/json`
+extern "C" fn http_server_response(lua: *mut td_rlua::lua_State) -> libc::c_int {
+    let cookie: u32 = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 1), return 0);
+    let status: Option<u16> = td_rlua::LuaRead::lua_read_at_position(lua, 2);
+    let mut headers = HttpMgr::json_to_headers(&JsonUtils::lua_to_json(lua, 3, false).unwrap_or(JsonValue::Null));
+    let (body, content_type) = if unsafe { td_rlua::lua_type(lua, 4) } == td_rlua::LUA_TTABLE {
+        let value = JsonUtils::lua_to_json(lua, 4, false).unwrap_or(JsonValue::Null);
+        (JsonUtils::to_string(&value).into_bytes(), "application/json")
+    } else {
+        (LuaUtils::read_str_to_vec(lua, 4).unwrap_or_default(), "text/plain; charset=UTF-8")
+    };
+    HttpMgr::set_default_content_type(&mut headers, content_type);
+    let success = HttpMgr::instance().http_server_response(cookie, status.unwrap_or(200), headers, body);
+    success.push_to_lua(lua);
+    1
+}
+
+/// http_route(method, pattern, func) the func(request) handle the request matched, the method `*`
+/// match all, the pattern such as `/api/user/:rid`, the func nil remove the route, the func return
+/// `{status = 200, headers = {}, body = ""}` to respond, or nil to respond later by the `request.cookie`
+extern "C" fn http_route(lua: *mut td_rlua::lua_State) -> libc::c_int {
+    let method: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 1), return 0);
+    let pattern: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 2), return 0);
+    let func: Option<String> = if unsafe { td_rlua::lua_isstring(lua, 3) } == 0 {
+        None
+    } else {
+        td_rlua::LuaRead::lua_read_at_position(lua, 3)
+    };
+    HttpMgr::instance().set_route(&method, &pattern, func);
+    0
+}
+
+fn http_route_clear() {
+    HttpMgr::instance().clear_routes();
+}
+
+/// http_request(cookie, options) the options is `HttpClientOptions`, the invalid options respond
+/// the status 0 with the err at once
+extern "C" fn http_request(lua: *mut td_rlua::lua_State) -> libc::c_int {
+    let cookie: u32 = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 1), return 0);
+    let value = JsonUtils::lua_to_json(lua, 2, false).unwrap_or(JsonValue::Null);
+    match serde_json::from_value::<HttpClientOptions>(value) {
+        Ok(options) => HttpMgr::instance().http_request(cookie, options),
+        Err(err) => {
+            let error = format!("invalid options {}", err);
+            LuaEngine::instance().apply_http_response(cookie, 0, vec![], HashMap::new(), Some(error));
+        }
+    }
+    0
+}
+
+/// the pending, peak_pending, oldest_ms, total, expired and rejected of the http server requests
+extern "C" fn get_http_stats(lua: *mut td_rlua::lua_State) -> libc::c_int {
+    let stats = HttpMgr::instance().get_stats();
+    JsonUtils::push_json(lua, serde_json::to_value(stats).unwrap_or(JsonValue::Null))
+}
+
 fn http_get_request(cookie: u32, addr: String, url: String) {
     HttpMgr::instance().http_get_request(cookie, addr, url);
 }
@@ -138,6 +285,68 @@ fn http_post_request(cookie: u32, addr: String, url: String, body: String) {
     HttpMgr::instance().http_post_request(cookie, addr, url, body);
 }
 
+fn stats_to_lua_table(unique: String, client_ip: String, stats: SocketStats) -> HashMap<String, LuaWrapperValue> {
+    let mut table: HashMap<String, LuaWrapperValue> = stats.to_value_map().into_iter()
+        .map(|(k, v)| (k, LuaWrapperValue(v)))
+        .collect();
+    table.insert("unique".to_string(), LuaWrapperValue(Value::Str(unique)));
+    table.insert("client_ip".to_string(), LuaWrapperValue(Value::Str(client_ip)));
+    table
+}
+
+extern "C" fn get_socket_stats(lua: *mut td_rlua::lua_State) -> libc::c_int {
+    let unique: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 1), return 0);
+    let client_ip = unwrap_or!(MioEventMgr::instance().get_socket_event(&unique), return 0).get_client_ip();
+    let stats = unwrap_or!(MioEventMgr::instance().get_socket_stats(&unique), return 0);
+    stats_to_lua_table(unique, client_ip, stats).push_to_lua(lua);
+    1
+}
+
+/// the negotiated handshake {result, version, caps, msg_types}, nil if the client not send it
+extern "C" fn get_socket_handshake(lua: *mut td_rlua::lua_State) -> libc::c_int {
+    let unique: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 1), return 0);
+    let socket_event = unwrap_or!(MioEventMgr::instance().get_socket_event(&unique), return 0);
+    let info = unwrap_or!(socket_event.get_handshake().and_then(|i| serde_json::to_value(i).ok()), return 0);
+    JsonUtils::push_json(lua, info)
+}
+
+/// get_top_socket_stats(field, num) field is one of STATS_FIELDS, default bytes_in
+extern "C" fn get_top_socket_stats(lua: *mut td_rlua::lua_State) -> libc::c_int {
+    let field: Option<String> = td_rlua::LuaRead::lua_read_at_position(lua, 1);
+    let num: Option<usize> = td_rlua::LuaRead::lua_read_at_position(lua, 2);
+    let list: Vec<HashMap<String, LuaWrapperValue>> = MioEventMgr::instance()
+        .get_top_socket_stats(&field.unwrap_or("bytes_in".to_string()), num.unwrap_or(10))
+        .into_iter()
+        .map(|(unique, client_ip, stats)| stats_to_lua_table(unique, client_ip, stats))
+        .collect();
+    list.push_to_lua(lua);
+    1
+}
+
+fn capture_start(path: String) -> bool {
+    CaptureMgr::instance().start_capture(path)
+}
+
+fn capture_stop() {
+    CaptureMgr::instance().stop_capture();
+}
+
+fn capture_add_unique(unique: String) {
+    CaptureMgr::instance().add_unique(unique);
+}
+
+fn capture_remove_unique(unique: String) {
+    CaptureMgr::instance().remove_unique(&unique);
+}
+
+fn capture_add_ip(ip: String) {
+    CaptureMgr::instance().add_ip_filter(ip);
+}
+
+fn capture_clear_filters() {
+    CaptureMgr::instance().clear_filters();
+}
+
 extern "C" fn listen_server(lua: *mut td_rlua::lua_State) -> libc::c_int {
     let bind_port: u16 = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 1), return 0);
     let bind_ip: Option<String> = td_rlua::LuaRead::lua_read_at_position(lua, 2);
@@ -146,12 +355,38 @@ extern "C" fn listen_server(lua: *mut td_rlua::lua_State) -> libc::c_int {
     0
 }
 
+/// set_websocket_hook(func, timeout) the func(request) called before accept the websocket,
+/// return nil to accept, or `{status = 401, reason = "invalid token"}` to reject, or the table of
+/// the metadata read by get_socket_meta, the func nil accept all
+extern "C" fn set_websocket_hook(lua: *mut td_rlua::lua_State) -> libc::c_int {
+    if unsafe { td_rlua::lua_isstring(lua, 1) } == 0 {
+        WebSocketMgr::instance().set_handshake_hook(None);
+        return 0;
+    }
+    let func: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 1), return 0);
+    let timeout: Option<u64> = td_rlua::LuaRead::lua_read_at_position(lua, 2);
+    WebSocketMgr::instance().set_lua_handshake_hook(func, timeout.unwrap_or(3000));
+    0
+}
+
+extern "C" fn get_socket_meta(lua: *mut td_rlua::lua_State) -> libc::c_int {
+    let unique: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 1), return 0);
+    let socket_event = unwrap_or!(MioEventMgr::instance().get_socket_event(&unique), return 0);
+    socket_event.get_meta().clone().push_to_lua(lua);
+    1
+}
+
 fn listen_http(url: String) {
     HttpMgr::instance().start_listen(url);
 }
 
+/// the websocket framed in the mio loop with the tcp connections, the wss use listen_websocket
 fn listen_mio_websocket(url: String, port: u16) {
-    WebSocketMgr::instance().start_listen(url, port);
+    if let Err(err) = MioEventMgr::instance().listen_websocket(url.clone(), port) {
+        let info = format!("websocket listen {}:{} failed by {}", url, port, err);
+        println!("{}", info);
+        LogUtils::instance().append(log_utils::LOG_ERROR, &info);
+    }
 }
 
 fn listen_websocket(url: String, port: u16) {
@@ -160,20 +395,41 @@ fn listen_websocket(url: String, port: u16) {
 
 pub fn register_network_func(lua: &mut Lua) {
     lua.set("close_fd", td_rlua::function1(close_fd));
+    lua.register("shutdown_fd", shutdown_fd);
     lua.set("forward_to_port", td_rlua::function2(forward_to_port));
     lua.set("send_msg_to_port", td_rlua::function2(send_msg_to_port));
 
     lua.register("pack_message", pack_message);
+    lua.register("load_protocol_schema", load_protocol_schema);
+    lua.register("reload_protocol", reload_protocol);
     lua.register("del_message", del_message);
     lua.register("pack_raw_message", pack_raw_message);
 
+    lua.register("get_socket_stats", get_socket_stats);
+    lua.register("get_top_socket_stats", get_top_socket_stats);
+    lua.register("get_socket_handshake", get_socket_handshake);
+    lua.register("get_socket_meta", get_socket_meta);
+    lua.register("set_websocket_hook", set_websocket_hook);
+
+    lua.set("capture_start", td_rlua::function1(capture_start));
+    lua.set("capture_stop", td_rlua::function0(capture_stop));
+    lua.set("capture_add_unique", td_rlua::function1(capture_add_unique));
+    lua.set("capture_remove_unique", td_rlua::function1(capture_remove_unique));
+    lua.set("capture_add_ip", td_rlua::function1(capture_add_ip));
+    lua.set("capture_clear_filters", td_rlua::function0(capture_clear_filters));
+
     lua.register("listen_server", listen_server);
     lua.set("stop_server", td_rlua::function0(stop_server));
     lua.set("new_connect", td_rlua::function4(new_connect));
-    lua.set("new_websocket_connect", td_rlua::function4(new_websocket_connect));
+    lua.register("new_websocket_connect", new_websocket_connect);
 
     lua.set("http_server_respone",
             td_rlua::function2(http_server_respone));
+    lua.register("http_server_response", http_server_response);
+    lua.register("http_route", http_route);
+    lua.register("get_http_stats", get_http_stats);
+    lua.set("http_route_clear", td_rlua::function0(http_route_clear));
+    lua.register("http_request", http_request);
     lua.set("http_get_request", td_rlua::function3(http_get_request));
     lua.set("http_post_request", td_rlua::function4(http_post_request));
 
@@ -1,17 +1,20 @@
 use std::iter::repeat;
 use std::collections::HashMap;
 use std::ffi::{CString};
+use std::io;
+use std::sync::mpsc;
+use serde_json::Value as JsonValue;
 use crypto::aes_gcm::AesGcm;
 use crypto::aes::{KeySize};
 use crypto::aead::AeadDecryptor;
-use {NetMsg, FileUtils};
+use {NetMsg, FileUtils, JsonUtils, HttpMgr, MioEventMgr, WebsocketAccept};
 use td_rlua::{self, Lua, LuaRead};
 use libc;
 use tunm_proto;
 use std::sync::Arc;
 use td_rthreadpool::ReentrantMutex;
 use td_rredis::{RedisResult, Value};
-use super::{LuaWrapperTableValue, RedisWrapperResult};
+use super::{LuaWrapperTableValue, LuaWrapperValue, RedisWrapperResult};
 
 const AES_KEY: [u8; 32] = [
             0x60, 0x3d, 0xeb, 0x10, 0x15, 0xca, 0x71, 0xbe,
@@ -37,14 +40,24 @@ enum LuaElem {
     RedisResult(u32, Option<RedisResult<Value>>),
     /// cookie, new_fd, client_ip, server_port, websocket
     NewConnection(u32, String, String, u16, bool),
-    /// fd
-    LostConnection(String, String),
+    /// fd, reason, os error kind
+    LostConnection(String, String, String),
+    /// cookie, reason, reconnecting
+    ConnectFailed(u32, String, bool),
     /// func_str
     ExecString(String),
     /// Args fuc
     HttpCallbackFunc(String, HashMap<String, String>, Vec<String>),
     /// Args fuc
     ArgsFunc(String, Vec<String>),
+    /// func, arg, the sender of the result, the other thread wait the result
+    SyncCall(String, JsonValue, mpsc::Sender<Option<JsonValue>>),
+    /// func, unique, token, the request table of the pending websocket upgrade
+    WsHandshake(String, String, u64, JsonValue),
+    /// func, cookie, the request table of the http route
+    HttpRoute(String, u32, JsonValue),
+    /// cookie, status, body, headers, error of the http client request
+    HttpResponse(u32, u16, Vec<u8>, HashMap<String, String>, Option<String>),
 }
 
 /// the enterface to call lua, it store the lua state and exec list
@@ -140,10 +153,17 @@ impl LuaEngine {
                 LuaElem::NewConnection(cookie, new_fd, client_ip, server_port, websocket) => {
                     self.execute_new_connect(cookie, new_fd, client_ip, server_port, websocket)
                 }
-                LuaElem::LostConnection(unique, reason) => self.execute_lost_connect(unique, reason),
+                LuaElem::LostConnection(unique, reason, kind) => self.execute_lost_connect(unique, reason, kind),
+                LuaElem::ConnectFailed(cookie, reason, reconnecting) => self.execute_connect_failed(cookie, reason, reconnecting),
                 LuaElem::ExecString(func_str) => self.execute_string(func_str),
                 LuaElem::ArgsFunc(func, args) => self.execute_args_func(func, args),
                 LuaElem::HttpCallbackFunc(method, headers, args) => self.execute_http_func(method, headers, args),
+                LuaElem::SyncCall(func, arg, sender) => self.execute_sync_call(func, arg, sender),
+                LuaElem::WsHandshake(func, unique, token, request) => self.execute_ws_handshake(func, unique, token, request),
+                LuaElem::HttpRoute(func, cookie, request) => self.execute_http_route(func, cookie, request),
+                LuaElem::HttpResponse(cookie, status, body, headers, error) => {
+                    self.execute_http_response(cookie, status, body, headers, error)
+                }
 
 
                 
@@ -162,9 +182,48 @@ impl LuaEngine {
         self.exec_list.push(LuaElem::NewConnection(cookie, unique, client_ip, server_port, websocket));
     }
 
+    /// call the lua func with the json arg in the lua thread, the result is None if the func failed
+    pub fn apply_sync_call(&mut self, func: String, arg: JsonValue) -> mpsc::Receiver<Option<JsonValue>> {
+        let (sender, receiver) = mpsc::channel();
+        let _guard = self.mutex.lock().unwrap();
+        self.exec_list.push(LuaElem::SyncCall(func, arg, sender));
+        receiver
+    }
+
+    /// call the handshake hook of the pending websocket upgrade, the answer finish the upgrade
+    pub fn apply_ws_handshake(&mut self, func: String, unique: String, token: u64, request: JsonValue) {
+        let _guard = self.mutex.lock().unwrap();
+        self.exec_list.push(LuaElem::WsHandshake(func, unique, token, request));
+    }
+
+    /// call the route func with the request table, the returned table is the response
+    pub fn apply_http_route(&mut self, func: String, cookie: u32, request: JsonValue) {
+        let _guard = self.mutex.lock().unwrap();
+        self.exec_list.push(LuaElem::HttpRoute(func, cookie, request));
+    }
+
+    /// the response of the http client request, the status is 0 and the error is set if the transport failed
+    pub fn apply_http_response(&mut self, cookie: u32, status: u16, body: Vec<u8>, headers: HashMap<String, String>, error: Option<String>) {
+        let _guard = self.mutex.lock().unwrap();
+        self.exec_list.push(LuaElem::HttpResponse(cookie, status, body, headers, error));
+    }
+
     pub fn apply_lost_connect(&mut self, unique: &String, reason: String) {
+        self.apply_lost_connect_with_error(unique, reason, None);
+    }
+
+    /// the kind is the os error kind of the close, such as ConnectionReset, BrokenPipe,
+    /// lua recv the empty string if closed without error
+    pub fn apply_lost_connect_with_error(&mut self, unique: &String, reason: String, kind: Option<io::ErrorKind>) {
+        let _guard = self.mutex.lock().unwrap();
+        let kind = kind.map(|kind| format!("{:?}", kind)).unwrap_or_default();
+        self.exec_list.push(LuaElem::LostConnection(unique.to_string(), reason, kind));
+    }
+
+    /// the connect of the cookie failed, reconnecting is true if it will try again
+    pub fn apply_connect_failed(&mut self, cookie: u32, reason: String, reconnecting: bool) {
         let _guard = self.mutex.lock().unwrap();
-        self.exec_list.push(LuaElem::LostConnection(unique.to_string(), reason));
+        self.exec_list.push(LuaElem::ConnectFailed(cookie, reason, reconnecting));
     }
 
     pub fn apply_db_result(&mut self,
@@ -212,8 +271,12 @@ impl LuaEngine {
         self.lua.exec_func5("cmd_new_connection", cookie, unique, client_ip, server_port, websocket)
     }
 
-    pub fn execute_lost_connect(&mut self, unique: String, reason: String) -> i32 {
-        self.lua.exec_func2("cmd_lost_connection", unique, reason)
+    pub fn execute_lost_connect(&mut self, unique: String, reason: String, kind: String) -> i32 {
+        self.lua.exec_func3("cmd_lost_connection", unique, reason, kind)
+    }
+
+    pub fn execute_connect_failed(&mut self, cookie: u32, reason: String, reconnecting: bool) -> i32 {
+        self.lua.exec_func3("cmd_connect_failed", cookie, reason, reconnecting)
     }
 
     pub fn execute_db_result(&mut self,
@@ -262,6 +325,62 @@ impl LuaEngine {
                             net_msg)
     }
 
+    pub fn execute_sync_call(&mut self, func: String, arg: JsonValue, sender: mpsc::Sender<Option<JsonValue>>) -> i32 {
+        let (success, result) = self.call_json_func(func, arg);
+        let _ = sender.send(result);
+        success
+    }
+
+    pub fn execute_ws_handshake(&mut self, func: String, unique: String, token: u64, request: JsonValue) -> i32 {
+        let (success, result) = self.call_json_func(func, request);
+        let accept = match result {
+            Some(value) => WebsocketAccept::from_json(value),
+            None => WebsocketAccept::Reject(500, "Handshake Hook Failed".to_string()),
+        };
+        MioEventMgr::instance().on_websocket_hook_answer(&unique, token, accept);
+        success
+    }
+
+    /// the handler return nil if it respond later by the cookie, the error respond 500
+    pub fn execute_http_route(&mut self, func: String, cookie: u32, request: JsonValue) -> i32 {
+        let (success, result) = self.call_json_func(func, request);
+        if success != 0 {
+            let headers = vec![("Content-Type".to_string(), "text/plain; charset=UTF-8".to_string())];
+            HttpMgr::instance().http_server_response(cookie, 500, headers, b"Internal Server Error".to_vec());
+        } else if let Some(response) = result.filter(|r| r.is_object()) {
+            HttpMgr::instance().http_json_response(cookie, &response);
+        }
+        success
+    }
+
+    pub fn execute_http_response(&mut self,
+                                 cookie: u32,
+                                 status: u16,
+                                 body: Vec<u8>,
+                                 headers: HashMap<String, String>,
+                                 error: Option<String>)
+                                 -> i32 {
+        let error = LuaWrapperValue(error.map(tunm_proto::Value::Str).unwrap_or(tunm_proto::Value::Nil));
+        let body = LuaWrapperValue(tunm_proto::Value::Raw(body));
+        self.lua.exec_func5("http_client_msg_respone", cookie, status, body, headers, error)
+    }
+
+    /// call the lua func with the json arg, the result is None if the func failed
+    fn call_json_func(&mut self, func: String, arg: JsonValue) -> (i32, Option<JsonValue>) {
+        let func = unwrap_or!(CString::new(func).ok(), return (-1, None));
+        let error = CString::new("error_handle").unwrap();
+        let state = self.lua.state();
+        unsafe {
+            td_rlua::lua_getglobal(state, error.as_ptr());
+            td_rlua::lua_getglobal(state, func.as_ptr());
+            JsonUtils::push_json(state, arg);
+            let success = td_rlua::lua_pcall(state, 1, 1, -3);
+            let result = if success == 0 { JsonUtils::lua_to_json(state, -1, false) } else { None };
+            td_rlua::lua_settop(state, -3);
+            (success, result)
+        }
+    }
+
     pub fn execute_string(&mut self, func_str: String) -> i32 {
         self.lua.exec_func1("RUN_STRING", func_str)
     }
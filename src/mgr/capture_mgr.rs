@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::io::prelude::*;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use td_rthreadpool::ReentrantMutex;
+
+use {NetMsg, TimeUtils, LogUtils, log_utils};
+
+/// the message recv from client
+pub const CAPTURE_IN: u8 = 0;
+/// the message send to client
+pub const CAPTURE_OUT: u8 = 1;
+
+const CAPTURE_MAGIC: &[u8; 4] = b"TNCP";
+const CAPTURE_VERSION: u8 = 1;
+
+/// one NetMsg frame in the capture file
+#[derive(Debug, Clone)]
+pub struct CaptureRecord {
+    /// the time in ms when the frame recorded
+    pub time: u64,
+    /// CAPTURE_IN or CAPTURE_OUT
+    pub direction: u8,
+    pub unique: String,
+    /// the whole NetMsg data include the head
+    pub data: Vec<u8>,
+}
+
+/// record the NetMsg frames of the selected connections to a capture file,
+/// the file is: magic(4) version(1) then records of
+/// time(u64) direction(u8) unique_len(u16) unique data_len(u32) data, all little endian
+pub struct CaptureMgr {
+    file: Option<BufWriter<File>>,
+    path: String,
+    uniques: HashSet<String>,
+    ip_filters: Vec<String>,
+    record_num: u64,
+    /// the file is set, read without the lock by the network threads for every message
+    capturing: AtomicBool,
+    mutex: Arc<ReentrantMutex<u32>>,
+}
+
+impl Default for CaptureMgr {
+    fn default() -> CaptureMgr {
+        CaptureMgr::new()
+    }
+}
+
+static mut EL: *mut CaptureMgr = ptr::null_mut();
+impl CaptureMgr {
+    pub fn instance() -> &'static mut CaptureMgr {
+        unsafe {
+            if EL.is_null() {
+                EL = Box::into_raw(Box::new(CaptureMgr::new()));
+            }
+            &mut *EL
+        }
+    }
+
+    pub fn new() -> CaptureMgr {
+        CaptureMgr {
+            file: None,
+            path: String::new(),
+            uniques: HashSet::new(),
+            ip_filters: vec![],
+            record_num: 0,
+            capturing: AtomicBool::new(false),
+            mutex: Arc::new(ReentrantMutex::new(0)),
+        }
+    }
+
+    /// start capture to the file, if already capturing the old file will be closed
+    pub fn start_capture(&mut self, path: String) -> bool {
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+        self.stop_capture();
+        let mut file = BufWriter::new(unwrap_or!(File::create(&*path).ok(), return false));
+        if file.write_all(CAPTURE_MAGIC).is_err() || file.write_all(&[CAPTURE_VERSION]).is_err() {
+            return false;
+        }
+        LogUtils::instance().append(log_utils::LOG_INFO, &format!("start capture to file {}", path));
+        self.file = Some(file);
+        self.path = path;
+        self.record_num = 0;
+        self.capturing.store(true, Ordering::Release);
+        true
+    }
+
+    pub fn stop_capture(&mut self) {
+        let _guard = self.mutex.lock().unwrap();
+        self.capturing.store(false, Ordering::Release);
+        if let Some(mut file) = self.file.take() {
+            let _ = file.flush();
+            let info = format!("stop capture file {} record num {}", self.path, self.record_num);
+            LogUtils::instance().append(log_utils::LOG_INFO, &info);
+        }
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capturing.load(Ordering::Acquire)
+    }
+
+    pub fn add_unique(&mut self, unique: String) {
+        let _guard = self.mutex.lock().unwrap();
+        self.uniques.insert(unique);
+    }
+
+    pub fn remove_unique(&mut self, unique: &String) {
+        let _guard = self.mutex.lock().unwrap();
+        self.uniques.remove(unique);
+    }
+
+    /// the ip filter is the prefix of client_ip, such as "192.168.1." or "10.0.0.2:"
+    pub fn add_ip_filter(&mut self, ip: String) {
+        let _guard = self.mutex.lock().unwrap();
+        if !self.ip_filters.contains(&ip) {
+            self.ip_filters.push(ip);
+        }
+    }
+
+    pub fn clear_filters(&mut self) {
+        let _guard = self.mutex.lock().unwrap();
+        self.uniques.clear();
+        self.ip_filters.clear();
+    }
+
+    /// if no filters, all the connections will be captured
+    pub fn is_selected(&self, unique: &String, client_ip: &str) -> bool {
+        if self.uniques.is_empty() && self.ip_filters.is_empty() {
+            return true;
+        }
+        self.uniques.contains(unique) || self.ip_filters.iter().any(|ip| client_ip.starts_with(&**ip))
+    }
+
+    pub fn record(&mut self, direction: u8, unique: &String, client_ip: &str, data: &[u8]) {
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+        if self.file.is_none() || !self.is_selected(unique, client_ip) {
+            return;
+        }
+        let record = CaptureRecord {
+            time: TimeUtils::get_time_ms(),
+            direction,
+            unique: unique.clone(),
+            data: data.to_vec(),
+        };
+        let success = Self::write_record(self.file.as_mut().unwrap(), &record).is_ok();
+        if !success {
+            LogUtils::instance().append(log_utils::LOG_ERROR, &format!("write capture file {} failed", self.path));
+            self.stop_capture();
+            return;
+        }
+        self.record_num += 1;
+    }
+
+    pub fn record_msg(&mut self, direction: u8, unique: &String, client_ip: &str, net_msg: &mut NetMsg) {
+        let wpos = net_msg.get_wpos();
+        self.record(direction, unique, client_ip, &net_msg.get_buffer().get_data()[..wpos]);
+    }
+
+    fn write_record<W: Write>(writer: &mut W, record: &CaptureRecord) -> io::Result<()> {
+        writer.write_all(&record.time.to_le_bytes())?;
+        writer.write_all(&[record.direction])?;
+        writer.write_all(&(record.unique.len() as u16).to_le_bytes())?;
+        writer.write_all(record.unique.as_bytes())?;
+        writer.write_all(&(record.data.len() as u32).to_le_bytes())?;
+        writer.write_all(&record.data)?;
+        Ok(())
+    }
+
+    /// read all the records of the capture file, used by the replay tool
+    pub fn read_capture_file(path: &str) -> io::Result<Vec<CaptureRecord>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut head = [0u8; 5];
+        reader.read_exact(&mut head)?;
+        if &head[..4] != CAPTURE_MAGIC || head[4] != CAPTURE_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a capture file"));
+        }
+
+        let mut records = vec![];
+        loop {
+            let mut time = [0u8; 8];
+            match reader.read_exact(&mut time) {
+                Ok(()) => (),
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+            let mut direction = [0u8; 1];
+            reader.read_exact(&mut direction)?;
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            let mut unique = vec![0u8; u16::from_le_bytes(len) as usize];
+            reader.read_exact(&mut unique)?;
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            let mut data = vec![0u8; u32::from_le_bytes(len) as usize];
+            reader.read_exact(&mut data)?;
+            records.push(CaptureRecord {
+                time: u64::from_le_bytes(time),
+                direction: direction[0],
+                unique: String::from_utf8_lossy(&unique).to_string(),
+                data,
+            });
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use super::*;
+
+    #[test]
+    fn record_and_read_back() {
+        let path = env::temp_dir().join(format!("tunm_capture_{}.tncp", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let mut capture = CaptureMgr::new();
+        assert!(!capture.is_capturing());
+        assert!(capture.start_capture(path.clone()));
+        assert!(capture.is_capturing());
+        capture.add_ip_filter("10.0.0.".to_string());
+        capture.record(CAPTURE_IN, &"NM:1".to_string(), "10.0.0.2:5000", &[1, 2, 3]);
+        capture.record(CAPTURE_IN, &"NM:2".to_string(), "192.168.1.2:5000", &[9]);
+        capture.record(CAPTURE_OUT, &"NM:1".to_string(), "10.0.0.2:5000", &[]);
+        capture.stop_capture();
+        assert!(!capture.is_capturing());
+        capture.record(CAPTURE_IN, &"NM:1".to_string(), "10.0.0.2:5000", &[4]);
+
+        let records = CaptureMgr::read_capture_file(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].direction, &*records[0].unique, &records[0].data[..]), (CAPTURE_IN, "NM:1", &[1u8, 2, 3][..]));
+        assert_eq!((records[1].direction, &*records[1].unique, records[1].data.len()), (CAPTURE_OUT, "NM:1", 0));
+        assert!(records[0].time <= records[1].time);
+    }
+}
@@ -1,15 +1,19 @@
 use std::collections::HashMap;
+use std::error::Error;
 use std::thread;
+use std::time::Duration;
 
 use std::io::prelude::*;
 
 use std::sync::Arc;
 use td_rthreadpool::ReentrantMutex;
-use tiny_http::{Server, Response, Request};
-use {ThreadUtils, LuaEngine, TimeUtils};
+use tiny_http::{Server, Response, Request, Header};
+use serde::{Serialize, Deserialize};
+use serde_json::{self, Value as JsonValue};
+use {ThreadUtils, LuaEngine, TimeUtils, LogUtils, log_utils, JsonUtils, GlobalConfig, MioEventMgr};
+use super::http_router::{HttpRouter, HttpRequest, HttpRouteMatch};
 
 
-#[allow(dead_code)]
 struct ServerRequest {
     request: Request,
     time: u64,
@@ -24,13 +28,93 @@ impl ServerRequest {
     }
 }
 
+/// the http server settings in the config, such as `http: {timeout: 10000, max_pending: 1000}`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HttpConfig {
+    /// the ms lua must respond the request in, or it is replied 504, default 30000, 0 no limit
+    pub timeout: Option<u64>,
+    /// the requests waiting the lua response, the more is replied 503, default 10000
+    pub max_pending: Option<usize>,
+}
+
+impl HttpConfig {
+    pub fn get_timeout(&self) -> u64 {
+        self.timeout.unwrap_or(30_000)
+    }
+
+    pub fn get_max_pending(&self) -> usize {
+        self.max_pending.unwrap_or(10_000)
+    }
+}
+
+/// the stats of the http server requests
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct HttpStats {
+    /// the requests waiting the lua response
+    pub pending: usize,
+    /// the max pending since the start
+    pub peak_pending: usize,
+    /// the ms the oldest pending request waited
+    pub oldest_ms: u64,
+    pub total: u64,
+    /// replied 504 after the timeout
+    pub expired: u64,
+    /// replied 503 by the max pending
+    pub rejected: u64,
+}
+
+/// the options of the http client request, such as `{url = "https://pay.example.com/notify",
+/// method = "POST", headers = {Authorization = "Bearer token"}, json = {order = 1}, timeout = 5000, retry = 2}`
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct HttpClientOptions {
+    /// the http or https url
+    pub url: String,
+    /// default GET
+    pub method: Option<String>,
+    /// the array value is the repeated header
+    pub headers: Option<JsonValue>,
+    /// appended to the query of the url
+    pub query: Option<HashMap<String, JsonValue>>,
+    /// the text body
+    pub body: Option<String>,
+    /// the body encoded to json with the `application/json`
+    pub json: Option<JsonValue>,
+    /// the body encoded with the `application/x-www-form-urlencoded`
+    pub form: Option<HashMap<String, JsonValue>>,
+    /// the ms limit the whole request, default 30000
+    pub timeout: Option<u64>,
+    /// the times try again after the transport failed, the response of any status is not retried, default 0
+    pub retry: Option<u32>,
+    /// the ms wait before try again, default 1000
+    pub retry_delay: Option<u64>,
+}
+
+/// the status, the lowercase headers with the repeated values joined by `, ` and the body
+type HttpClientResponse = (u16, HashMap<String, String>, Vec<u8>);
+
 pub struct HttpMgr {
     requests: HashMap<u32, ServerRequest>,
+    router: HttpRouter,
+    stats: HttpStats,
+    /// the client by the timeout ms, shared by the requests to reuse the connections
+    clients: HashMap<u64, reqwest::blocking::Client>,
+    /// the HTTP_EXPIRE timer added by the first listen
+    expire_timer: bool,
     mutex: Arc<ReentrantMutex<u32>>,
 }
 
 static HTTP_POOL_NAME: &'static str = "http";
+/// the outbound requests wait the remote server, so they not block the response of the server
+static HTTP_CLIENT_POOL_NAME: &str = "http_client";
 static mut EL: *mut HttpMgr = 0 as *mut _;
+
+/// the name is the token and the value has no line break, so the header can't split the response
+fn is_valid_header(name: &str, value: &str) -> bool {
+    !name.is_empty()
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+        && !value.bytes().any(|b| b == b'\r' || b == b'\n')
+}
+
 impl HttpMgr {
     pub fn instance() -> &'static mut HttpMgr {
         unsafe {
@@ -43,52 +127,312 @@ impl HttpMgr {
 
     pub fn new() -> HttpMgr {
         ThreadUtils::instance().create_pool(HTTP_POOL_NAME.to_string(), 10);
+        ThreadUtils::instance().create_pool(HTTP_CLIENT_POOL_NAME.to_string(), 10);
         HttpMgr {
             requests: HashMap::new(),
+            router: HttpRouter::new(),
+            stats: HttpStats::default(),
+            clients: HashMap::new(),
+            expire_timer: false,
             mutex: Arc::new(ReentrantMutex::new(0)),
         }
     }
 
     pub fn new_request_receive(&mut self, mut request: Request) {
-        let mut data = self.mutex.lock().unwrap();
+        let mutex = self.mutex.clone();
+        let mut data = mutex.lock().unwrap();
         if *data > u32::max_value() - 1000 {
             *data = 0;
         }
         *data += 1;
+        let cookie = *data;
 
-        let mut body = String::new();
+        let mut body = vec![];
         let mut headers = HashMap::new();
         for it in request.headers() {
             headers.insert(it.field.as_str().to_string(), it.value.as_str().to_string());
         }
 
-        let _ = request.as_reader().read_to_string(&mut body);
-        LuaEngine::instance().apply_http_callback_func(request.method().as_str().to_string(), headers, vec![
-                        data.to_string(),
-                        request.url().to_string(),
-                        body,
-                        format!("{}", request.remote_addr())
-                    ]);
-        
-        // ("http_server_msg_recv".to_string(),
-        //                                       vec![data.to_string(),
-        //                                            request.url().to_string(),
-        //                                            headers,
-        //                                            body,
-        //                                            format!("{}", request.remote_addr())]);
-        self.requests.insert(*data, ServerRequest::new(request));
+        let _ = request.as_reader().read_to_end(&mut body);
+        let body = String::from_utf8_lossy(&body).to_string();
+        let method = request.method().as_str().to_string();
+        let url = request.url().to_string();
+        let addr = format!("{}", request.remote_addr());
+        let config = GlobalConfig::instance().http.clone().unwrap_or_default();
+        let rejected = self.requests.len() >= config.get_max_pending();
+        self.requests.insert(cookie, ServerRequest::new(request));
+        self.stats.total += 1;
+        if rejected {
+            self.stats.rejected += 1;
+            let headers = vec![("Content-Type".to_string(), "text/plain; charset=UTF-8".to_string())];
+            self.http_server_response(cookie, 503, headers, b"Service Unavailable".to_vec());
+            return;
+        }
+        self.stats.peak_pending = self.stats.peak_pending.max(self.requests.len());
+
+        if self.router.is_empty() {
+            LuaEngine::instance().apply_http_callback_func(method, headers, vec![
+                            cookie.to_string(),
+                            url,
+                            body,
+                            addr
+                        ]);
+            // ("http_server_msg_recv".to_string(),
+            //                                       vec![data.to_string(),
+            //                                            request.url().to_string(),
+            //                                            headers,
+            //                                            body,
+            //                                            format!("{}", request.remote_addr())]);
+            return;
+        }
+
+        let headers = headers.into_iter().map(|(k, v)| (k.to_lowercase(), v)).collect();
+        let mut http_request = HttpRequest::new(cookie, &method, &url, headers, body, addr);
+        match self.router.find(&method, &http_request.path) {
+            HttpRouteMatch::Found(func, params) => {
+                http_request.params = params;
+                let arg = serde_json::to_value(http_request).unwrap_or(JsonValue::Null);
+                LuaEngine::instance().apply_http_route(func, cookie, arg);
+            }
+            HttpRouteMatch::MethodNotAllowed(allowed) => {
+                let headers = vec![
+                    ("Allow".to_string(), allowed.join(", ")),
+                    ("Content-Type".to_string(), "text/plain; charset=UTF-8".to_string()),
+                ];
+                self.http_server_response(cookie, 405, headers, b"Method Not Allowed".to_vec());
+            }
+            HttpRouteMatch::NotFound => {
+                let headers = vec![("Content-Type".to_string(), "text/plain; charset=UTF-8".to_string())];
+                self.http_server_response(cookie, 404, headers, b"Not Found".to_vec());
+            }
+        }
+    }
+
+    /// reply 504 to the requests not responded in the timeout, called by the HTTP_EXPIRE timer
+    pub fn expire_requests(&mut self) {
+        let mutex = self.mutex.clone();
+        let _data = mutex.lock().unwrap();
+        let timeout = GlobalConfig::instance().http.clone().unwrap_or_default().get_timeout();
+        if timeout == 0 {
+            return;
+        }
+        let now = TimeUtils::get_time_ms();
+        let mut cookies: Vec<u32> = self.requests.iter()
+            .filter(|(_, request)| now >= request.time + timeout)
+            .map(|(cookie, _)| *cookie)
+            .collect();
+        cookies.sort();
+        for cookie in cookies {
+            let info = format!("http request {} not responded in {} ms", cookie, timeout);
+            LogUtils::instance().append(log_utils::LOG_ERROR, &info);
+            self.stats.expired += 1;
+            let headers = vec![("Content-Type".to_string(), "text/plain; charset=UTF-8".to_string())];
+            self.http_server_response(cookie, 504, headers, b"Gateway Timeout".to_vec());
+        }
+    }
 
+    pub fn get_stats(&self) -> HttpStats {
+        let _data = self.mutex.lock().unwrap();
+        let now = TimeUtils::get_time_ms();
+        let oldest = self.requests.values().map(|r| r.time).min().unwrap_or(now);
+        HttpStats {
+            pending: self.requests.len(),
+            oldest_ms: now.saturating_sub(oldest),
+            ..self.stats.clone()
+        }
+    }
+
+    /// the lua func(request) handle the request of the method and the path pattern, such as
+    /// `/api/user/:rid`, the func None remove the route, all the requests call the
+    /// `http_server_msg_recv` if no route
+    pub fn set_route(&mut self, method: &str, pattern: &str, func: Option<String>) {
+        let _data = self.mutex.lock().unwrap();
+        self.router.set_route(method, pattern, func);
+    }
+
+    pub fn clear_routes(&mut self) {
+        let _data = self.mutex.lock().unwrap();
+        self.router.clear();
+    }
+
+    /// the lua headers table to the header list, the array value is the repeated header
+    pub fn json_to_headers(value: &JsonValue) -> Vec<(String, String)> {
+        let to_header = |value: &JsonValue| match value {
+            JsonValue::String(value) => value.clone(),
+            value => JsonUtils::to_string(value),
+        };
+        let mut headers = vec![];
+        if let JsonValue::Object(ref map) = *value {
+            for (name, value) in map {
+                match value {
+                    JsonValue::Array(values) => headers.extend(values.iter().map(|v| (name.clone(), to_header(v)))),
+                    value => headers.push((name.clone(), to_header(value))),
+                }
+            }
+        }
+        headers
+    }
+
+    /// the content type added if not in the headers
+    pub fn set_default_content_type(headers: &mut Vec<(String, String)>, content_type: &str) {
+        if !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("Content-Type")) {
+            headers.push(("Content-Type".to_string(), content_type.to_string()));
+        }
+    }
+
+    /// respond the table `{status = 200, headers = {}, body = ""}` returned by the route handler,
+    /// the body table is encoded to json
+    pub fn http_json_response(&mut self, cookie: u32, response: &JsonValue) -> bool {
+        let status = response["status"].as_u64().unwrap_or(200).min(u16::MAX as u64) as u16;
+        let mut headers = Self::json_to_headers(&response["headers"]);
+        let (body, content_type) = match response["body"] {
+            JsonValue::Null => (vec![], "text/plain; charset=UTF-8"),
+            JsonValue::String(ref body) => (body.clone().into_bytes(), "text/plain; charset=UTF-8"),
+            ref body => (JsonUtils::to_string(body).into_bytes(), "application/json"),
+        };
+        Self::set_default_content_type(&mut headers, content_type);
+        self.http_server_response(cookie, status, headers, body)
     }
 
     pub fn http_server_respone(&mut self, cookie: u32, content: String) {
+        let headers = vec![("Content-Type".to_string(), "text/plain; charset=UTF-8".to_string())];
+        self.http_server_response(cookie, 200, headers, content.into_bytes());
+    }
+
+    /// respond the request with the status, the headers and the binary body, the headers keep
+    /// the repeated name such as `Set-Cookie`, the invalid header is ignored, the status not in
+    /// 100..600 respond 500, false if the request not found
+    pub fn http_server_response(&mut self, cookie: u32, status: u16, headers: Vec<(String, String)>, body: Vec<u8>) -> bool {
         let _data = self.mutex.lock().unwrap();
-        let request = unwrap_or!(self.requests.remove(&cookie), return);
+        let request = unwrap_or!(self.requests.remove(&cookie), return false);
         let pool = ThreadUtils::instance().get_pool(&HTTP_POOL_NAME.to_string());
         pool.execute(move || {
-            let _ = request.request.respond(Response::from_string(&*content));
+            let status = if (100..600).contains(&status) { status } else { 500 };
+            let mut response = Response::from_data(body).with_status_code(status);
+            for (name, value) in headers {
+                let header = if is_valid_header(&name, &value) {
+                    Header::from_bytes(name.as_bytes(), value.as_bytes()).ok()
+                } else {
+                    None
+                };
+                match header {
+                    Some(header) => response.add_header(header),
+                    None => {
+                        let info = format!("http response invalid header {:?}: {:?}", name, value);
+                        LogUtils::instance().append(log_utils::LOG_ERROR, &info);
+                    }
+                }
+            }
+            let _ = request.request.respond(response);
+        });
+        true
+    }
+
+    /// send the request in the http client pool, the lua `http_client_msg_respone(cookie, status, body, headers, err)`
+    /// recv the response, the status is 0 and the err is set if the transport failed after the retries
+    pub fn http_request(&mut self, cookie: u32, options: HttpClientOptions) {
+        let pool = ThreadUtils::instance().get_pool(&HTTP_CLIENT_POOL_NAME.to_string());
+        pool.execute(move || {
+            let client = match HttpMgr::instance().get_client(options.timeout.unwrap_or(30000)) {
+                Ok(client) => client,
+                Err(error) => {
+                    let info = format!("http request {} failed {}", options.url, error);
+                    LogUtils::instance().append(log_utils::LOG_ERROR, &info);
+                    LuaEngine::instance().apply_http_response(cookie, 0, vec![], HashMap::new(), Some(error));
+                    return;
+                }
+            };
+            let mut times = 0;
+            loop {
+                let error = match Self::send_request(&client, &options) {
+                    Ok((status, headers, body)) => {
+                        LuaEngine::instance().apply_http_response(cookie, status, body, headers, None);
+                        return;
+                    }
+                    Err(error) => error,
+                };
+                if times >= options.retry.unwrap_or(0) {
+                    let info = format!("http request {} failed {}", options.url, error);
+                    LogUtils::instance().append(log_utils::LOG_ERROR, &info);
+                    LuaEngine::instance().apply_http_response(cookie, 0, vec![], HashMap::new(), Some(error));
+                    return;
+                }
+                times += 1;
+                thread::sleep(Duration::from_millis(options.retry_delay.unwrap_or(1000)));
+            }
         });
     }
 
+    /// the client of the timeout is built once, the clone share the connection pool
+    fn get_client(&mut self, timeout: u64) -> Result<reqwest::blocking::Client, String> {
+        let _data = self.mutex.lock().unwrap();
+        if let Some(client) = self.clients.get(&timeout) {
+            return Ok(client.clone());
+        }
+        let client = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_millis(timeout))
+            .build()
+            .map_err(|err| Self::error_chain(&err))?;
+        self.clients.insert(timeout, client.clone());
+        Ok(client)
+    }
+
+    /// Err with the reason if the transport failed
+    fn send_request(client: &reqwest::blocking::Client, options: &HttpClientOptions) -> Result<HttpClientResponse, String> {
+        let to_value = |value: &JsonValue| match value {
+            JsonValue::String(value) => value.clone(),
+            value => JsonUtils::to_string(value),
+        };
+        let mut url = reqwest::Url::parse(&options.url).map_err(|err| format!("invalid url {}", err))?;
+        if let Some(ref query) = options.query {
+            let mut pairs = url.query_pairs_mut();
+            for (key, value) in query {
+                pairs.append_pair(key, &to_value(value));
+            }
+        }
+        let method = options.method.as_ref().map(|m| m.to_uppercase()).unwrap_or_else(|| "GET".to_string());
+        let method = reqwest::Method::from_bytes(method.as_bytes()).map_err(|err| format!("invalid method {}", err))?;
+        let mut builder = client.request(method, url);
+        for (name, value) in Self::json_to_headers(options.headers.as_ref().unwrap_or(&JsonValue::Null)) {
+            builder = builder.header(&name[..], &value[..]);
+        }
+        if let Some(ref json) = options.json {
+            builder = builder.json(json);
+        } else if let Some(ref form) = options.form {
+            let form: HashMap<&String, String> = form.iter().map(|(k, v)| (k, to_value(v))).collect();
+            builder = builder.form(&form);
+        } else if let Some(ref body) = options.body {
+            builder = builder.body(body.clone());
+        }
+        let mut response = builder.send().map_err(|err| Self::error_chain(&err))?;
+        let mut headers: HashMap<String, String> = HashMap::new();
+        for (name, value) in response.headers() {
+            let value = String::from_utf8_lossy(value.as_bytes()).to_string();
+            headers.entry(name.as_str().to_string())
+                .and_modify(|v| { v.push_str(", "); v.push_str(&value); })
+                .or_insert(value);
+        }
+        let mut body = vec![];
+        response.read_to_end(&mut body).map_err(|err| format!("read body failed {}", err))?;
+        Ok((response.status().as_u16(), headers, body))
+    }
+
+    /// the reqwest error display not show the cause, such as the connection refused,
+    /// the cause already shown by the hyper error is skipped
+    fn error_chain(err: &dyn Error) -> String {
+        let mut info = err.to_string();
+        let mut source = err.source();
+        while let Some(err) = source {
+            let cause = err.to_string();
+            if !info.contains(&cause) {
+                info.push_str(": ");
+                info.push_str(&cause);
+            }
+            source = err.source();
+        }
+        info
+    }
+
     pub fn http_get_request(&mut self, cookie: u32, addr: String, url: String) {
         let pool = ThreadUtils::instance().get_pool(&HTTP_POOL_NAME.to_string());
         pool.execute(move || {
@@ -273,6 +617,13 @@ impl HttpMgr {
     // }
 
     pub fn start_listen(&mut self, url: String) -> bool {
+        {
+            let _data = self.mutex.lock().unwrap();
+            if !self.expire_timer {
+                self.expire_timer = true;
+                MioEventMgr::instance().add_http_expire_timer();
+            }
+        }
         thread::spawn(move || {
             let server = Server::http(&*url).unwrap();
 
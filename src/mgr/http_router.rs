@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use percent_encoding::percent_decode_str;
+use url;
+
+/// the request table passed to the lua route handler
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct HttpRequest {
+    /// reply by `http_server_response(cookie, ...)` if the handler not return the response
+    pub cookie: u32,
+    pub method: String,
+    /// the path without the query, such as `/api/user/1001`
+    pub path: String,
+    /// the params matched in the pattern, such as `{rid = "1001"}` of `/api/user/:rid`
+    pub params: HashMap<String, String>,
+    pub query: HashMap<String, String>,
+    /// the body of the `application/x-www-form-urlencoded`
+    pub form: HashMap<String, String>,
+    /// the lowercase header name to the value
+    pub headers: HashMap<String, String>,
+    pub body: String,
+    /// the body of the `application/json` decoded, nil if not the json
+    pub json: Option<JsonValue>,
+    pub addr: String,
+}
+
+/// the result of the route find
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpRouteMatch {
+    /// the lua func and the path params
+    Found(String, HashMap<String, String>),
+    /// the path matched but the method not, the allowed methods
+    MethodNotAllowed(Vec<String>),
+    NotFound,
+}
+
+struct HttpRoute {
+    method: String,
+    pattern: String,
+    segments: Vec<String>,
+    func: String,
+}
+
+/// the lua handlers by the method and the path pattern, the `:name` segment is the param,
+/// the last `*` segment match the rest path in the param `*`
+#[derive(Default)]
+pub struct HttpRouter {
+    routes: Vec<HttpRoute>,
+}
+
+impl HttpRequest {
+    pub fn new(cookie: u32, method: &str, url: &str, headers: HashMap<String, String>, body: String, addr: String) -> HttpRequest {
+        let (path, query) = match url.find('?') {
+            Some(index) => (&url[..index], &url[index + 1..]),
+            None => (url, ""),
+        };
+        let content_type = headers.get("content-type").map(|t| t.to_lowercase()).unwrap_or_default();
+        let form = if content_type.starts_with("application/x-www-form-urlencoded") {
+            url::form_urlencoded::parse(body.as_bytes()).into_owned().collect()
+        } else {
+            HashMap::new()
+        };
+        let json = if content_type.starts_with("application/json") {
+            serde_json::from_str(&body).ok()
+        } else {
+            None
+        };
+        HttpRequest {
+            cookie,
+            method: method.to_string(),
+            path: path.to_string(),
+            params: HashMap::new(),
+            query: url::form_urlencoded::parse(query.as_bytes()).into_owned().collect(),
+            form,
+            headers,
+            body,
+            json,
+            addr,
+        }
+    }
+}
+
+impl HttpRouter {
+    pub fn new() -> HttpRouter {
+        HttpRouter { routes: vec![] }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// the method `*` match all the methods, the same method and pattern replace the old func,
+    /// the func None remove the route
+    pub fn set_route(&mut self, method: &str, pattern: &str, func: Option<String>) {
+        let method = method.to_uppercase();
+        let index = self.routes.iter().position(|r| r.method == method && r.pattern == pattern);
+        match (index, func) {
+            (Some(index), Some(func)) => self.routes[index].func = func,
+            (Some(index), None) => {
+                self.routes.remove(index);
+            }
+            (None, Some(func)) => self.routes.push(HttpRoute {
+                method,
+                pattern: pattern.to_string(),
+                segments: Self::split_path(pattern).iter().map(|s| s.to_string()).collect(),
+                func,
+            }),
+            (None, None) => (),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.routes.clear();
+    }
+
+    /// the route with more static segments is preferred, such as `/user/list` before `/user/:rid`,
+    /// the first added is preferred if the same
+    pub fn find(&self, method: &str, path: &str) -> HttpRouteMatch {
+        let path = Self::split_path(path);
+        let method = method.to_uppercase();
+        let mut found: Option<(usize, &HttpRoute, HashMap<String, String>)> = None;
+        let mut allowed: Vec<String> = vec![];
+        for route in &self.routes {
+            let params = unwrap_or!(Self::match_path(&route.segments, &path), continue);
+            if route.method != "*" && route.method != method {
+                if !allowed.contains(&route.method) {
+                    allowed.push(route.method.clone());
+                }
+                continue;
+            }
+            let rank = route.segments.iter().filter(|s| !s.starts_with(':') && *s != "*").count();
+            if found.as_ref().map(|f| rank > f.0).unwrap_or(true) {
+                found = Some((rank, route, params));
+            }
+        }
+        if let Some((_, route, params)) = found {
+            return HttpRouteMatch::Found(route.func.clone(), params);
+        }
+        if allowed.is_empty() {
+            HttpRouteMatch::NotFound
+        } else {
+            HttpRouteMatch::MethodNotAllowed(allowed)
+        }
+    }
+
+    /// the empty segments are ignored, so `/user/` is same as `/user`
+    fn split_path(path: &str) -> Vec<&str> {
+        path.split('/').filter(|s| !s.is_empty()).collect()
+    }
+
+    fn match_path(segments: &[String], path: &[&str]) -> Option<HashMap<String, String>> {
+        let mut params = HashMap::new();
+        for (i, segment) in segments.iter().enumerate() {
+            if segment == "*" && i + 1 == segments.len() {
+                let rest: Vec<String> = path.get(i..).unwrap_or(&[]).iter().map(|s| Self::decode(s)).collect();
+                params.insert("*".to_string(), rest.join("/"));
+                return Some(params);
+            }
+            let value = path.get(i)?;
+            if let Some(name) = segment.strip_prefix(':') {
+                params.insert(name.to_string(), Self::decode(value));
+            } else if segment != value {
+                return None;
+            }
+        }
+        if segments.len() == path.len() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+
+    fn decode(segment: &str) -> String {
+        percent_decode_str(segment).decode_utf8_lossy().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn found(func: &str, pairs: &[(&str, &str)]) -> HttpRouteMatch {
+        HttpRouteMatch::Found(func.to_string(), params(pairs))
+    }
+
+    #[test]
+    fn param_decoded() {
+        let mut router = HttpRouter::new();
+        router.set_route("get", "/api/user/:rid/item/:item", Some("user_item".to_string()));
+        assert_eq!(router.find("GET", "/api/user/1001/item/sword"), found("user_item", &[("rid", "1001"), ("item", "sword")]));
+        assert_eq!(router.find("GET", "/api/user/%E5%BC%A0%20san/item/a%2Fb/"),
+            found("user_item", &[("rid", "张 san"), ("item", "a/b")]));
+        assert_eq!(router.find("GET", "/api/user/1001/item"), HttpRouteMatch::NotFound);
+        assert_eq!(router.find("GET", "/api/user/1001/item/sword/more"), HttpRouteMatch::NotFound);
+    }
+
+    #[test]
+    fn rest_segment() {
+        let mut router = HttpRouter::new();
+        router.set_route("GET", "/static/*", Some("static".to_string()));
+        assert_eq!(router.find("GET", "/static/js/app%20v1.js"), found("static", &[("*", "js/app v1.js")]));
+        assert_eq!(router.find("GET", "/static"), found("static", &[("*", "")]));
+        assert_eq!(router.find("GET", "/other/js"), HttpRouteMatch::NotFound);
+    }
+
+    #[test]
+    fn more_static_segments_win() {
+        let mut router = HttpRouter::new();
+        router.set_route("GET", "/user/:rid", Some("user_get".to_string()));
+        router.set_route("GET", "/user/*", Some("user_rest".to_string()));
+        router.set_route("GET", "/user/list", Some("user_list".to_string()));
+        router.set_route("GET", "/:kind/list", Some("kind_list".to_string()));
+        assert_eq!(router.find("GET", "/user/list"), found("user_list", &[]));
+        assert_eq!(router.find("GET", "/user/1001"), found("user_get", &[("rid", "1001")]));
+        assert_eq!(router.find("GET", "/user/1001/bag"), found("user_rest", &[("*", "1001/bag")]));
+        assert_eq!(router.find("GET", "/item/list"), found("kind_list", &[("kind", "item")]));
+
+        // the first added is preferred if the same, the same pattern replace the func
+        router.set_route("GET", "/user/:name", Some("user_name".to_string()));
+        assert_eq!(router.find("GET", "/user/1001"), found("user_get", &[("rid", "1001")]));
+        router.set_route("GET", "/user/:rid", Some("user_get2".to_string()));
+        assert_eq!(router.find("GET", "/user/1001"), found("user_get2", &[("rid", "1001")]));
+        // the `*` added before `:name` is preferred too
+        router.set_route("GET", "/user/:rid", None);
+        assert_eq!(router.find("GET", "/user/1001"), found("user_rest", &[("*", "1001")]));
+    }
+
+    #[test]
+    fn any_method_and_not_allowed() {
+        let mut router = HttpRouter::new();
+        router.set_route("GET", "/api/user", Some("user_get".to_string()));
+        router.set_route("post", "/api/user", Some("user_post".to_string()));
+        router.set_route("*", "/ping", Some("ping".to_string()));
+        assert_eq!(router.find("post", "/api/user"), found("user_post", &[]));
+        assert_eq!(router.find("DELETE", "/ping"), found("ping", &[]));
+        assert_eq!(router.find("DELETE", "/api/user"),
+            HttpRouteMatch::MethodNotAllowed(vec!["GET".to_string(), "POST".to_string()]));
+        assert_eq!(router.find("DELETE", "/api/item"), HttpRouteMatch::NotFound);
+
+        router.set_route("*", "/api/:kind", Some("any_kind".to_string()));
+        assert_eq!(router.find("DELETE", "/api/user"), found("any_kind", &[("kind", "user")]));
+        router.clear();
+        assert!(router.is_empty());
+        assert_eq!(router.find("GET", "/ping"), HttpRouteMatch::NotFound);
+    }
+
+    #[test]
+    fn request_query_and_form() {
+        let form_type = params(&[("content-type", "Application/X-WWW-Form-Urlencoded; charset=utf-8")]);
+        let request = HttpRequest::new(7, "POST", "/api/login?from=web&name=%E5%BC%A0+san", form_type,
+            "account=a%26b&password=p+w".to_string(), "127.0.0.1:5000".to_string());
+        assert_eq!(request.path, "/api/login");
+        assert_eq!(request.query, params(&[("from", "web"), ("name", "张 san")]));
+        assert_eq!(request.form, params(&[("account", "a&b"), ("password", "p w")]));
+        assert_eq!(request.json, None);
+
+        let json_type = params(&[("content-type", "application/json")]);
+        let request = HttpRequest::new(8, "POST", "/api/login", json_type, "{\"account\": \"a\"}".to_string(), String::new());
+        assert!(request.query.is_empty() && request.form.is_empty());
+        assert_eq!(request.json, Some(serde_json::json!({"account": "a"})));
+
+        // the form body without the content type is only the body
+        let request = HttpRequest::new(9, "POST", "/api/login?", HashMap::new(), "account=a".to_string(), String::new());
+        assert!(request.query.is_empty() && request.form.is_empty() && request.json.is_none());
+        assert_eq!(request.body, "account=a");
+    }
+}
@@ -6,16 +6,22 @@ use std::io::Result;
 
 use tunm_timer::{Factory, RetTimer, Timer, Handler};
 
-use crate::{LogUtils};
-use SocketEvent;
+use crate::{LogUtils, TimeUtils, log_utils};
+use {SocketEvent, SocketStats, LoopbackStream, LoopbackPeer, GlobalConfig};
+use {HandshakeRequest, HandshakeInfo, HandshakeStep, MSG_TYPE_HANDSHAKE};
+use {WsFrame, WsUpgrade, WsSession, ws_inflate, WebsocketConfig, WebsocketRequest, WebsocketAccept, ProtoJson, MSG_TYPE_JSON};
+use crate::net::{WS_MAX_HANDSHAKE, WS_OP_CONTINUE, WS_OP_TEXT, WS_OP_BINARY, WS_OP_CLOSE, WS_OP_PING, WS_OP_PONG};
 use LuaEngine;
 use NetMsg;
-use WebSocketMgr;
+use {WebSocketMgr, CaptureMgr, ProtocolMgr, CAPTURE_IN, CAPTURE_OUT};
 use DbPool;
+use HttpMgr;
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 use td_rthreadpool::ReentrantMutex;
 use tunm_proto::{self, Buffer, decode_number};
+use serde_json::{self, Value as JsonValue};
 
 use crate::net::{AsSocket, AcceptCb, ReadCb, EndCb};
 
@@ -23,42 +29,51 @@ use mio::net::{TcpListener};
 use mio::{Events, Interest, Poll, Token};
 
 static mut EL: *mut MioEventMgr = 0 as *mut _;
+/// the ms between the sweeps of the http requests, the 504 is late at most it
+const HTTP_EXPIRE_STEP: u64 = 100;
 static mut READ_DATA: [u8; 65536] = [0; 65536];
 pub struct MioEventMgr {
     connect_ids: HashMap<String, SocketEvent>,
     
     mutex: Arc<ReentrantMutex<i32>>,
     timer: Timer<TimeHandle>,
+    /// the timer added by the other threads, TIMER_QUEUE move them to the timer in the timer thread
+    timer_queue: Mutex<Vec<Handler<TimeHandle>>>,
     poll: Poll,
     exit: bool,
 }
 
+/// the timer id is unknown until the queued timer added in the timer thread,
+/// so the timer of the connection is matched by the token
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(1);
 
 pub struct TimeHandle {
     timer_name: String,
     unique: String,
+    token: u64,
 }
 
 impl TimeHandle {
     pub fn new(timer_name: String) -> TimeHandle {
-        TimeHandle {
-            timer_name,
-            unique: String::new(),
-        }
+        TimeHandle::new_unique(timer_name, String::new())
     }
     
-    #[allow(unused)]
     pub fn new_unique(timer_name: String, unique: String) -> TimeHandle {
         TimeHandle {
             timer_name,
             unique,
+            token: NEXT_TOKEN.fetch_add(1, Ordering::Relaxed),
         }
     }
 
+    /// the unique token of the handle, never 0
+    pub fn get_token(&self) -> u64 {
+        self.token
+    }
 }
 
 impl Factory for TimeHandle {
-    fn on_trigger(&mut self, _timer: &mut Timer<Self>, id: u64) -> RetTimer {
+    fn on_trigger(&mut self, timer: &mut Timer<Self>, id: u64) -> RetTimer {
         match &*self.timer_name {
             "MIO" => {
                 let _ = MioEventMgr::instance().run_one_server();
@@ -72,8 +87,23 @@ impl Factory for TimeHandle {
             "CHECK_DB" => {
                 DbPool::instance().check_connect_timeout();
             }
-            "KICK_SOCKET" => {
-                LuaEngine::instance().apply_lost_connect(&self.unique, "定时关闭".to_string());
+            "TIMER_QUEUE" => {
+                MioEventMgr::instance().add_queued_timers(timer);
+            }
+            "SHUTDOWN_TIMEOUT" => {
+                MioEventMgr::instance().close_shutdown_timeout(&self.unique);
+            }
+            "WS_PING" => {
+                MioEventMgr::instance().on_websocket_ping(&self.unique, self.token);
+            }
+            "WS_HANDSHAKE" => {
+                MioEventMgr::instance().on_websocket_handshake_timeout(&self.unique, self.token);
+            }
+            "WS_HOOK" => {
+                MioEventMgr::instance().on_websocket_hook_timeout(&self.unique, self.token);
+            }
+            "HTTP_EXPIRE" => {
+                HttpMgr::instance().expire_requests();
             }
             _ => {
                 println!("unknow name {}", self.timer_name);
@@ -91,6 +121,7 @@ impl MioEventMgr {
             mutex: Arc::new(ReentrantMutex::new(0)),
             poll: Poll::new().ok().unwrap(),
             timer: Timer::new(100),
+            timer_queue: Mutex::new(vec![]),
             exit: false,
         }
     }
@@ -199,17 +230,37 @@ impl MioEventMgr {
             println!("error!!!!!!!! net_msg.get_pack_len() = {:?}, net_msg.len() = {:?}", net_msg.get_pack_len(), net_msg.len());
             return false;
         }
-        let (is_websocket, is_local) = {
+        // the websocket framed by mio write the frame to the socket, Some((text, deflate)) of the frame
+        let (is_websocket, is_local, ws_text) = {
             let mutex = self.mutex.clone();
             let _guard = mutex.lock().unwrap();
             if !self.connect_ids.contains_key(unique) {
                 return false;
             } else {
                 let socket_event = self.connect_ids.get_mut(unique).unwrap();
-                (socket_event.is_websocket(), socket_event.is_local())
+                let ws_text = socket_event.get_ws_session()
+                    .map(|session| (session.text_json && net_msg.get_msg_type() == MSG_TYPE_JSON, session.deflate));
+                socket_event.get_stats_mut().add_msg_out();
+                if socket_event.is_websocket() && ws_text.is_none() {
+                    socket_event.get_stats_mut().add_bytes_out(net_msg.len());
+                }
+                (socket_event.is_websocket() && ws_text.is_none(), socket_event.is_local(), ws_text)
             }
         };
-        
+        self.capture_message(CAPTURE_OUT, unique, net_msg);
+
+        if let Some((is_text, deflate)) = ws_text {
+            let text = if is_text { ProtoJson::to_text(net_msg).ok() } else { None };
+            let frame = match text {
+                Some(text) => WsFrame::new_data(WS_OP_TEXT, text.as_bytes(), deflate),
+                None => {
+                    net_msg.get_buffer().set_rpos(0);
+                    WsFrame::new_data(WS_OP_BINARY, &net_msg.get_buffer().get_write_data()[26..], deflate)
+                }
+            };
+            return self.write_to_socket(unique, &frame.encode()).ok().unwrap_or(false);
+        }
+
         if is_websocket {
             return WebSocketMgr::instance().send_message(unique, net_msg, is_local);
         } else {
@@ -220,14 +271,17 @@ impl MioEventMgr {
     }
 
     pub fn close_fd(&mut self, unique: &String, reason: String) -> bool {
-        let (is_websocket, unique) = {
+        // lua not know the websocket before the upgrade finished
+        let (is_websocket, is_known, unique) = {
             let mutex = self.mutex.clone();
             let _guard = mutex.lock().unwrap();
             if !self.connect_ids.contains_key(unique) {
                 return false;
             } else {
                 let socket_event = self.connect_ids.get_mut(unique).unwrap();
-                (socket_event.is_websocket(), socket_event.get_unique().clone())
+                let session = socket_event.get_ws_session();
+                (socket_event.is_websocket() && session.is_none(), session.map(|s| s.upgraded).unwrap_or(true),
+                 socket_event.get_unique().clone())
             }
         };
 
@@ -241,7 +295,7 @@ impl MioEventMgr {
 
         if is_websocket {
             return WebSocketMgr::instance().close_fd(&unique);
-        } else {
+        } else if is_known {
             LuaEngine::instance().apply_lost_connect(&unique, reason);
         }
         true
@@ -274,26 +328,190 @@ impl MioEventMgr {
             return;
         }
         let socket_event = socket_event.unwrap();
+        // the kicked connection wait the peer close, the message after the kick is not dispatched
+        if socket_event.is_shutting_down() {
+            socket_event.get_in_buffer().clear();
+            return;
+        }
         let buffer_len = socket_event.get_in_buffer().data_len();
-        let buffer = socket_event.get_in_buffer();
         loop {
-            let message: Option<Vec<u8>> = MioEventMgr::get_next_message(buffer);
+            let message: Option<Vec<u8>> = MioEventMgr::get_next_message(socket_event.get_in_buffer());
             if message.is_none() {
                 break;
             }
             let msg = NetMsg::new_by_data(&message.unwrap()[..]);
             if msg.is_err() {
+                socket_event.get_stats_mut().add_dispatch_error();
                 println!("message error kick fd {:?} msg = {:?}, buffer = {}", unique, msg.err(), buffer_len);
                 self.add_kick_event(unique, "Message Dispatch Error".to_string());
                 break;
             }
 
-            LuaEngine::instance().apply_message(unique, msg.ok().unwrap());
+            if !self.dispatch_net_msg(unique, msg.ok().unwrap()) {
+                break;
+            }
         }
     }
 
-    fn get_next_message(buffer: &mut Buffer) -> Option<Vec<u8>> {
-        if buffer.len() < NetMsg::min_len() as usize {
+    /// check the handshake and the schema, then pass the message to lua,
+    /// return false if the connection is closing and the left data should be dropped
+    pub fn dispatch_net_msg(&mut self, unique: &String, mut msg: NetMsg) -> bool {
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+
+        let socket_event = unwrap_or!(MioEventMgr::instance().get_socket_event(unique), return false);
+        if socket_event.is_shutting_down() {
+            return false;
+        }
+        if !socket_event.is_local() {
+            match self.check_handshake(unique, &mut msg) {
+                HandshakeStep::Dispatch => (),
+                HandshakeStep::Handled => return true,
+                HandshakeStep::Invalid(reason) => {
+                    socket_event.get_stats_mut().add_dispatch_error();
+                    return self.reject_invalid_message(unique, reason);
+                }
+                HandshakeStep::Reject(reason) => {
+                    self.shutdown_fd(unique, format!("Handshake Reject: {}", reason), 3000);
+                    return false;
+                }
+            }
+            if let Err(reason) = ProtocolMgr::instance().validate_message(&mut msg) {
+                socket_event.get_stats_mut().add_dispatch_error();
+                return self.reject_invalid_message(unique, reason);
+            }
+        }
+        socket_event.get_stats_mut().add_msg_in();
+        if CaptureMgr::instance().is_capturing() {
+            CaptureMgr::instance().record_msg(CAPTURE_IN, unique, &socket_event.get_client_ip(), &mut msg);
+        }
+        LuaEngine::instance().apply_message(unique, msg);
+        true
+    }
+
+    /// the first frame of the client may be the handshake, negotiate it by the config `handshake`
+    /// and reply, the message not in the negotiated msg_types is invalid
+    pub fn check_handshake(&mut self, unique: &String, net_msg: &mut NetMsg) -> HandshakeStep {
+        let config = unwrap_or!(GlobalConfig::instance().handshake.as_ref(), return HandshakeStep::Dispatch);
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+        let is_handshake = net_msg.get_msg_type() == MSG_TYPE_HANDSHAKE;
+        let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return HandshakeStep::Handled);
+        if socket_event.is_handshake_checked() {
+            return match socket_event.get_handshake() {
+                // the data after the reject is dropped
+                Some(info) if info.is_reject() => HandshakeStep::Handled,
+                _ if is_handshake => HandshakeStep::Invalid("handshake repeated".to_string()),
+                Some(info) if !info.msg_types.contains(&net_msg.get_msg_type()) => {
+                    HandshakeStep::Invalid(format!("msg_type {} not negotiated", net_msg.get_msg_type()))
+                }
+                _ => HandshakeStep::Dispatch,
+            };
+        }
+        socket_event.set_handshake_checked(true);
+        let info = if is_handshake {
+            match HandshakeRequest::parse(net_msg) {
+                Ok(request) => config.negotiate(&request, &ProtocolMgr::instance().get_msg_types()),
+                Err(reason) => HandshakeInfo::reject(config.version, reason),
+            }
+        } else if config.is_required() {
+            HandshakeInfo::reject(config.version, "handshake required".to_string())
+        } else {
+            return HandshakeStep::Dispatch;
+        };
+        socket_event.set_handshake(Some(info.clone()));
+        let log = format!("handshake fd {:?} result {:?}", unique, info);
+        LogUtils::instance().append(log_utils::LOG_INFO, &log);
+        if let Ok(mut net_msg) = info.to_net_msg() {
+            self.send_netmsg(unique, &mut net_msg);
+        }
+        match info.reason {
+            Some(reason) if info.is_reject() => HandshakeStep::Reject(reason),
+            _ => HandshakeStep::Handled,
+        }
+    }
+
+    /// log the invalid message, kick the connection if config, return false if kicked
+    pub fn reject_invalid_message(&mut self, unique: &String, reason: String) -> bool {
+        let info = format!("message invalid fd {:?} reason = {}", unique, reason);
+        LogUtils::instance().append(log_utils::LOG_ERROR, &info);
+        if ProtocolMgr::instance().is_kick_invalid() {
+            self.add_kick_event(unique, format!("Message Invalid: {}", reason));
+            return false;
+        }
+        true
+    }
+
+    /// record the message to the capture file if the connection is selected
+    pub fn capture_message(&mut self, direction: u8, unique: &String, net_msg: &mut NetMsg) {
+        if !CaptureMgr::instance().is_capturing() {
+            return;
+        }
+        let client_ip = {
+            let _guard = self.mutex.lock().unwrap();
+            unwrap_or!(self.connect_ids.get(unique), return).get_client_ip()
+        };
+        CaptureMgr::instance().record_msg(direction, unique, &client_ip, net_msg);
+    }
+
+    /// the websocket message not read by mio, so record the stats by the caller
+    pub fn record_socket_recv(&mut self, unique: &String, size: usize, success: bool) {
+        let _guard = self.mutex.lock().unwrap();
+        if let Some(socket_event) = self.connect_ids.get_mut(unique) {
+            let stats = socket_event.get_stats_mut();
+            stats.add_bytes_in(size);
+            if success {
+                stats.add_msg_in();
+            } else {
+                stats.add_dispatch_error();
+            }
+        }
+    }
+
+    pub fn record_socket_rtt(&mut self, unique: &String, rtt: u64) {
+        let _guard = self.mutex.lock().unwrap();
+        if let Some(socket_event) = self.connect_ids.get_mut(unique) {
+            socket_event.get_stats_mut().set_rtt(rtt);
+        }
+    }
+
+    pub fn get_socket_stats(&self, unique: &String) -> Option<SocketStats> {
+        let _guard = self.mutex.lock().unwrap();
+        self.connect_ids.get(unique).map(|ev| ev.get_stats().clone())
+    }
+
+    /// return the (unique, client_ip, stats) of the top num connections order by field desc
+    pub fn get_top_socket_stats(&self, field: &str, num: usize) -> Vec<(String, String, SocketStats)> {
+        let _guard = self.mutex.lock().unwrap();
+        let mut list: Vec<(String, String, SocketStats)> = self.connect_ids.values()
+            .filter(|ev| !ev.is_server())
+            .map(|ev| (ev.get_unique().clone(), ev.get_client_ip(), ev.get_stats().clone()))
+            .collect();
+        list.sort_by_key(|item| ::std::cmp::Reverse(item.2.get_field(field).unwrap_or(0)));
+        list.truncate(num);
+        list
+    }
+
+    pub fn dump_socket_stats(&self, field: &str, num: usize) -> String {
+        let now = TimeUtils::get_time_ms();
+        let mut info = format!("{:<12} {:<22} {:>12} {:>12} {:>10} {:>10} {:>8} {:>10} {:>10}\r\n",
+                               "unique", "client_ip", "bytes_in", "bytes_out", "msgs_in", "msgs_out",
+                               "errors", "online(s)", "idle(s)");
+        for (unique, client_ip, stats) in self.get_top_socket_stats(field, num) {
+            info.push_str(&format!("{:<12} {:<22} {:>12} {:>12} {:>10} {:>10} {:>8} {:>10} {:>10}\r\n",
+                                   unique, client_ip, stats.bytes_in, stats.bytes_out, stats.msgs_in,
+                                   stats.msgs_out, stats.dispatch_errors,
+                                   now.saturating_sub(stats.connect_time) / 1000,
+                                   now.saturating_sub(stats.last_active) / 1000));
+        }
+        info
+    }
+
+    /// split the next whole frame from the stream buffer, the buffer len is the capacity,
+    /// so the data_len must be used. the frame shorter than the head is drained as it is,
+    /// NetMsg::new_by_data will reject it and the socket will be kicked
+    pub fn get_next_message(buffer: &mut Buffer) -> Option<Vec<u8>> {
+        if buffer.data_len() < NetMsg::min_len() {
             return None;
         }
         let rpos = buffer.get_rpos();
@@ -301,10 +519,11 @@ impl MioEventMgr {
                               .into();
         buffer.set_rpos(rpos);
         length = unsafe { ::std::cmp::min(length, READ_DATA.len() as u32) };
-        if buffer.len() - rpos < length as usize {
+        let length = ::std::cmp::max(length as usize, NetMsg::min_len());
+        if buffer.data_len() < length {
             return None;
         }
-        Some(buffer.drain_collect(length as usize))
+        Some(buffer.drain_collect(rpos + length).split_off(rpos))
     }
 
     pub fn exist_socket_event(&self, unique: &String) -> bool {
@@ -327,21 +546,21 @@ impl MioEventMgr {
         let _sock_ev = unwrap_or!(self.connect_ids.remove(&unique), return);
     }
 
+    /// the stream is half closed, so the kick message sent before can be recv by the peer,
+    /// the websocket of the ws crate is closed by its sender
     pub fn add_kick_event(&mut self, unique: &String, reason: String) {
-        let _guard = self.mutex.lock().unwrap();
-        let info = format!("Close Fd {} by Reason {}", unique, reason);
-        println!("{}", info);
-        LogUtils::instance().append(2, &*info);
-
-        let sock_ev = unwrap_or!(self.connect_ids.remove(unique), return);
-        if !sock_ev.is_websocket() || !sock_ev.is_mio() {
-            let _ = self.timer.add_timer(Handler::new_step_ms(
-                TimeHandle::new_unique("KICK_SOCKET".to_string(), unique.clone()), 20, false, false
-            ));
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+        let is_stream = unwrap_or!(self.connect_ids.get(unique), return).is_stream();
+        if is_stream {
+            self.shutdown_fd(unique, reason, 3000);
             return;
         }
-
-        LuaEngine::instance().apply_lost_connect(&unique, "服务端关闭".to_string());
+        let info = format!("Close Fd {} by Reason {}", unique, reason);
+        LogUtils::instance().append(2, &*info);
+        self.connect_ids.remove(unique);
+        WebSocketMgr::instance().close_fd(unique);
+        LuaEngine::instance().apply_lost_connect(unique, reason);
     }
 
     // //由事件管理主动推送关闭的调用
@@ -377,26 +596,138 @@ impl MioEventMgr {
         let _ = self.add_timer_step("LUA_EXEC".to_string(), 1, true, false);
     }
 
+    /// append the bytes to the out buffer and write it, the data can't write now will be
+    /// written when the socket writable. if write failed the connection will be closed
     pub fn write_to_socket(&mut self, unique: &String, bytes: &[u8]) -> Result<bool> {
-        if let Some(socket_event) = self.connect_ids.get_mut(unique) {
-            if socket_event.is_client() {
-                // let size = socket_event.get_out_buffer().write(bytes)?;
-                // let token = socket_event.as_token();
-                // self.poll.registry().reregister(socket_event.as_client().unwrap(), token, Interest::READABLE.add(Interest::WRITABLE))?;
-                // Ok(size == bytes.len())
-                let _ = socket_event.get_out_buffer().write(bytes)?;
-                socket_event.write_data()
-            } else {
-                Ok(false)
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+        let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return Ok(false));
+        if !socket_event.is_stream() || socket_event.is_write_shutdown() {
+            return Ok(false);
+        }
+        // the websocket peer not read in time is closed without the close frame
+        let limit = socket_event.get_ws_session().map(|s| s.out_buffer).unwrap_or(0);
+        if limit > 0 && socket_event.get_out_buffer().data_len() + bytes.len() > limit {
+            let reason = format!("Websocket Out Buffer Overflow: {}", limit);
+            self.close_socket_event(unique, reason, None);
+            return Ok(false);
+        }
+        let _ = socket_event.get_out_buffer().write(bytes)?;
+        match socket_event.write_data() {
+            Ok(true) => Ok(true),
+            Ok(false) => {
+                if socket_event.is_client() {
+                    let token = socket_event.as_token();
+                    self.poll.registry().reregister(socket_event.as_client().unwrap(), token, Interest::READABLE.add(Interest::WRITABLE))?;
+                }
+                Ok(true)
             }
-        } else {
-            Ok(false)
+            Err(err) => {
+                let reason = format!("写入错误: {}", err);
+                self.close_socket_event(unique, reason, Some(err.kind()));
+                Err(err)
+            }
+        }
+    }
+
+    /// flush the out buffer when the socket writable, and shutdown the write if it is pending,
+    /// any error only close this connection
+    fn flush_socket_event(&mut self, unique: &String) {
+        let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return);
+        match socket_event.write_data() {
+            Ok(true) => {
+                if socket_event.is_client() {
+                    let token = socket_event.as_token();
+                    if let Err(err) = self.poll.registry().reregister(socket_event.as_client().unwrap(), token, Interest::READABLE) {
+                        let reason = format!("注册事件错误: {}", err);
+                        self.close_socket_event(unique, reason, Some(err.kind()));
+                        return;
+                    }
+                }
+                if socket_event.is_shutdown_pending() {
+                    if let Err(err) = socket_event.shutdown_write() {
+                        let reason = format!("半关闭错误: {}", err);
+                        self.close_socket_event(unique, reason, Some(err.kind()));
+                    }
+                }
+            }
+            Ok(false) => (),
+            Err(err) => {
+                let reason = format!("写入错误: {}", err);
+                self.close_socket_event(unique, reason, Some(err.kind()));
+            }
+        }
+    }
+
+    /// remove the connection and notify lua by the end callback with the reason and the os error kind
+    pub fn close_socket_event(&mut self, unique: &String, reason: String, kind: Option<io::ErrorKind>) {
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+        let mut socket_event = unwrap_or!(self.connect_ids.remove(unique), return);
+        let info = format!("Close Fd {} by Reason {} Kind {:?}", unique, reason, kind);
+        LogUtils::instance().append(2, &info);
+        // closed by the peer after shutdown, keep the shutdown reason
+        let is_shutdown = socket_event.is_shutting_down();
+        if !is_shutdown || kind.is_some() {
+            socket_event.set_close_info(reason, kind);
+        }
+        if socket_event.is_server() {
+            let _ = self.poll.registry().deregister(socket_event.as_server().unwrap());
+        } else if socket_event.is_client() {
+            let _ = self.poll.registry().deregister(socket_event.as_client().unwrap());
+        }
+        socket_event.call_end();
+    }
+
+    /// the unique may be used by the new connection, only close the shutdown one
+    pub fn close_shutdown_timeout(&mut self, unique: &String) {
+        let is_shutdown = {
+            let _guard = self.mutex.lock().unwrap();
+            let socket_event = unwrap_or!(self.connect_ids.get(unique), return);
+            socket_event.is_shutting_down()
+        };
+        if is_shutdown {
+            self.close_socket_event(unique, "半关闭超时".to_string(), Some(io::ErrorKind::TimedOut));
+        }
+    }
+
+    /// half close: shutdown the write after the out buffer flushed, so the kick message can
+    /// be recv by the peer, the connection closed when the peer close or timeout,
+    /// the timeout timer is queued, so it can be called in the poll thread such as the handshake reject
+    pub fn shutdown_fd(&mut self, unique: &String, reason: String, timeout: u64) -> bool {
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+        let (is_stream, is_shutting_down) = {
+            let socket_event = unwrap_or!(self.connect_ids.get(unique), return false);
+            (socket_event.is_stream(), socket_event.is_shutting_down())
+        };
+        if !is_stream {
+            return self.close_fd(unique, reason);
         }
+        // the first reason and timeout are kept
+        if is_shutting_down {
+            return true;
+        }
+        let info = format!("Shutdown Fd {} by Reason {}", unique, reason);
+        LogUtils::instance().append(2, &info);
+        // the upgraded websocket send the close frame before the half close
+        self.write_websocket_close(unique, 1000, &reason);
+        let flushed = {
+            let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return false);
+            socket_event.set_close_info(reason, None);
+            socket_event.set_shutdown_pending(true);
+            socket_event.get_out_buffer().data_len() == 0
+        };
+        if flushed {
+            self.flush_socket_event(unique);
+        }
+        self.queue_timer(TimeHandle::new_unique("SHUTDOWN_TIMEOUT".to_string(), unique.clone()), timeout);
+        true
     }
 
     
     pub fn write_by_socket_event(&mut self, ev: &mut SocketEvent, bytes: &[u8]) -> Result<bool> {
-        if ev.is_client() {
+        if ev.is_stream() {
             let _ = ev.get_out_buffer().write(bytes)?;
             let all = ev.write_data()?;
             Ok(all)
@@ -415,7 +746,7 @@ impl MioEventMgr {
 
     fn read_end_callback(
         socket: &mut SocketEvent) {
-        LuaEngine::instance().apply_lost_connect(&socket.get_unique(), "客户端关闭".to_string());
+        LuaEngine::instance().apply_lost_connect_with_error(&socket.get_unique(), socket.get_close_reason().clone(), socket.get_close_kind());
     }
 
     fn accept_callback(
@@ -424,6 +755,426 @@ impl MioEventMgr {
         return 0;
     }
 
+    /// lua not notified until the websocket upgrade finished
+    fn ws_accept_callback(
+        socket: &mut SocketEvent,
+    ) -> usize {
+        let config = WebsocketConfig::get(socket.get_server_port());
+        let mut session = WsSession::new(&config);
+        if config.get_handshake_timeout() > 0 {
+            let handle = TimeHandle::new_unique("WS_HANDSHAKE".to_string(), socket.get_unique().clone());
+            session.handshake_timer = handle.get_token();
+            MioEventMgr::instance().queue_timer(handle, config.get_handshake_timeout());
+        }
+        socket.set_websocket(true);
+        socket.set_ws_session(Some(session));
+        1
+    }
+
+    fn ws_read_callback(
+        socket: &mut SocketEvent,
+    ) -> usize {
+        MioEventMgr::instance().try_dispatch_websocket(socket.get_unique());
+        0
+    }
+
+    fn ws_end_callback(
+        socket: &mut SocketEvent) {
+        let upgraded = socket.get_ws_session().map(|s| s.upgraded).unwrap_or(false);
+        if upgraded {
+            Self::read_end_callback(socket);
+        }
+    }
+
+    /// finish the upgrade first, then split the frames from the in buffer and dispatch them
+    /// the data after the upgrade request is kept until the handshake hook answered
+    pub fn try_dispatch_websocket(&mut self, unique: &String) {
+        let (upgraded, pending) = {
+            let _guard = self.mutex.lock().unwrap();
+            let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return);
+            let shutting_down = socket_event.is_shutting_down();
+            let session = unwrap_or!(socket_event.get_ws_session(), return);
+            // no frame is handled after the close frame sent or received, the second close
+            // frame must not shutdown again
+            if session.closing || shutting_down {
+                socket_event.get_in_buffer().clear();
+                return;
+            }
+            (session.upgraded, session.pending_upgrade.is_some())
+        };
+        if pending {
+            self.check_websocket_in_buffer(unique);
+            return;
+        }
+        if !upgraded && !self.upgrade_websocket(unique) {
+            return;
+        }
+        loop {
+            let frame = {
+                let mutex = self.mutex.clone();
+                let _guard = mutex.lock().unwrap();
+                let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return);
+                let max_frame = unwrap_or!(socket_event.get_ws_session(), return).max_frame;
+                let result = WsFrame::parse(socket_event.get_in_buffer().get_write_data(), max_frame);
+                match result {
+                    Ok(Some((frame, size))) => {
+                        socket_event.get_in_buffer().read_offset(size);
+                        Some(frame)
+                    }
+                    Ok(None) => None,
+                    Err((code, reason)) => {
+                        socket_event.get_stats_mut().add_dispatch_error();
+                        socket_event.get_in_buffer().clear();
+                        self.close_websocket(unique, code, &format!("Websocket Protocol Error: {}", reason));
+                        return;
+                    }
+                }
+            };
+            let frame = unwrap_or!(frame, {
+                self.check_websocket_in_buffer(unique);
+                return;
+            });
+            if !self.on_websocket_frame(unique, frame) {
+                return;
+            }
+        }
+    }
+
+    /// the data not framed yet, such as the frames sent before the hook answered, is limited
+    /// by the in_buffer of the listener, return false if the connection closed by it
+    fn check_websocket_in_buffer(&mut self, unique: &String) -> bool {
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+        let (upgraded, limit) = {
+            let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return false);
+            let len = socket_event.get_in_buffer().data_len();
+            let session = unwrap_or!(socket_event.get_ws_session(), return false);
+            if len <= session.in_buffer {
+                return true;
+            }
+            let state = (session.upgraded, session.in_buffer);
+            socket_event.get_in_buffer().clear();
+            state
+        };
+        let reason = format!("Websocket In Buffer Overflow: {}", limit);
+        if upgraded {
+            self.close_websocket(unique, 1009, &reason);
+        } else {
+            self.reject_websocket(unique, 413, "Payload Too Large", reason);
+        }
+        false
+    }
+
+    /// parse the http upgrade, ask the handshake hook and reply, return true if upgraded
+    fn upgrade_websocket(&mut self, unique: &String) -> bool {
+        {
+            let mutex = self.mutex.clone();
+            let _guard = mutex.lock().unwrap();
+            let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return false);
+            let result = WsUpgrade::parse(socket_event.get_in_buffer().get_write_data());
+            let reason = match result {
+                Ok(Some((upgrade, size))) => {
+                    socket_event.get_in_buffer().read_offset(size);
+                    let port = socket_event.get_server_port();
+                    return self.finish_upgrade(unique, upgrade, port);
+                }
+                Ok(None) if socket_event.get_in_buffer().data_len() <= WS_MAX_HANDSHAKE => return false,
+                Ok(None) => "websocket upgrade too large".to_string(),
+                Err(reason) => reason,
+            };
+            socket_event.get_in_buffer().clear();
+            self.reject_websocket(unique, 400, "Bad Request", reason);
+            false
+        }
+    }
+
+    fn finish_upgrade(&mut self, unique: &String, upgrade: WsUpgrade, port: u16) -> bool {
+        let config = WebsocketConfig::get(port);
+        let connections = {
+            let _guard = self.mutex.lock().unwrap();
+            self.connect_ids.values()
+                .filter(|ev| ev.get_server_port() == port && ev.get_ws_session().map(|s| s.upgraded).unwrap_or(false))
+                .count()
+        };
+        if connections >= config.get_max_connections() {
+            let info = format!("websocket handshake {} reject by max connections {}", upgrade.resource, connections);
+            self.reject_websocket(unique, 503, "Service Unavailable", info);
+            return false;
+        }
+        let request = WebsocketRequest::new_by_parts(port, &upgrade.resource, &upgrade.headers);
+        // the lua hook is called in the lua thread, the upgrade is pending until the answer
+        // and the poll thread not wait it, reject with 503 if not answered in the timeout
+        if let Some((func, timeout)) = WebSocketMgr::instance().get_lua_handshake_hook() {
+            let handle = TimeHandle::new_unique("WS_HOOK".to_string(), unique.clone());
+            let token = handle.get_token();
+            {
+                let _guard = self.mutex.lock().unwrap();
+                let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return false);
+                let session = unwrap_or!(socket_event.get_ws_session_mut(), return false);
+                session.hook_timer = token;
+                session.pending_upgrade = Some(upgrade);
+            }
+            self.queue_timer(handle, timeout);
+            let request = serde_json::to_value(&request).unwrap_or(JsonValue::Null);
+            LuaEngine::instance().apply_ws_handshake(func, unique.clone(), token, request);
+            return false;
+        }
+        // the hook set by rust is called in the poll thread, it must not block
+        let accept = match WebSocketMgr::instance().get_handshake_hook() {
+            Some(hook) => hook(&request),
+            None => WebsocketAccept::Accept(HashMap::new()),
+        };
+        self.accept_upgrade(unique, upgrade, port, accept)
+    }
+
+    /// the answer of the lua handshake hook, the frames arrived when waiting are dispatched after
+    /// the upgrade, the answer of the closed connection or the timeout is ignored
+    pub fn on_websocket_hook_answer(&mut self, unique: &String, token: u64, accept: WebsocketAccept) {
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+        let (upgrade, port) = {
+            let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return);
+            let port = socket_event.get_server_port();
+            let session = unwrap_or!(socket_event.get_ws_session_mut(), return);
+            if session.hook_timer != token {
+                return;
+            }
+            (unwrap_or!(session.pending_upgrade.take(), return), port)
+        };
+        if self.accept_upgrade(unique, upgrade, port, accept) {
+            self.try_dispatch_websocket(unique);
+        }
+    }
+
+    pub fn on_websocket_hook_timeout(&mut self, unique: &String, token: u64) {
+        let accept = WebsocketAccept::Reject(503, "Handshake Hook Timeout".to_string());
+        self.on_websocket_hook_answer(unique, token, accept);
+    }
+
+    fn accept_upgrade(&mut self, unique: &String, upgrade: WsUpgrade, port: u16, accept: WebsocketAccept) -> bool {
+        let config = WebsocketConfig::get(port);
+        let meta = match accept {
+            WebsocketAccept::Accept(meta) => meta,
+            WebsocketAccept::Reject(status, reason) => {
+                let info = format!("websocket handshake {} reject {} {}", upgrade.resource, status, reason);
+                self.reject_websocket(unique, status, &reason, info);
+                return false;
+            }
+        };
+        let deflate = upgrade.deflate && config.is_deflate();
+        if !self.write_to_socket(unique, &upgrade.accept_response(deflate)).ok().unwrap_or(false) {
+            return false;
+        }
+
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+        let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return false);
+        socket_event.set_meta(meta);
+        let session = unwrap_or!(socket_event.get_ws_session_mut(), return false);
+        session.upgraded = true;
+        session.deflate = deflate;
+        LuaEngine::instance().apply_new_connect(socket_event.get_cookie(),
+                                                unique.clone(),
+                                                socket_event.get_client_ip(),
+                                                port,
+                                                true);
+        self.add_websocket_ping(unique);
+        true
+    }
+
+    /// reply the http error before the upgrade, lua not know the connection
+    fn reject_websocket(&mut self, unique: &String, status: u16, reason: &str, info: String) {
+        if let Some(session) = self.connect_ids.get_mut(unique).and_then(|ev| ev.get_ws_session_mut()) {
+            session.pending_upgrade = None;
+        }
+        trace!("{}", info);
+        LogUtils::instance().append(log_utils::LOG_INFO, &info);
+        let _ = self.write_to_socket(unique, &WsUpgrade::reject_response(status, reason));
+        self.shutdown_fd(unique, info, 3000);
+    }
+
+    /// return false if the connection is closing and the left frames should be dropped
+    fn on_websocket_frame(&mut self, unique: &String, frame: WsFrame) -> bool {
+        match frame.opcode {
+            WS_OP_PING => {
+                let _ = self.write_to_socket(unique, &WsFrame::new(WS_OP_PONG, frame.payload).encode());
+                return true;
+            }
+            WS_OP_PONG => {
+                let sent = String::from_utf8_lossy(&frame.payload).parse::<u64>().ok();
+                let _guard = self.mutex.lock().unwrap();
+                let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return false);
+                if let Some(sent) = sent {
+                    socket_event.get_stats_mut().set_rtt(TimeUtils::get_time_ms().saturating_sub(sent));
+                }
+                let session = unwrap_or!(socket_event.get_ws_session_mut(), return false);
+                session.ping_wait = false;
+                session.ping_miss = 0;
+                return true;
+            }
+            WS_OP_CLOSE => {
+                // reply the close frame if not sent, the 1005 is not allowed in the frame
+                let (code, reason) = frame.get_close_info();
+                self.write_websocket_close(unique, if code == 1005 { 1000 } else { code }, "");
+                self.shutdown_fd(unique, format!("WebSocket closing for ({}) {}", code, reason), 3000);
+                return false;
+            }
+            WS_OP_CONTINUE | WS_OP_TEXT | WS_OP_BINARY => (),
+            opcode => {
+                self.close_websocket(unique, 1002, &format!("Websocket Unknown Opcode {}", opcode));
+                return false;
+            }
+        }
+
+        // the fragments is assembled to the whole message, the size limited by max_frame
+        let message = {
+            let _guard = self.mutex.lock().unwrap();
+            let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return false);
+            let session = unwrap_or!(socket_event.get_ws_session_mut(), return false);
+            let fin = frame.fin;
+            match (session.fragments.take(), frame.opcode) {
+                (_, _) if frame.rsv1 && (!session.deflate || frame.opcode == WS_OP_CONTINUE) => {
+                    Err((1002, "Websocket Compressed Error".to_string()))
+                }
+                (Some((opcode, compressed, mut data)), WS_OP_CONTINUE) => {
+                    data.extend_from_slice(&frame.payload);
+                    Ok((opcode, compressed, data))
+                }
+                (None, opcode) if opcode != WS_OP_CONTINUE => Ok((opcode, frame.rsv1, frame.payload)),
+                _ => Err((1002, "Websocket Fragment Error".to_string())),
+            }.and_then(|(opcode, compressed, data)| {
+                if data.len() > session.max_frame {
+                    Err((1009, "Websocket Message Too Big".to_string()))
+                } else if opcode == WS_OP_TEXT && !session.text_json {
+                    Err((1003, "未受支持的TEXT格式".to_string()))
+                } else if !fin {
+                    session.fragments = Some((opcode, compressed, data));
+                    Ok(None)
+                } else if compressed {
+                    match ws_inflate(&data, session.max_frame) {
+                        Ok(data) => Ok(Some((opcode, data))),
+                        Err(reason) => Err((1007, format!("Websocket Deflate Error: {}", reason))),
+                    }
+                } else {
+                    Ok(Some((opcode, data)))
+                }
+            })
+        };
+        let (opcode, data) = match message {
+            Ok(Some(message)) => message,
+            Ok(None) => return true,
+            Err((code, reason)) => {
+                self.close_websocket(unique, code, &reason);
+                return false;
+            }
+        };
+
+        if opcode == WS_OP_TEXT && data.len() > ProtoJson::MAX_TEXT_LEN {
+            self.close_websocket(unique, 1009, "Websocket Text Too Big");
+            return false;
+        }
+        let net_msg = if opcode == WS_OP_TEXT {
+            String::from_utf8(data).ok().and_then(|text| ProtoJson::new_by_text(&text).ok())
+        } else {
+            NetMsg::new_by_data(&data[..]).ok()
+        };
+        let net_msg = unwrap_or!(net_msg, {
+            if let Some(socket_event) = self.get_socket_event(unique) {
+                socket_event.get_stats_mut().add_dispatch_error();
+            }
+            let reason = if opcode == WS_OP_TEXT { "解析JSON文本失败" } else { "解析二进制协议失败" };
+            self.close_websocket(unique, 1007, reason);
+            return false;
+        });
+        self.dispatch_net_msg(unique, net_msg)
+    }
+
+    /// send the close frame once, the frame after it is not allowed
+    fn write_websocket_close(&mut self, unique: &String, code: u16, reason: &str) {
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+        let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return);
+        let session = unwrap_or!(socket_event.get_ws_session_mut(), return);
+        if !session.upgraded || session.closing {
+            return;
+        }
+        session.closing = true;
+        let _ = self.write_to_socket(unique, &WsFrame::new_close(code, reason).encode());
+    }
+
+    /// send the close frame with the code and half close the connection
+    pub fn close_websocket(&mut self, unique: &String, code: u16, reason: &str) -> bool {
+        self.write_websocket_close(unique, code, reason);
+        self.shutdown_fd(unique, reason.to_string(), 3000)
+    }
+
+    /// called after the upgrade in the poll thread or the lua thread, so the timer is queued
+    fn add_websocket_ping(&mut self, unique: &String) {
+        let _guard = self.mutex.lock().unwrap();
+        let handle = TimeHandle::new_unique("WS_PING".to_string(), unique.clone());
+        let interval = {
+            let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return);
+            let session = unwrap_or!(socket_event.get_ws_session_mut(), return);
+            if session.ping_interval == 0 {
+                return;
+            }
+            session.ping_timer = handle.get_token();
+            session.ping_interval
+        };
+        self.queue_timer(handle, interval);
+    }
+
+    /// the upgrade not finished in the handshake_timeout, lua not know the connection
+    pub fn on_websocket_handshake_timeout(&mut self, unique: &String, token: u64) {
+        let upgraded = {
+            let _guard = self.mutex.lock().unwrap();
+            let socket_event = unwrap_or!(self.connect_ids.get(unique), return);
+            let session = unwrap_or!(socket_event.get_ws_session(), return);
+            if session.handshake_timer != token {
+                return;
+            }
+            session.upgraded
+        };
+        if !upgraded {
+            let info = format!("websocket handshake {} timeout", unique);
+            self.reject_websocket(unique, 408, "Request Timeout", info);
+        }
+    }
+
+    /// ping with the current ms, the pong reply it to record the rtt,
+    /// close the connection if the pongs missed in a row reach the ping_miss
+    pub fn on_websocket_ping(&mut self, unique: &String, token: u64) {
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+        let miss = {
+            let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), return);
+            let session = unwrap_or!(socket_event.get_ws_session_mut(), return);
+            if session.ping_timer != token || session.closing {
+                return;
+            }
+            if session.ping_wait {
+                session.ping_miss += 1;
+                if session.ping_miss >= session.ping_max_miss {
+                    Some(session.ping_miss)
+                } else {
+                    None
+                }
+            } else {
+                None
+            }
+        };
+        if let Some(miss) = miss {
+            self.close_websocket(unique, 1001, &format!("Websocket Ping Timeout: {} pongs missed", miss));
+            return;
+        }
+        if let Some(session) = self.connect_ids.get_mut(unique).and_then(|ev| ev.get_ws_session_mut()) {
+            session.ping_wait = true;
+        }
+        let ping = WsFrame::new(WS_OP_PING, TimeUtils::get_time_ms().to_string().into_bytes());
+        let _ = self.write_to_socket(unique, &ping.encode());
+        self.add_websocket_ping(unique);
+    }
+
 
     pub fn listen_server(&mut self, bind_ip: String, bind_port: u16, accept: Option<AcceptCb>, read: Option<ReadCb>, end: Option<EndCb>)-> Result<usize>  {
 
@@ -455,6 +1206,16 @@ impl MioEventMgr {
         Ok(socket)
     }
 
+    /// the websocket framed in the mio loop, share the connection table and the write path
+    /// with the tcp, the wss still listen by WebSocketMgr
+    pub fn listen_websocket(&mut self, bind_ip: String, bind_port: u16) -> Result<usize> {
+        if WebsocketConfig::get(bind_port).is_tls() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the wss must listen by listen_websocket"));
+        }
+        self.listen_server(bind_ip, bind_port, Some(Self::ws_accept_callback),
+                           Some(Self::ws_read_callback), Some(Self::ws_end_callback))
+    }
+
     pub fn is_unique_server(&self, unique: &String) -> bool {
         if let Some(socket_event) = self.connect_ids.get(unique) {
             return socket_event.is_server()
@@ -476,7 +1237,7 @@ impl MioEventMgr {
         let mut events = Events::with_capacity(128);
         self.poll.poll(&mut events, None)?;
         for event in events.iter() {
-            let mut is_need_cose = false;
+            let mut close_info = None;
             let unique = SocketEvent::token_to_unique(&event.token()) ;
             if self.is_unique_server(&unique) {
                 loop {
@@ -517,88 +1278,97 @@ impl MioEventMgr {
                     if socket_event.end.is_some() {
                         ev.set_end(socket_event.end);
                     }
-                    if socket_event.call_accept(&mut ev) != 1 {
+                    let accept_ret = socket_event.call_accept(&mut ev);
+                    if ev.get_out_buffer().data_len() > 0 {
+                        self.poll.registry().reregister(
+                            ev.as_client().unwrap(),
+                            client_token,
+                            Interest::READABLE.add(Interest::WRITABLE),
+                        )?;
+                    }
+                    if accept_ret != 1 {
                         self.new_socket_event_lua(ev);
                     } else {
                         self.new_socket_client(ev);
                     }
                 }
             } else if self.is_unique_client(&unique) {
-                let socket_event = self.connect_ids.get_mut(&unique).unwrap();
-                let mut is_read_data = false;
-                // if event.is_writable() {
-                //     // We can (maybe) write to the connection.
-                //     // client.get_out_cache().get_write_data()
-                //     loop {
-                //         match socket_event.write_data() {
-                //             // We want to write the entire `DATA` buffer in a single go. If we
-                //             // write less we'll return a short write error (same as
-                //             // `io::Write::write_all` does).
-                //             // Ok(n) if n < DATA.len() => return Err(io::ErrorKind::WriteZero.into()),
-                //             Ok(true) => {
-                //                 // After we've written something we'll reregister the connection
-                //                 // to only respond to readable events.
-                //                 self.poll.registry().reregister(socket_event.as_client().unwrap(), event.token(), Interest::READABLE)?;
-                //             }
-                //             Ok(false) => {
-                //             }
-                //             // Would block "errors" are the OS's way of saying that the
-                //             // connection is not actually ready to perform this I/O operation.
-                //             Err(ref err) if Self::would_block(err) => {
-                //                 break;
-                //             }
-                //             // Got interrupted (how rude!), we'll try again.
-                //             Err(ref err) if Self::interrupted(err) => {
-    
-                //             }
-                //             // Other errors we'll consider fatal.
-                //             Err(err) => {
-                //                 is_need_cose = true;
-                //                 break;
-                //             },
-                //         }
-                //     }
-                // }
-            
+                if event.is_writable() {
+                    self.flush_socket_event(&unique);
+                }
+
+                let socket_event = unwrap_or!(self.connect_ids.get_mut(&unique), continue);
                 if event.is_readable() {
-                    loop {
-                        match socket_event.read_data() {
-                            Ok(true) => {
-                                // Reading 0 bytes means the other side has closed the
-                                // connection or is done writing, then so are we.
-                                is_need_cose = true;
-                                break;
-                            }
-                            Ok(false) => {
-                                is_read_data = true;
-                                break;
-                            }
-                            Err(_err) => {
-                                is_need_cose = true;
-                                break;
-                            },
+                    match socket_event.read_data() {
+                        Ok(true) => {
+                            // Reading 0 bytes means the other side has closed the
+                            // connection or is done writing, then so are we.
+                            close_info = Some(("客户端关闭".to_string(), None));
                         }
+                        Ok(false) => (),
+                        Err(err) => {
+                            close_info = Some((Self::read_error_reason(&err), Some(err.kind())));
+                        },
                     }
 
-                    if is_read_data {
+                    // the data before the eof or error still need dispatch
+                    if socket_event.get_in_buffer().data_len() > 0 {
                         socket_event.call_read();
                     }
                 }
             }
-            if is_need_cose {
-                if let Some(mut socket_event) = self.connect_ids.remove(&unique) {
-                    socket_event.call_end();
+            if let Some((reason, kind)) = close_info {
+                self.close_socket_event(&unique, reason, kind);
+            }
+        }
+        Ok(())
+    }
 
-                    if socket_event.is_server() {
-                        self.poll.registry().deregister(socket_event.as_server().unwrap())?;
-                    } else if socket_event.is_client() {
-                        self.poll.registry().deregister(socket_event.as_client().unwrap())?;
+    /// create the in memory connection as a client accept by server_port, lua will recv
+    /// cmd_new_connection as the tcp client, the peer is used to inject and check the data
+    pub fn new_loopback_client(&mut self, server_port: u16) -> LoopbackPeer {
+        let (stream, peer) = LoopbackStream::pair();
+        let ev = SocketEvent::new_loopback(peer.get_unique().clone(), stream, server_port);
+        self.new_socket_local(ev);
+        peer
+    }
 
-                    }
-                }
+    /// create the in memory connection as a websocket client accept by server_port, the peer
+    /// inject the upgrade request and the frames, lua know it after the upgrade finished
+    pub fn new_loopback_websocket(&mut self, server_port: u16) -> LoopbackPeer {
+        let (stream, peer) = LoopbackStream::pair();
+        let mut ev = SocketEvent::new_loopback(peer.get_unique().clone(), stream, server_port);
+        ev.set_read(Some(Self::ws_read_callback));
+        ev.set_end(Some(Self::ws_end_callback));
+        Self::ws_accept_callback(&mut ev);
+        self.new_socket_client(ev);
+        peer
+    }
+
+    /// the loopback not registered in poll, read the delivered data and dispatch it like
+    /// run_one_server, return the number of the connections read data or closed
+    pub fn run_loopback(&mut self) -> usize {
+        let mutex = self.mutex.clone();
+        let _guard = mutex.lock().unwrap();
+        let uniques: Vec<String> = self.connect_ids.values()
+            .filter(|ev| ev.get_loopback().map(|l| l.is_readable()).unwrap_or(false))
+            .map(|ev| ev.get_unique().clone())
+            .collect();
+        for unique in &uniques {
+            let socket_event = unwrap_or!(self.connect_ids.get_mut(unique), continue);
+            let close_info = match socket_event.read_data() {
+                Ok(false) => None,
+                Ok(true) => Some(("客户端关闭".to_string(), None)),
+                Err(err) => Some((Self::read_error_reason(&err), Some(err.kind()))),
+            };
+            if socket_event.get_in_buffer().data_len() > 0 {
+                socket_event.call_read();
+            }
+            if let Some((reason, kind)) = close_info {
+                self.close_socket_event(unique, reason, kind);
             }
         }
-        Ok(())
+        uniques.len()
     }
 
     pub fn run_server(&mut self) -> Result<()> {
@@ -626,18 +1396,48 @@ impl MioEventMgr {
             TimeHandle::new("MIO".to_string()), 10, true, false));
     }
     
+    /// sweep the http requests not responded by lua in the timeout, it is added in the
+    /// thread start the listen, so queued like queue_timer
+    pub fn add_http_expire_timer(&self) {
+        self.timer_queue.lock().unwrap().push(Handler::new_step_ms(
+            TimeHandle::new("HTTP_EXPIRE".to_string()), HTTP_EXPIRE_STEP, true, false));
+    }
+
     pub fn add_check_db_timer(&mut self) {
         self.timer.add_timer(Handler::new_step_ms(
             TimeHandle::new("CHECK_DB".to_string()), 5 * 60 * 1000, true, false));
     }
 
 
+    /// the timer is not thread safe, the network threads add the timer by the queue
+    pub fn queue_timer(&self, handle: TimeHandle, tick_step: u64) {
+        self.timer_queue.lock().unwrap().push(Handler::new_step_ms(handle, tick_step, false, false));
+    }
+
+    fn add_queued_timers(&mut self, timer: &mut Timer<TimeHandle>) {
+        let handles: Vec<Handler<TimeHandle>> = self.timer_queue.lock().unwrap().drain(..).collect();
+        for handle in handles {
+            timer.add_timer(handle);
+        }
+    }
+
     pub fn run_timer(&mut self) {
         // self.add_server_to_timer();
+        self.timer.add_timer(Handler::new_step_ms(
+            TimeHandle::new("TIMER_QUEUE".to_string()), 1, true, false));
         self.timer.run_loop_timer();
     }
 
     
+    fn read_error_reason(err: &io::Error) -> String {
+        match err.kind() {
+            io::ErrorKind::ConnectionReset => "连接被重置".to_string(),
+            io::ErrorKind::ConnectionAborted => "连接被中止".to_string(),
+            io::ErrorKind::InvalidData => format!("数据异常: {}", err),
+            _ => format!("读取错误: {}", err),
+        }
+    }
+
     fn would_block(err: &io::Error) -> bool {
         err.kind() == io::ErrorKind::WouldBlock
     }
@@ -1,14 +1,18 @@
 
 mod http_mgr;
+mod http_router;
 mod command_mgr;
 mod mio_event_mgr;
 mod protocol_mgr;
 mod websocket_mgr;
 mod tcp_mgr;
+mod capture_mgr;
 
-pub use self::http_mgr::HttpMgr;
+pub use self::http_mgr::{HttpMgr, HttpConfig, HttpStats, HttpClientOptions};
+pub use self::http_router::{HttpRouter, HttpRequest, HttpRouteMatch};
 pub use self::command_mgr::CommandMgr;
 pub use self::mio_event_mgr::MioEventMgr;
 pub use self::protocol_mgr::ProtocolMgr;
-pub use self::websocket_mgr::{WebSocketMgr, WebsocketClient};
-pub use self::tcp_mgr::TcpMgr;
\ No newline at end of file
+pub use self::websocket_mgr::{WebSocketMgr, WebsocketClient, WebsocketConnectOptions, WebsocketConfig, WebsocketRequest, WebsocketAccept, WebsocketHook};
+pub use self::tcp_mgr::TcpMgr;
+pub use self::capture_mgr::{CaptureMgr, CaptureRecord, CAPTURE_IN, CAPTURE_OUT};
@@ -1,20 +1,32 @@
+use std::collections::HashMap;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use tunm_proto;
 use td_rlua::{self};
-use {EngineProtocol, ProtoRt, ProtoJson, ProtoBin, ProtoText};
-use {NetMsg, MSG_TYPE_TD, MSG_TYPE_BIN, MSG_TYPE_TEXT, MSG_TYPE_JSON, NetResult};
+use {EngineProtocol, ProtoRt, ProtoJson, ProtoBin, ProtoText, ProtoProtobuf, ProtoMsgpack};
+use {NetMsg, MSG_TYPE_TD, MSG_TYPE_BIN, MSG_TYPE_TEXT, MSG_TYPE_JSON, MSG_TYPE_PROTOBUF, MSG_TYPE_MSGPACK, NetResult, make_extension_error};
+use {GlobalConfig, LogUtils, log_utils, ProtoSchema, SchemaConfig, ProtoCompat, CompatReport};
 
-#[allow(dead_code)]
+/// the protocols registered by msg_type, the built-in protocols are registered when created,
+/// the custom protocol can be registered from rust or the config list `protocols`
 pub struct ProtocolMgr {
-    td: ProtoRt,
-    bin: ProtoBin,
-    json: ProtoJson,
-    text: ProtoText,
+    protocols: HashMap<u8, Box<dyn EngineProtocol>>,
+    /// the schema and its config used by the network thread to validate the incoming message,
+    /// reload by the lua thread, so both are changed under the lock
+    schema: Mutex<Option<(Arc<ProtoSchema>, SchemaConfig)>>,
 }
 
-static mut EL: *mut ProtocolMgr = 0 as *mut _;
+impl Default for ProtocolMgr {
+    fn default() -> ProtocolMgr {
+        ProtocolMgr::new()
+    }
+}
+
+static mut EL: *mut ProtocolMgr = ptr::null_mut();
 impl ProtocolMgr {
     pub fn instance() -> &'static mut ProtocolMgr {
         unsafe {
-            if EL == 0 as *mut _ {
+            if EL.is_null() {
                 EL = Box::into_raw(Box::new(ProtocolMgr::new()));
             }
             &mut *EL
@@ -22,32 +34,205 @@ impl ProtocolMgr {
     }
 
     pub fn new() -> ProtocolMgr {
-        ProtocolMgr {
-            td: ProtoRt {},
-            bin: ProtoBin {},
-            json: ProtoJson {},
-            text: ProtoText {},
+        let mut mgr = ProtocolMgr {
+            protocols: HashMap::new(),
+            schema: Mutex::new(None),
+        };
+        mgr.register_protocol(MSG_TYPE_TD, Box::new(ProtoRt {}));
+        mgr.register_protocol(MSG_TYPE_JSON, Box::new(ProtoJson {}));
+        mgr.register_protocol(MSG_TYPE_BIN, Box::new(ProtoBin {}));
+        mgr.register_protocol(MSG_TYPE_TEXT, Box::new(ProtoText {}));
+        mgr.register_protocol(MSG_TYPE_PROTOBUF, Box::new(ProtoProtobuf::new()));
+        mgr.register_protocol(MSG_TYPE_MSGPACK, Box::new(ProtoMsgpack {}));
+        mgr
+    }
+
+    /// create the built-in protocol by the name, used by the config list
+    pub fn create_builtin(name: &str) -> Option<Box<dyn EngineProtocol>> {
+        match name {
+            "td" => Some(Box::new(ProtoRt {})),
+            "json" => Some(Box::new(ProtoJson {})),
+            "bin" => Some(Box::new(ProtoBin {})),
+            "text" => Some(Box::new(ProtoText {})),
+            "protobuf" => Some(Box::new(ProtoProtobuf::new())),
+            "msgpack" => Some(Box::new(ProtoMsgpack {})),
+            _ => None,
+        }
+    }
+
+    /// register the protocol for the msg_type, return the old one if exist
+    pub fn register_protocol(&mut self, msg_type: u8, protocol: Box<dyn EngineProtocol>) -> Option<Box<dyn EngineProtocol>> {
+        self.protocols.insert(msg_type, protocol)
+    }
+
+    pub fn unregister_protocol(&mut self, msg_type: u8) -> Option<Box<dyn EngineProtocol>> {
+        self.protocols.remove(&msg_type)
+    }
+
+    pub fn get_protocol(&mut self, msg_type: u8) -> Option<&mut Box<dyn EngineProtocol>> {
+        self.protocols.get_mut(&msg_type)
+    }
+
+    pub fn has_protocol(&self, msg_type: u8) -> bool {
+        self.protocols.contains_key(&msg_type)
+    }
+
+    /// the registered msg_types in order
+    pub fn get_msg_types(&self) -> Vec<u8> {
+        let mut msg_types: Vec<u8> = self.protocols.keys().cloned().collect();
+        msg_types.sort();
+        msg_types
+    }
+
+    /// load the schema of the protocol, such as the .proto files of protobuf
+    pub fn load_schema(&mut self, msg_type: u8, path: &str) -> NetResult<()> {
+        match self.protocols.get_mut(&msg_type) {
+            Some(protocol) => protocol.load_schema(path),
+            None => {
+                Self::log_unknow_type(msg_type, "load schema");
+                Err(make_extension_error("unknow protocol msg_type", None))
+            }
+        }
+    }
+
+    /// load the protocol.txt and validate the incoming td message by it
+    pub fn load_proto_schema(&mut self, config: SchemaConfig) -> NetResult<()> {
+        let schema = Self::load_checked_schema(&config.path)?;
+        *self.schema.lock().unwrap() = Some((Arc::new(schema), config));
+        Ok(())
+    }
+
+    /// parse and check the new protocol.txt, then swap it after the message in validating,
+    /// return the changes from the old schema. the path None reload the loaded one
+    pub fn reload_proto_schema(&mut self, path: Option<String>) -> NetResult<CompatReport> {
+        let loaded = self.schema.lock().unwrap().as_ref().map(|(_, config)| config.path.clone());
+        let path = path.or(loaded)
+            .or_else(|| GlobalConfig::instance().proto_schema.as_ref().map(|c| c.path.clone()));
+        let path = unwrap_or!(path, return Err(make_extension_error("reload proto schema no path", None)));
+        let schema = Self::load_checked_schema(&path)?;
+        let report = {
+            let mut guard = self.schema.lock().unwrap();
+            match guard.as_mut() {
+                Some((old, config)) => {
+                    let report = ProtoCompat::diff(old, &schema);
+                    *old = Arc::new(schema);
+                    config.path = path.clone();
+                    report
+                }
+                None => {
+                    // not loaded when start, validate by the config settings from now on
+                    let mut config = GlobalConfig::instance().proto_schema.clone().unwrap_or_default();
+                    config.path = path.clone();
+                    let report = ProtoCompat::diff(&ProtoSchema::default(), &schema);
+                    *guard = Some((Arc::new(schema), config));
+                    report
+                }
+            }
+        };
+        let info = format!("reload proto schema {} with {} changes, {} breaking", path, report.changes.len(), report.get_breaking().len());
+        LogUtils::instance().append(log_utils::LOG_INFO, &info);
+        for change in report.get_breaking() {
+            LogUtils::instance().append(log_utils::LOG_WARN, &change.to_string());
+        }
+        Ok(report)
+    }
+
+    /// the schema passed the parse but failed the check such as the reused index is not loaded
+    fn load_checked_schema(path: &str) -> NetResult<ProtoSchema> {
+        let schema = ProtoSchema::load(path)?;
+        if let Err(err) = schema.check() {
+            return Err(make_extension_error("proto schema check failed", Some(&err)));
+        }
+        Ok(schema)
+    }
+
+    pub fn get_proto_schema(&self) -> Option<Arc<ProtoSchema>> {
+        self.schema.lock().unwrap().as_ref().map(|(schema, _)| schema.clone())
+    }
+
+    /// kick the connection when the message invalid
+    pub fn is_kick_invalid(&self) -> bool {
+        self.schema.lock().unwrap().as_ref().and_then(|(_, config)| config.kick).unwrap_or(false)
+    }
+
+    /// check the td message by the schema, the other msg_type or no schema always pass,
+    /// the decoded args are kept in the message, so lua not decode it again
+    pub fn validate_message(&self, net_msg: &mut NetMsg) -> Result<(), String> {
+        if net_msg.get_msg_type() != MSG_TYPE_TD {
+            return Ok(());
+        }
+        let guard = self.schema.lock().unwrap();
+        let (schema, config) = unwrap_or!(guard.as_ref(), return Ok(()));
+        net_msg.set_read_data();
+        let ret = match tunm_proto::decode_proto(net_msg.get_buffer()) {
+            Ok((name, args)) => {
+                let ret = schema.validate(&name, &args, config);
+                net_msg.set_args(Some(args));
+                ret
+            }
+            Err(err) => Err(format!("decode failed {:?}", err)),
+        };
+        net_msg.set_read_data();
+        ret
+    }
+
+    /// register the built-in protocols by the config list, such as `protocols: {5: json}`,
+    /// then load the schemas by `protocol_schemas: {4: proto/}`
+    pub fn load_config(&mut self, config: &GlobalConfig) -> bool {
+        let mut success = true;
+        for (msg_type, name) in config.protocols.iter().flat_map(|p| p.iter()) {
+            match Self::create_builtin(name) {
+                Some(protocol) => {
+                    self.register_protocol(*msg_type, protocol);
+                }
+                None => {
+                    let info = format!("unknow protocol name {} for msg_type {}", name, msg_type);
+                    LogUtils::instance().append(log_utils::LOG_ERROR, &info);
+                    success = false;
+                }
+            }
+        }
+        if let Some(ref schema_config) = config.proto_schema {
+            if let Err(err) = self.load_proto_schema(schema_config.clone()) {
+                let info = format!("load proto schema {} failed {:?}", schema_config.path, err);
+                LogUtils::instance().append(log_utils::LOG_ERROR, &info);
+                success = false;
+            }
         }
+        for (msg_type, path) in config.protocol_schemas.iter().flat_map(|p| p.iter()) {
+            if let Err(err) = self.load_schema(*msg_type, path) {
+                let info = format!("load schema {} for msg_type {} failed {:?}", path, msg_type, err);
+                LogUtils::instance().append(log_utils::LOG_ERROR, &info);
+                success = false;
+            }
+        }
+        success
+    }
+
+    fn log_unknow_type(msg_type: u8, action: &str) {
+        let info = format!("{} unknow protocol msg_type {}", action, msg_type);
+        println!("{}", info);
+        LogUtils::instance().append(log_utils::LOG_ERROR, &info);
     }
 
     pub fn pack_protocol(&mut self, lua: *mut td_rlua::lua_State, index: i32, msg_type: u8) -> Option<NetMsg> {
-        match msg_type {
-            MSG_TYPE_TD => ProtoRt::pack_protocol(lua, index),
-            MSG_TYPE_JSON => ProtoJson::pack_protocol(lua, index),
-            MSG_TYPE_BIN => ProtoBin::pack_protocol(lua, index),
-            MSG_TYPE_TEXT => ProtoText::pack_protocol(lua, index),
-            _ => None
+        match self.protocols.get_mut(&msg_type) {
+            Some(protocol) => protocol.pack_protocol(lua, index),
+            None => {
+                Self::log_unknow_type(msg_type, "pack");
+                None
+            }
         }
     }
 
     pub fn unpack_protocol(&mut self, lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> i32 {
         let msg_type = net_msg.get_msg_type();
-        let ret = match msg_type {
-            MSG_TYPE_TD => ProtoRt::unpack_protocol(lua, net_msg),
-            MSG_TYPE_JSON => ProtoJson::unpack_protocol(lua, net_msg),
-            MSG_TYPE_BIN => ProtoBin::unpack_protocol(lua, net_msg),
-            MSG_TYPE_TEXT => ProtoText::unpack_protocol(lua, net_msg),
-            _ => Ok(0)
+        let ret = match self.protocols.get_mut(&msg_type) {
+            Some(protocol) => protocol.unpack_protocol(lua, net_msg),
+            None => {
+                Self::log_unknow_type(msg_type, "unpack");
+                Ok(0)
+            }
         };
         ret.ok().unwrap_or(0)
     }
@@ -55,13 +240,12 @@ impl ProtocolMgr {
 
     pub fn convert_string(&mut self, lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<String> {
         let msg_type = net_msg.get_msg_type();
-        let ret = match msg_type {
-            MSG_TYPE_TD => ProtoRt::convert_string(lua, net_msg),
-            MSG_TYPE_JSON => ProtoJson::convert_string(lua, net_msg),
-            MSG_TYPE_BIN => ProtoBin::convert_string(lua, net_msg),
-            MSG_TYPE_TEXT => ProtoText::convert_string(lua, net_msg),
-            _ => Ok("".to_string())
-        };
-        ret
+        match self.protocols.get_mut(&msg_type) {
+            Some(protocol) => protocol.convert_string(lua, net_msg),
+            None => {
+                Self::log_unknow_type(msg_type, "convert");
+                Err(make_extension_error("unknow protocol msg_type", None))
+            }
+        }
     }
 }
@@ -2,72 +2,456 @@
 
 extern crate ws;
 
-use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::IpAddr;
 use std::thread;
-use std::sync::Arc;
+use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use td_rthreadpool::ReentrantMutex;
 
-use ws::{Builder, Settings, CloseCode, Sender, Handler, Handshake, Message, Result, Error};
-use ws::util::{Token, Timeout};
+use ws::{Builder, Factory, Settings, CloseCode, Sender, Handler, Handshake, Message, Result, Error, ErrorKind, Request, Response,
+    Frame, OpCode};
+use serde_json::{self, Value as JsonValue};
+use ws::util::{Token, Timeout, TcpStream};
+use openssl::error::ErrorStack;
+use openssl::ssl::{SslAcceptor, SslConnector, SslFiletype, SslMethod, SslStream};
+
+
+use crate::{LuaEngine, NetMsg, SocketEvent, MioEventMgr, ProtocolMgr, LogUtils, log_utils, CAPTURE_IN, HandshakeStep};
+use crate::{GlobalConfig, ProtoJson, MSG_TYPE_JSON, NetResult, make_extension_error, TimeUtils};
+use crate::net::{WsUpgrade, ws_deflate, ws_inflate, WS_DEFLATE_EXTENSION};
+
+/// the timeout token of the keepalive ping
+const PING_EVENT: Token = Token(1);
+/// the timeout token of the upgrade not finished
+const HANDSHAKE_EVENT: Token = Token(2);
+/// the timeout token of the client connect not opened
+const CONNECT_EVENT: Token = Token(3);
+
+/// the id of the client connection
+static CLIENT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// the settings of the websocket listener in the config, such as
+/// `websockets: {8080: {text_json: true}}`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct WebsocketConfig {
+    /// accept the text frame as the json message `{"name": name, "args": [args...]}`,
+    /// and the json message reply in the text frame, default close the connection
+    pub text_json: Option<bool>,
+    /// the pem certificate chain file, listen the wss with the key
+    pub cert: Option<String>,
+    /// the pem private key file of the certificate
+    pub key: Option<String>,
+    /// send the ping every ms to detect the dead peer, default 0 no ping
+    pub ping_interval: Option<u64>,
+    /// close the connection after the pongs missed, default 3
+    pub ping_miss: Option<u32>,
+    /// the max connections of the listener, default 10000
+    pub max_connections: Option<usize>,
+    /// the in buffer capacity of each connection, default 2048000
+    pub in_buffer: Option<usize>,
+    /// the out buffer capacity of each connection, default 2048000
+    pub out_buffer: Option<usize>,
+    /// close the connection recv the frame or the message bigger than it, default 2048000
+    pub max_frame: Option<usize>,
+    /// close the connection not finish the upgrade in ms, default 0 no limit
+    pub handshake_timeout: Option<u64>,
+    /// accept the permessage-deflate extension offered by the client, default false
+    pub deflate: Option<bool>,
+}
+
+impl WebsocketConfig {
+    /// the settings of the listen port, default if not config
+    pub fn get(port: u16) -> WebsocketConfig {
+        GlobalConfig::instance().websockets.as_ref()
+            .and_then(|configs| configs.get(&port).cloned())
+            .unwrap_or_default()
+    }
+
+    pub fn is_text_json(&self) -> bool {
+        self.text_json.unwrap_or(false)
+    }
+
+    pub fn get_ping_interval(&self) -> u64 {
+        self.ping_interval.unwrap_or(0)
+    }
+
+    pub fn get_ping_miss(&self) -> u32 {
+        self.ping_miss.unwrap_or(3)
+    }
+
+    pub fn get_max_connections(&self) -> usize {
+        self.max_connections.unwrap_or(10_000)
+    }
+
+    pub fn get_in_buffer(&self) -> usize {
+        self.in_buffer.unwrap_or(2048000)
+    }
+
+    pub fn get_out_buffer(&self) -> usize {
+        self.out_buffer.unwrap_or(2048000)
+    }
+
+    pub fn get_max_frame(&self) -> usize {
+        self.max_frame.unwrap_or(2048000)
+    }
+
+    pub fn get_handshake_timeout(&self) -> u64 {
+        self.handshake_timeout.unwrap_or(0)
+    }
+
+    pub fn is_deflate(&self) -> bool {
+        self.deflate.unwrap_or(false)
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.cert.is_some() && self.key.is_some()
+    }
+
+    /// load the certificate and the key for the wss listener
+    pub fn build_acceptor(&self) -> NetResult<SslAcceptor> {
+        let (cert, key) = match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => (cert, key),
+            _ => return Err(make_extension_error("websocket tls need cert and key", None)),
+        };
+        let map_err = |err: ErrorStack| make_extension_error("websocket tls config failed", Some(&err.to_string()));
+        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).map_err(map_err)?;
+        builder.set_certificate_chain_file(cert).map_err(map_err)?;
+        builder.set_private_key_file(key, SslFiletype::PEM).map_err(map_err)?;
+        builder.check_private_key().map_err(map_err)?;
+        Ok(builder.build())
+    }
+}
+
+/// the upgrade request of the websocket passed to the handshake hook
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct WebsocketRequest {
+    pub port: u16,
+    /// the path without the query, such as `/game`
+    pub path: String,
+    pub query: HashMap<String, String>,
+    /// the lowercase header name to the value
+    pub headers: HashMap<String, String>,
+    pub origin: Option<String>,
+}
+
+/// the result of the handshake hook
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebsocketAccept {
+    /// accept with the metadata attached to the `SocketEvent`
+    Accept(HashMap<String, String>),
+    /// reply the http status and the reason, then close
+    Reject(u16, String),
+}
+
+/// decide the upgrade in the websocket thread before `cmd_new_connection`
+pub type WebsocketHook = Box<dyn Fn(&WebsocketRequest) -> WebsocketAccept + Send + Sync>;
+
+impl WebsocketRequest {
+    pub fn new(req: &Request, port: u16) -> WebsocketRequest {
+        WebsocketRequest::new_by_parts(port, req.resource(), req.headers())
+    }
+
+    /// the resource is the path with the query, the header name is lowercased
+    pub fn new_by_parts(port: u16, resource: &str, headers: &[(String, Vec<u8>)]) -> WebsocketRequest {
+        let (path, query) = match resource.find('?') {
+            Some(index) => (&resource[..index], &resource[index + 1..]),
+            None => (resource, ""),
+        };
+        let headers: HashMap<String, String> = headers.iter()
+            .map(|(k, v)| (k.to_lowercase(), String::from_utf8_lossy(v).to_string()))
+            .collect();
+        WebsocketRequest {
+            port,
+            path: path.to_string(),
+            query: url::form_urlencoded::parse(query.as_bytes()).into_owned().collect(),
+            origin: headers.get("origin").cloned(),
+            headers,
+        }
+    }
+}
+
+impl WebsocketAccept {
+    /// the lua hook return nil or true to accept, false to reject with 403, or the table
+    /// `{status = 401, reason = "invalid token"}`, the other keys of the table are the metadata
+    pub fn from_json(value: JsonValue) -> WebsocketAccept {
+        let mut map = match value {
+            JsonValue::Null | JsonValue::Bool(true) => return WebsocketAccept::Accept(HashMap::new()),
+            JsonValue::Object(map) => map,
+            _ => return WebsocketAccept::Reject(403, "Forbidden".to_string()),
+        };
+        let status = map.remove("status").and_then(|s| s.as_u64()).unwrap_or(101) as u16;
+        let reason = map.remove("reason");
+        if status != 101 {
+            let reason = reason.and_then(|r| r.as_str().map(|r| r.to_string()));
+            return WebsocketAccept::Reject(status, reason.unwrap_or("Forbidden".to_string()));
+        }
+        WebsocketAccept::Accept(map.into_iter().map(|(k, v)| {
+            match v {
+                JsonValue::String(v) => (k, v),
+                v => (k, v.to_string()),
+            }
+        }).collect())
+    }
+}
 
+/// the options of the websocket client, such as
+/// `{headers = {Authorization = "Bearer token"}, protocols = {"chat"}, reconnect = 3000, reconnect_max = 5}`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct WebsocketConnectOptions {
+    /// the extra headers of the upgrade request
+    pub headers: Option<HashMap<String, String>>,
+    /// the subprotocols offered in the `Sec-WebSocket-Protocol`
+    pub protocols: Option<Vec<String>>,
+    /// reconnect after the ms when the connect failed or the connection lost, default no reconnect
+    pub reconnect: Option<u64>,
+    /// the max reconnect times in a row, reset after the connection opened, default 0 no limit
+    pub reconnect_max: Option<u32>,
+}
+
+/// the result of one connect, shared by the client and the connect thread
+#[derive(Debug, Default)]
+struct WebsocketClientState {
+    opened: bool,
+    /// the connection closed by the script or the server stopped, no reconnect
+    closed: bool,
+    /// the first error before the connection opened
+    error: Option<String>,
+}
 
-use crate::{LuaEngine, NetMsg, SocketEvent, MioEventMgr, LogUtils, log_utils};
+/// create the client in the event loop of the connect, the connection dropped without the
+/// callback of the client such as the address not resolved is reported in `connection_lost`
+struct WebsocketClientFactory {
+    port: u16,
+    cookie: u32,
+    timeout: u64,
+    options: Arc<WebsocketConnectOptions>,
+    state: Arc<Mutex<WebsocketClientState>>,
+}
 
 pub struct WebsocketClient {
     pub out: Sender,
     pub port: u16,
     pub unique: String,
     pub cookie: u32,
+    options: Arc<WebsocketConnectOptions>,
+    state: Arc<Mutex<WebsocketClientState>>,
+    opened: bool,
+    /// the lost reported, the close after the error is ignored
+    lost: bool,
+}
+
+impl Factory for WebsocketClientFactory {
+    type Handler = WebsocketClient;
+
+    fn connection_made(&mut self, out: Sender) -> WebsocketClient {
+        if self.timeout > 0 {
+            let _ = out.timeout(self.timeout, CONNECT_EVENT);
+        }
+        WebsocketClient {
+            out,
+            port: self.port,
+            unique: String::new(),
+            cookie: self.cookie,
+            options: self.options.clone(),
+            state: self.state.clone(),
+            opened: false,
+            lost: false,
+        }
+    }
+
+    fn connection_lost(&mut self, client: WebsocketClient) {
+        if !client.opened {
+            let mut state = self.state.lock().unwrap();
+            state.error.get_or_insert_with(|| "connect failed".to_string());
+        }
+    }
+}
+
+impl WebsocketClient {
+    /// connect the url in the current thread until the connection closed, the failed connect
+    /// call the lua `cmd_connect_failed(cookie, reason, reconnecting)`, the lost connection
+    /// and the failed connect try again after the `reconnect` ms if set
+    pub fn connect(url: String, port: u16, timeout: u64, cookie: u32, options: WebsocketConnectOptions) {
+        let options = Arc::new(options);
+        let delay = options.reconnect.unwrap_or(0);
+        let max_times = options.reconnect_max.unwrap_or(0);
+        let mut times = 0;
+        loop {
+            let state = Arc::new(Mutex::new(WebsocketClientState::default()));
+            let factory = WebsocketClientFactory {
+                port,
+                cookie,
+                timeout,
+                options: options.clone(),
+                state: state.clone(),
+            };
+            let result = url::Url::parse(&url)
+                .map_err(|err| format!("invalid url {} {}", url, err))
+                .and_then(|parsed| Self::run_connect(factory, parsed));
+            let (opened, closed, error) = {
+                let mut state = state.lock().unwrap();
+                (state.opened, state.closed, state.error.take())
+            };
+            if opened {
+                times = 0;
+            }
+            let retry = result.is_ok() && !closed && delay > 0 && (max_times == 0 || times < max_times)
+                && !MioEventMgr::instance().is_exit();
+            if !opened {
+                let reason = result.err().or(error).unwrap_or_else(|| "connect failed".to_string());
+                let info = format!("Websocket connect {} failed {}", url, reason);
+                LogUtils::instance().append(log_utils::LOG_ERROR, &info);
+                LuaEngine::instance().apply_connect_failed(cookie, reason, retry);
+            }
+            if !retry {
+                return;
+            }
+            times += 1;
+            thread::sleep(Duration::from_millis(delay));
+        }
+    }
+
+    fn run_connect(factory: WebsocketClientFactory, url: url::Url) -> ::std::result::Result<(), String> {
+        let mut ws = Builder::new().build(factory).map_err(|err| err.to_string())?;
+        ws.connect(url).map_err(|err| err.to_string())?;
+        ws.run().map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    /// report the lost connection once, the connection closed by the script is removed before
+    fn on_lost(&mut self, reason: String) {
+        if !self.opened {
+            self.state.lock().unwrap().error.get_or_insert(reason);
+            return;
+        }
+        if self.lost {
+            return;
+        }
+        self.lost = true;
+        if MioEventMgr::instance().get_socket_event(&self.unique).is_none() {
+            self.state.lock().unwrap().closed = true;
+        }
+        WebSocketMgr::instance().on_close(&self.unique, &self.out, reason);
+    }
 }
 
 impl Handler for WebsocketClient {
-    
+
+    /// the upgrade request with the extra headers and the subprotocols in the options
+    fn build_request(&mut self, url: &url::Url) -> Result<Request> {
+        let mut req = Request::from_url(url)?;
+        if let Some(ref headers) = self.options.headers {
+            for (name, value) in headers {
+                req.headers_mut().push((name.clone(), value.clone().into_bytes()));
+            }
+        }
+        if let Some(ref protocols) = self.options.protocols {
+            for protocol in protocols {
+                req.add_protocol(protocol);
+            }
+        }
+        Ok(req)
+    }
+
+    /// the wss client verify the server by the system ca, or the `wss_ca` in the config such as
+    /// the self-signed certificate, the ip address host is verified without the sni
+    fn upgrade_ssl_client(&mut self, stream: TcpStream, url: &url::Url) -> Result<SslStream<TcpStream>> {
+        let host = url.host_str().ok_or_else(|| Error::new(ErrorKind::Protocol, format!("Unable to parse host from {}", url)))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let ssl_error = |err: ErrorStack| Error::new(ErrorKind::Internal, format!("Failed to upgrade client to SSL: {}", err));
+        let mut builder = SslConnector::builder(SslMethod::tls()).map_err(ssl_error)?;
+        if let Some(ref ca) = GlobalConfig::instance().wss_ca {
+            builder.set_ca_file(ca).map_err(ssl_error)?;
+        }
+        let mut config = builder.build().configure().map_err(ssl_error)?;
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            config.set_use_server_name_indication(false);
+            config.set_verify_hostname(false);
+            config.param_mut().set_ip(ip).map_err(ssl_error)?;
+        }
+        config.connect(host, stream).map_err(Error::from)
+    }
+
     fn on_open(&mut self, shake: Handshake) -> Result<()> {
         let mut addr = "unkown_ip".to_string();
         if let Some(ip_addr) = shake.remote_addr()? {
             addr = format!("{}", ip_addr);
         }
-        
-        self.unique = format!("WS:{}", self.out.token().0);
+
+        // each connect has the own event loop, so the token is not unique
+        self.unique = format!("WSC:{}", CLIENT_ID.fetch_add(1, Ordering::Relaxed));
+        self.opened = true;
+        self.state.lock().unwrap().opened = true;
 
         let mut event = SocketEvent::new(self.unique.clone(), addr.to_string(), self.port);
         event.set_cookie(self.cookie);
         event.set_websocket(true);
         event.set_local(true);
         event.set_mio(true);
+        // the subprotocol selected by the server is read by get_socket_meta
+        if let Ok(Some(protocol)) = shake.response.protocol() {
+            let mut meta = HashMap::new();
+            meta.insert("protocol".to_string(), protocol.to_string());
+            event.set_meta(meta);
+        }
 
         MioEventMgr::instance().new_socket_event_lua(event);
         WebSocketMgr::instance().on_open(&self.unique, self.out.clone());
         Ok(())
     }
 
+    /// the connection not opened in the timeout is dropped
+    fn on_timeout(&mut self, event: Token) -> Result<()> {
+        if event == CONNECT_EVENT && !self.opened {
+            self.state.lock().unwrap().error.get_or_insert_with(|| "connect timeout".to_string());
+            return Err(Error::from(io::Error::new(io::ErrorKind::TimedOut, "connect timeout")));
+        }
+        Ok(())
+    }
+
     fn on_message(&mut self, msg: Message) -> Result<()> {
-        let net_msg = match msg {
-            Message::Text(_text) => {
-                WebSocketMgr::instance().on_close(&self.unique, &self.out, "未受支持的TEXT格式".to_string());
-                // LuaEngine::instance().apply_lost_connect(&self.unique as SOCKET, "未受支持的TEXT格式".to_string());
-                return Ok(());
+        let mut net_msg = match msg {
+            // the server reply the json message in the text frame
+            Message::Text(text) => {
+                let net_msg = ProtoJson::new_by_text(&text).ok();
+                MioEventMgr::instance().record_socket_recv(&self.unique, text.len(), net_msg.is_some());
+                unwrap_or!(net_msg, {
+                    self.on_lost("解析JSON文本失败".to_string());
+                    return Ok(())
+                })
             },
             Message::Binary(data) => {
-                unwrap_or!(NetMsg::new_by_proto_data(&data[..]).ok(), {
-                    WebSocketMgr::instance().on_close(&self.unique, &self.out, "解析二进制协议失败".to_string());
+                let net_msg = NetMsg::new_by_proto_data(&data[..]).ok();
+                MioEventMgr::instance().record_socket_recv(&self.unique, data.len(), net_msg.is_some());
+                unwrap_or!(net_msg, {
+                    self.on_lost("解析二进制协议失败".to_string());
                     // LuaEngine::instance().apply_lost_connect(&self.unique as SOCKET, "解析二进制协议失败".to_string());
                     return Ok(())
                 })
             },
         };
 
+        MioEventMgr::instance().capture_message(CAPTURE_IN, &self.unique, &mut net_msg);
         LuaEngine::instance().apply_message(&self.unique, net_msg);
         Ok(())
     }
 
     fn on_close(&mut self, code: CloseCode, reason: &str) {
-        WebSocketMgr::instance().on_close(&self.unique, &self.out, format!("WebSocket closing for ({:?}) {}", code, reason).to_string());
+        self.on_lost(format!("WebSocket closing for ({:?}) {}", code, reason));
     }
 
     fn on_error(&mut self, err: Error) {
+        // the display of the io error is the deprecated description, such as the connection refused
+        let reason = match err.kind {
+            ErrorKind::Io(ref err) => err.to_string(),
+            _ => err.to_string(),
+        };
         // Shutdown on any error
-        WebSocketMgr::instance().on_close(&self.unique, &self.out, format!("Shutting down server for error: {}", err).to_string());
+        self.on_lost(format!("Shutting down server for error: {}", reason));
     }
 
 }
@@ -80,10 +464,131 @@ struct WebsocketServer {
     port: u16,
     unique: String,
     open_timeout: Option<Timeout>,
+    text_json: bool,
+    ssl: Option<Arc<SslAcceptor>>,
+    /// accepted by the handshake hook
+    meta: HashMap<String, String>,
+    ping_interval: u64,
+    ping_max_miss: u32,
+    /// the pongs missed in a row
+    ping_miss: u32,
+    /// the ping sent and the pong not received
+    ping_wait: bool,
+    ping_timeout: Option<Timeout>,
+    max_frame: usize,
+    /// accept the permessage-deflate if the client offered
+    deflate: bool,
+    /// the permessage-deflate negotiated
+    compress: bool,
+    /// the opcode and the payload of the compressed fragmented message
+    fragments: Option<(OpCode, Vec<u8>)>,
+}
+
+impl WebsocketServer {
+    fn new(out: Sender, port: u16, config: &WebsocketConfig, ssl: Option<Arc<SslAcceptor>>) -> WebsocketServer {
+        if config.get_handshake_timeout() > 0 {
+            let _ = out.timeout(config.get_handshake_timeout(), HANDSHAKE_EVENT);
+        }
+        WebsocketServer {
+            out,
+            port,
+            unique: String::new(),
+            open_timeout: None,
+            text_json: config.is_text_json(),
+            ssl,
+            meta: HashMap::new(),
+            ping_interval: config.get_ping_interval(),
+            ping_max_miss: config.get_ping_miss(),
+            ping_miss: 0,
+            ping_wait: false,
+            ping_timeout: None,
+            max_frame: config.get_max_frame(),
+            deflate: config.is_deflate(),
+            compress: false,
+            fragments: None,
+        }
+    }
+
+    /// the compressed message assembled and inflated, Ok(None) wait the left fragments
+    fn inflate_frame(&mut self, mut frame: Frame) -> ::std::result::Result<Option<Frame>, String> {
+        if !self.compress || frame.is_control() || (self.fragments.is_none() && !frame.has_rsv1()) {
+            return Ok(Some(frame));
+        }
+        frame.set_rsv1(false);
+        let is_final = frame.is_final();
+        let (opcode, mut data) = match self.fragments.take() {
+            Some(fragments) if frame.opcode() == OpCode::Continue => fragments,
+            None if frame.opcode() != OpCode::Continue => (frame.opcode(), Vec::new()),
+            _ => return Err("compressed fragment error".to_string()),
+        };
+        data.extend(frame.into_data());
+        if data.len() > self.max_frame {
+            return Err(format!("compressed message size > {}", self.max_frame));
+        }
+        if !is_final {
+            self.fragments = Some((opcode, data));
+            return Ok(None);
+        }
+        Ok(Some(Frame::message(ws_inflate(&data, self.max_frame)?, opcode, true)))
+    }
+
+    fn clear_timeout(&mut self) {
+        if let Some(t) = self.open_timeout.take() {
+            let _ = self.out.cancel(t);
+        }
+        if let Some(t) = self.ping_timeout.take() {
+            let _ = self.out.cancel(t);
+        }
+    }
+
+    /// the ping carry the send time, the pong echo it back to calc the rtt
+    fn on_ping(&mut self) {
+        self.ping_timeout = None;
+        if self.ping_wait {
+            self.ping_miss += 1;
+            if self.ping_miss >= self.ping_max_miss {
+                let reason = format!("Websocket Ping Timeout: {} pongs missed", self.ping_miss);
+                let _ = self.out.close_with_reason(CloseCode::Away, reason.clone());
+                WebSocketMgr::instance().on_close(&self.unique, &self.out, reason);
+                return;
+            }
+        }
+        self.ping_wait = true;
+        let _ = self.out.ping(TimeUtils::get_time_ms().to_string().into_bytes());
+        let _ = self.out.timeout(self.ping_interval, PING_EVENT);
+    }
 }
 
 impl Handler for WebsocketServer {
 
+    fn on_request(&mut self, req: &Request) -> Result<Response> {
+        if let Some(hook) = WebSocketMgr::instance().get_handshake_hook() {
+            match hook(&WebsocketRequest::new(req, self.port)) {
+                WebsocketAccept::Accept(meta) => self.meta = meta,
+                WebsocketAccept::Reject(status, reason) => {
+                    let info = format!("websocket handshake {} reject {} {}", req.resource(), status, reason);
+                    trace!("{}", info);
+                    LogUtils::instance().append(log_utils::LOG_INFO, &info);
+                    return Ok(Response::new(status, reason.clone(), reason.into_bytes()));
+                }
+            }
+        }
+        let mut res = Response::from_request(req)?;
+        let offered = req.header("sec-websocket-extensions")
+            .map(|e| WsUpgrade::accept_deflate(&String::from_utf8_lossy(e)))
+            .unwrap_or(false);
+        if self.deflate && offered {
+            self.compress = true;
+            res.add_extension(WS_DEFLATE_EXTENSION);
+        }
+        Ok(res)
+    }
+
+    fn upgrade_ssl_server(&mut self, stream: TcpStream) -> Result<SslStream<TcpStream>> {
+        let acceptor = self.ssl.as_ref().ok_or_else(|| Error::new(ErrorKind::Internal, "websocket listen without tls"))?;
+        acceptor.accept(stream).map_err(Error::from)
+    }
+
     fn on_open(&mut self, shake: Handshake) -> Result<()> {
         let mut addr = "unkown_ip".to_string();
         if let Some(ip_addr) = shake.remote_addr()? {
@@ -100,21 +605,74 @@ impl Handler for WebsocketServer {
         let mut event = SocketEvent::new(self.unique.clone(), addr.to_string(), self.port);
         event.set_websocket(true);
         event.set_mio(true);
+        event.set_meta(::std::mem::take(&mut self.meta));
 
         MioEventMgr::instance().new_socket_event_lua(event);
         WebSocketMgr::instance().on_open(&self.unique, self.out.clone());
+        if self.text_json {
+            WebSocketMgr::instance().set_text_json(&self.unique);
+        }
+        if self.ping_interval > 0 {
+            self.out.timeout(self.ping_interval, PING_EVENT)?;
+        }
         Ok(())
     }
 
+    fn on_frame(&mut self, frame: Frame) -> Result<Option<Frame>> {
+        let frame = match self.inflate_frame(frame) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return Ok(None),
+            Err(reason) => return Err(Error::new(ErrorKind::Protocol, reason)),
+        };
+        if frame.opcode() == OpCode::Pong {
+            let sent = String::from_utf8_lossy(frame.payload()).parse::<u64>().ok();
+            if let Some(sent) = sent {
+                let rtt = TimeUtils::get_time_ms().saturating_sub(sent);
+                MioEventMgr::instance().record_socket_rtt(&self.unique, rtt);
+            }
+            self.ping_wait = false;
+            self.ping_miss = 0;
+        }
+        if frame.has_rsv1() || frame.has_rsv2() || frame.has_rsv3() {
+            return Err(Error::new(ErrorKind::Protocol, "Encountered frame with reserved bits set."));
+        }
+        Ok(Some(frame))
+    }
+
+    fn on_send_frame(&mut self, mut frame: Frame) -> Result<Option<Frame>> {
+        if self.compress && !frame.is_control() {
+            let payload = ws_deflate(frame.payload());
+            *frame.payload_mut() = payload;
+            frame.set_rsv1(true);
+        }
+        Ok(Some(frame))
+    }
+
     fn on_message(&mut self, msg: Message) -> Result<()> {
-        let net_msg = match msg {
-            Message::Text(_text) => {
-                // WebSocketMgr::instance().on_close(&self.unique, &self.out, "未受支持的TEXT格式".to_string());
-                LuaEngine::instance().apply_lost_connect(&self.unique, "未受支持的TEXT格式".to_string());
-                return Ok(());
+        let mut net_msg = match msg {
+            Message::Text(text) => {
+                if !self.text_json {
+                    // WebSocketMgr::instance().on_close(&self.unique, &self.out, "未受支持的TEXT格式".to_string());
+                    LuaEngine::instance().apply_lost_connect(&self.unique, "未受支持的TEXT格式".to_string());
+                    return Ok(());
+                }
+                if text.len() > ProtoJson::MAX_TEXT_LEN {
+                    let reason = "Websocket Text Too Big".to_string();
+                    let _ = self.out.close_with_reason(CloseCode::Size, reason.clone());
+                    WebSocketMgr::instance().on_close(&self.unique, &self.out, reason);
+                    return Ok(());
+                }
+                let net_msg = ProtoJson::new_by_text(&text).ok();
+                MioEventMgr::instance().record_socket_recv(&self.unique, text.len(), net_msg.is_some());
+                unwrap_or!(net_msg, {
+                    LuaEngine::instance().apply_lost_connect(&self.unique, "解析JSON文本失败".to_string());
+                    return Ok(())
+                })
             },
             Message::Binary(data) => {
-                unwrap_or!(NetMsg::new_by_data(&data[..]).ok(), {
+                let net_msg = NetMsg::new_by_data(&data[..]).ok();
+                MioEventMgr::instance().record_socket_recv(&self.unique, data.len(), net_msg.is_some());
+                unwrap_or!(net_msg, {
                     // WebSocketMgr::instance().on_close(&self.unique, &self.out, "解析二进制协议失败".to_string());
                     LuaEngine::instance().apply_lost_connect(&self.unique, "解析二进制协议失败".to_string());
                     return Ok(())
@@ -122,37 +680,70 @@ impl Handler for WebsocketServer {
             },
         };
 
+        let invalid = match MioEventMgr::instance().check_handshake(&self.unique, &mut net_msg) {
+            HandshakeStep::Dispatch => ProtocolMgr::instance().validate_message(&mut net_msg).err(),
+            HandshakeStep::Handled => return Ok(()),
+            HandshakeStep::Invalid(reason) => Some(reason),
+            HandshakeStep::Reject(reason) => {
+                let reason = format!("Handshake Reject: {}", reason);
+                let _ = self.out.close_with_reason(CloseCode::Policy, reason.clone());
+                WebSocketMgr::instance().on_close(&self.unique, &self.out, reason);
+                return Ok(());
+            }
+        };
+        if let Some(reason) = invalid {
+            if ProtocolMgr::instance().is_kick_invalid() {
+                WebSocketMgr::instance().on_close(&self.unique, &self.out, format!("Message Invalid: {}", reason));
+            } else {
+                MioEventMgr::instance().reject_invalid_message(&self.unique, reason);
+            }
+            return Ok(());
+        }
+
+        MioEventMgr::instance().capture_message(CAPTURE_IN, &self.unique, &mut net_msg);
         LuaEngine::instance().apply_message(&self.unique, net_msg);
         Ok(())
     }
 
     fn on_close(&mut self, code: CloseCode, reason: &str) {
-        if let Some(t) = self.open_timeout.take() {
-            let _ = self.out.cancel(t);
-        }
-        self.open_timeout = None;
-
+        self.clear_timeout();
         WebSocketMgr::instance().on_close(&self.unique, &self.out, format!("WebSocket closing for ({:?}) {}", code, reason).to_string());
     }
 
     fn on_error(&mut self, err: Error) {
-        if let Some(t) = self.open_timeout.take() {
-            let _ = self.out.cancel(t);
+        self.clear_timeout();
+        // the connection not open is unknown to lua
+        if self.unique.is_empty() {
+            return;
         }
-        self.open_timeout = None;
-
         // Shutdown on any error
         WebSocketMgr::instance().on_close(&self.unique, &self.out, format!("Shutting down server for error: {}", err).to_string());
     }
 
-    fn on_timeout(&mut self, _event: Token) -> Result<()> {
-        trace!("wait connecting handshake!!!! on_timeout occur {}", self.unique);
+    fn on_timeout(&mut self, event: Token) -> Result<()> {
+        if event == PING_EVENT {
+            self.on_ping();
+            return Ok(());
+        }
+        // the upgrade finished, the handshake timeout is canceled
+        if !self.unique.is_empty() {
+            return Ok(());
+        }
+        trace!("wait connecting handshake!!!! on_timeout occur {}", self.port);
         self.open_timeout = None;
-        let _ = self.out.close(CloseCode::Normal);
-        Ok(())
+        // the connection not open can't be closed by the close frame, drop it by the io error
+        let err = io::Error::new(io::ErrorKind::TimedOut, "websocket handshake timeout");
+        Err(Error::new(ErrorKind::Io(err), "websocket handshake timeout"))
     }
 
-    fn on_new_timeout(&mut self, _event: Token, timeout: Timeout) -> Result<()> {
+    fn on_new_timeout(&mut self, event: Token, timeout: Timeout) -> Result<()> {
+        if event == PING_EVENT {
+            if let Some(t) = self.ping_timeout.take() {
+                self.out.cancel(t)?
+            }
+            self.ping_timeout = Some(timeout);
+            return Ok(());
+        }
         if let Some(t) = self.open_timeout.take() {
             self.out.cancel(t)?
         }
@@ -172,6 +763,11 @@ impl Handler for WebsocketServer {
 pub struct WebSocketMgr {
     port: u16,
     connect_ids: HashMap<String, Sender>,
+    /// the connections reply the json message in the text frame
+    text_ids: HashSet<String>,
+    hook: Option<Arc<WebsocketHook>>,
+    /// the func and the timeout of the lua hook, the mio websocket call it without waiting
+    lua_hook: Option<(String, u64)>,
     mutex: Arc<ReentrantMutex<u32>>,
 }
 
@@ -190,6 +786,9 @@ impl WebSocketMgr {
         WebSocketMgr { 
             port: 0, 
             connect_ids: HashMap::new(),
+            text_ids: HashSet::new(),
+            hook: None,
+            lua_hook: None,
             mutex: Arc::new(ReentrantMutex::new(0))
         }
     }
@@ -199,9 +798,51 @@ impl WebSocketMgr {
         self.connect_ids.insert(unique.clone(), sender);
     }
 
+    /// all the websocket listeners call the hook before accept the upgrade, None accept all
+    pub fn set_handshake_hook(&mut self, hook: Option<WebsocketHook>) {
+        let _data = self.mutex.lock().unwrap();
+        self.hook = hook.map(Arc::new);
+        self.lua_hook = None;
+    }
+
+    /// the hook call the lua func with the `WebsocketRequest` table, reject with 503 if lua not
+    /// return in the timeout, the websocket thread is blocked when waiting, the mio websocket
+    /// keep the upgrade pending and finish it when lua answered
+    pub fn set_lua_handshake_hook(&mut self, func: String, timeout: u64) {
+        let mutex = self.mutex.clone();
+        let _data = mutex.lock().unwrap();
+        let hook_func = func.clone();
+        self.set_handshake_hook(Some(Box::new(move |request: &WebsocketRequest| {
+            let arg = serde_json::to_value(request).unwrap_or(JsonValue::Null);
+            let receiver = LuaEngine::instance().apply_sync_call(hook_func.clone(), arg);
+            match receiver.recv_timeout(Duration::from_millis(timeout)) {
+                Ok(Some(value)) => WebsocketAccept::from_json(value),
+                Ok(None) => WebsocketAccept::Reject(500, "Handshake Hook Failed".to_string()),
+                Err(_) => WebsocketAccept::Reject(503, "Handshake Hook Timeout".to_string()),
+            }
+        })));
+        self.lua_hook = Some((func, timeout));
+    }
+
+    pub fn get_lua_handshake_hook(&self) -> Option<(String, u64)> {
+        let _data = self.mutex.lock().unwrap();
+        self.lua_hook.clone()
+    }
+
+    pub fn get_handshake_hook(&self) -> Option<Arc<WebsocketHook>> {
+        let _data = self.mutex.lock().unwrap();
+        self.hook.clone()
+    }
+
+    pub fn set_text_json(&mut self, unique: &str) {
+        let _data = self.mutex.lock().unwrap();
+        self.text_ids.insert(unique.to_string());
+    }
+
     pub fn on_close(&mut self, unique: &String, sender: &Sender, reason: String) {
         let _connect = {
             let _data = self.mutex.lock().unwrap();
+            self.text_ids.remove(unique);
             unwrap_or!(self.connect_ids.remove(unique), {
                 let _ = sender.close_with_reason(CloseCode::Abnormal, reason);
                 // let _ = sender.shutdown();
@@ -233,6 +874,13 @@ impl WebSocketMgr {
             return false;
         }
 
+        if net_msg.get_msg_type() == MSG_TYPE_JSON && self.text_ids.contains(unique) {
+            if let Ok(text) = ProtoJson::to_text(net_msg) {
+                let _ = self.connect_ids[unique].send(Message::text(text));
+                return true;
+            }
+        }
+
         let sender = self.connect_ids.get_mut(unique).unwrap();
         net_msg.get_buffer().set_rpos(0);
         if is_local {
@@ -246,23 +894,33 @@ impl WebSocketMgr {
     pub fn start_listen(&mut self, url: String, port: u16) {
         let url = format!("{}:{}", url, port);
         self.port = port;
+        let config = WebsocketConfig::get(port);
+        let ssl = if config.is_tls() {
+            match config.build_acceptor() {
+                Ok(acceptor) => Some(Arc::new(acceptor)),
+                Err(err) => {
+                    let info = format!("websocket listen {} failed by {}", url, err);
+                    trace!("{}", info);
+                    LogUtils::instance().append(log_utils::LOG_ERROR, &info);
+                    return;
+                }
+            }
+        } else {
+            None
+        };
         let _ = thread::Builder::new().name("webscoket".to_owned()).spawn(move || {
             loop {
-                let _ = Builder::new().with_settings(Settings {
-                    max_connections: 10_000,
-                    in_buffer_capacity: 2048000,
-                    out_buffer_capacity: 2048000,
+                let mut builder = Builder::new();
+                builder.with_settings(Settings {
+                    max_connections: config.get_max_connections(),
+                    in_buffer_capacity: config.get_in_buffer(),
+                    out_buffer_capacity: config.get_out_buffer(),
+                    max_fragment_size: config.get_max_frame(),
+                    encrypt_server: ssl.is_some(),
                     ..Settings::default()
-                }).build(|out : Sender| {
-                    let server = WebsocketServer {
-                        out: out,
-                        port: port,
-                        unique: String::new(),
-                        open_timeout: None,
-                    };
-                    // let token = server.out.token();
-                    // let _ = server.out.timeout(15_000, token).ok();
-                    server
+                });
+                let _ = builder.build(|out: Sender| {
+                    WebsocketServer::new(out, port, &config, ssl.clone())
                 }).unwrap().listen(&*url);
                 let websocket = &format!("websocket close exit may webscoket fd is closed!!!!")[..];
                 trace!("{:?}", websocket);
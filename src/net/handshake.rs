@@ -0,0 +1,144 @@
+use serde::{Serialize, Deserialize};
+use serde_json;
+use tunm_proto::{self, Value, decode_str_raw};
+use {NetMsg, NetResult};
+
+/// the msg_type reserved for the handshake frame, it is never dispatched to lua
+pub const MSG_TYPE_HANDSHAKE: u8 = 255;
+
+pub const HANDSHAKE_ACCEPT: &str = "accept";
+pub const HANDSHAKE_DOWNGRADE: &str = "downgrade";
+pub const HANDSHAKE_REJECT: &str = "reject";
+
+/// the handshake settings in the config, such as
+/// `handshake: {version: 3, min_version: 2, caps: [compress], required: false}`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HandshakeConfig {
+    /// the protocol version of the server
+    pub version: u32,
+    /// the client older than it is rejected, default the version
+    pub min_version: Option<u32>,
+    /// the capabilities the server supported, such as compress, encrypt
+    pub caps: Option<Vec<String>>,
+    /// the msg_types the client can use, default all the registered protocols
+    pub msg_types: Option<Vec<u8>>,
+    /// the first frame must be the handshake, default the old client without handshake is accepted
+    pub required: Option<bool>,
+}
+
+/// the body of the handshake frame sent by the client, the json such as
+/// `{"version": 3, "caps": ["compress"], "msg_types": [0, 5]}`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HandshakeRequest {
+    pub version: u32,
+    #[serde(default)]
+    pub caps: Vec<String>,
+    #[serde(default)]
+    pub msg_types: Vec<u8>,
+}
+
+/// the negotiated result, replied to the client as the json body of the handshake frame
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HandshakeInfo {
+    /// accept, downgrade or reject
+    pub result: String,
+    pub version: u32,
+    pub caps: Vec<String>,
+    pub msg_types: Vec<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// what the network thread do with the incoming frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeStep {
+    /// dispatch the message to lua
+    Dispatch,
+    /// the frame consumed by the handshake or dropped
+    Handled,
+    /// the message not allowed by the negotiated result
+    Invalid(String),
+    /// close the connection after the reply sent
+    Reject(String),
+}
+
+impl HandshakeInfo {
+    pub fn reject(version: u32, reason: String) -> HandshakeInfo {
+        HandshakeInfo {
+            result: HANDSHAKE_REJECT.to_string(),
+            version,
+            reason: Some(reason),
+            ..HandshakeInfo::default()
+        }
+    }
+
+    pub fn is_reject(&self) -> bool {
+        self.result == HANDSHAKE_REJECT
+    }
+
+    /// the reply frame, the same format as the request
+    pub fn to_net_msg(&self) -> NetResult<NetMsg> {
+        let data = serde_json::to_vec(self).unwrap_or_default();
+        NetMsg::new_by_detail(MSG_TYPE_HANDSHAKE, "handshake".to_string(), &data)
+    }
+}
+
+impl HandshakeRequest {
+    pub fn to_net_msg(&self) -> NetResult<NetMsg> {
+        let data = serde_json::to_vec(self).unwrap_or_default();
+        NetMsg::new_by_detail(MSG_TYPE_HANDSHAKE, "handshake".to_string(), &data)
+    }
+
+    /// the frame is `NetMsg::new_by_detail(MSG_TYPE_HANDSHAKE, "handshake", json)`
+    pub fn parse(net_msg: &mut NetMsg) -> Result<HandshakeRequest, String> {
+        net_msg.set_read_data();
+        let ret = decode_str_raw(net_msg.get_buffer(), tunm_proto::TYPE_STR)
+            .and_then(|_| decode_str_raw(net_msg.get_buffer(), tunm_proto::TYPE_RAW));
+        net_msg.set_read_data();
+        let data = match ret {
+            Ok(Value::Raw(data)) => data,
+            Ok(_) => vec![],
+            Err(err) => return Err(format!("decode handshake failed {:?}", err)),
+        };
+        serde_json::from_slice(&data).map_err(|err| format!("parse handshake failed {}", err))
+    }
+}
+
+impl HandshakeConfig {
+    pub fn is_required(&self) -> bool {
+        self.required.unwrap_or(false)
+    }
+
+    /// negotiate with the client, the version is the lower one, the caps and the msg_types are
+    /// the common ones, the result is downgrade if the client can't use all it declared
+    pub fn negotiate(&self, request: &HandshakeRequest, server_types: &[u8]) -> HandshakeInfo {
+        let min_version = self.min_version.unwrap_or(self.version);
+        if request.version < min_version {
+            return HandshakeInfo::reject(self.version,
+                format!("version {} < min version {}", request.version, min_version));
+        }
+        let server_types = self.msg_types.as_ref().map(|t| &t[..]).unwrap_or(server_types);
+        let msg_types: Vec<u8> = if request.msg_types.is_empty() {
+            server_types.to_vec()
+        } else {
+            request.msg_types.iter().filter(|t| server_types.contains(t)).cloned().collect()
+        };
+        if msg_types.is_empty() {
+            return HandshakeInfo::reject(self.version,
+                format!("no common msg_type in {:?}", request.msg_types));
+        }
+        let server_caps = self.caps.as_ref().map(|c| &c[..]).unwrap_or(&[]);
+        let caps: Vec<String> = request.caps.iter().filter(|c| server_caps.contains(c)).cloned().collect();
+
+        let version = ::std::cmp::min(request.version, self.version);
+        let is_full = version == request.version && caps.len() == request.caps.len()
+            && (request.msg_types.is_empty() || msg_types.len() == request.msg_types.len());
+        HandshakeInfo {
+            result: if is_full { HANDSHAKE_ACCEPT } else { HANDSHAKE_DOWNGRADE }.to_string(),
+            version,
+            caps,
+            msg_types,
+            reason: None,
+        }
+    }
+}
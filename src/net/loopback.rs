@@ -0,0 +1,348 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tunm_proto::Buffer;
+
+use {NetMsg, MioEventMgr};
+
+static LOOPBACK_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// the state shared by the engine stream and the test peer, the time is a virtual clock
+/// only moved by the peer, so the delivery is deterministic
+#[derive(Default)]
+struct LoopbackState {
+    now: u64,
+    seq: u64,
+    latency: u64,
+    /// the frames inject by peer, order by (deliver time, inject seq)
+    pending: BTreeMap<(u64, u64), Vec<u8>>,
+    /// the bytes already delivered but not read by engine
+    ready: Vec<u8>,
+    /// the bytes the engine write to the peer
+    sent: Vec<u8>,
+    write_error: Option<io::ErrorKind>,
+    peer_closed: bool,
+    engine_closed: bool,
+    engine_shutdown: bool,
+}
+
+impl LoopbackState {
+    fn deliver(&mut self) {
+        let now = self.now;
+        let due: Vec<(u64, u64)> = self.pending.range(..(now + 1, 0)).map(|(k, _)| *k).collect();
+        for key in due {
+            let data = self.pending.remove(&key).unwrap();
+            self.ready.extend_from_slice(&data);
+        }
+    }
+}
+
+/// the engine side of the in memory connection, used by SocketEvent as the client stream
+pub struct LoopbackStream {
+    state: Arc<Mutex<LoopbackState>>,
+}
+
+/// the test side of the in memory connection, inject frames to engine and check what engine sent
+#[derive(Clone)]
+pub struct LoopbackPeer {
+    unique: String,
+    state: Arc<Mutex<LoopbackState>>,
+}
+
+impl LoopbackStream {
+    pub fn pair() -> (LoopbackStream, LoopbackPeer) {
+        let state = Arc::new(Mutex::new(LoopbackState::default()));
+        let unique = format!("LB:{}", LOOPBACK_ID.fetch_add(1, Ordering::Relaxed));
+        (LoopbackStream { state: state.clone() }, LoopbackPeer { unique, state })
+    }
+
+    /// the engine shutdown the write side
+    pub fn shutdown(&mut self) {
+        self.state.lock().unwrap().engine_shutdown = true;
+    }
+
+    /// has the data can be read now, or the peer closed
+    pub fn is_readable(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.deliver();
+        !state.ready.is_empty() || state.peer_closed
+    }
+}
+
+impl Read for LoopbackStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        state.deliver();
+        if state.ready.is_empty() {
+            if state.peer_closed {
+                return Ok(0);
+            }
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let size = ::std::cmp::min(buf.len(), state.ready.len());
+        buf[..size].copy_from_slice(&state.ready[..size]);
+        state.ready.drain(..size);
+        Ok(size)
+    }
+}
+
+impl Write for LoopbackStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(kind) = state.write_error {
+            return Err(kind.into());
+        }
+        if state.peer_closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        state.sent.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LoopbackStream {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.engine_closed = true;
+        }
+    }
+}
+
+impl LoopbackPeer {
+    /// the unique of the engine side SocketEvent
+    pub fn get_unique(&self) -> &String {
+        &self.unique
+    }
+
+    /// the default delay in ms of the inject data
+    pub fn set_latency(&self, latency: u64) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    pub fn inject(&self, data: &[u8]) {
+        let latency = self.state.lock().unwrap().latency;
+        self.inject_after(data, latency);
+    }
+
+    /// the data will be delivered after delay ms, the data with less delay inject later
+    /// will be delivered first, so the order can be controlled
+    pub fn inject_after(&self, data: &[u8], delay: u64) {
+        let mut state = self.state.lock().unwrap();
+        let key = (state.now + delay, state.seq);
+        state.seq += 1;
+        state.pending.insert(key, data.to_vec());
+    }
+
+    pub fn inject_msg(&self, net_msg: &mut NetMsg) {
+        let wpos = net_msg.get_wpos();
+        self.inject(&net_msg.get_buffer().get_data()[..wpos]);
+    }
+
+    /// move the virtual clock, the data due will be readable by the engine
+    pub fn advance(&self, ms: u64) {
+        self.state.lock().unwrap().now += ms;
+    }
+
+    /// the number of frames not delivered yet
+    pub fn pending_len(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+
+    /// take all the bytes the engine sent
+    pub fn take_sent(&self) -> Vec<u8> {
+        let mut state = self.state.lock().unwrap();
+        ::std::mem::take(&mut state.sent)
+    }
+
+    /// take all the whole NetMsg the engine sent, the incomplete tail is kept
+    pub fn take_sent_msgs(&self) -> Vec<NetMsg> {
+        let mut state = self.state.lock().unwrap();
+        let mut buffer = Buffer::new();
+        let _ = buffer.write(&state.sent);
+        let mut msgs = vec![];
+        while let Some(data) = MioEventMgr::get_next_message(&mut buffer) {
+            if let Ok(net_msg) = NetMsg::new_by_data(&data) {
+                msgs.push(net_msg);
+            }
+        }
+        state.sent = buffer.get_write_data().to_vec();
+        msgs
+    }
+
+    /// make the engine write fail with the error kind, None to recover
+    pub fn set_write_error(&self, kind: Option<io::ErrorKind>) {
+        self.state.lock().unwrap().write_error = kind;
+    }
+
+    /// close the peer side, the engine will read eof after all data read
+    pub fn close(&self) {
+        self.state.lock().unwrap().peer_closed = true;
+    }
+
+    /// is the engine side shutdown the write, the peer will read nothing more
+    pub fn is_shutdown(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.engine_shutdown || state.engine_closed
+    }
+
+    /// is the engine side closed the connection
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().engine_closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use tunm_proto::{self, Buffer, Value};
+    use {NetMsg, MioEventMgr, LuaEngine, WsFrame, register_custom_func};
+    use net::{WS_OP_BINARY, WS_OP_CLOSE};
+
+    const SCRIPT: &str = r#"
+        LB_EVENTS = {}
+        function cmd_new_connection(cookie, unique, ip, port, ws)
+            table.insert(LB_EVENTS, "new:" .. unique)
+        end
+        function global_dispatch_command(unique, name, msg)
+            table.insert(LB_EVENTS, "msg:" .. unique .. ":" .. name)
+        end
+        function cmd_lost_connection(unique, reason, kind)
+            table.insert(LB_EVENTS, "lost:" .. unique)
+        end
+    "#;
+
+    fn take_events() -> String {
+        let lua = LuaEngine::instance().get_lua();
+        let events: Option<String> = lua.exec_string("local s = table.concat(LB_EVENTS, ',') LB_EVENTS = {} return s");
+        events.unwrap_or_default()
+    }
+
+    fn new_td_msg(name: &str, args: Vec<Value>) -> NetMsg {
+        let mut net_msg = NetMsg::new();
+        tunm_proto::encode_proto(net_msg.get_buffer(), &name.to_string(), args).unwrap();
+        net_msg.end_msg();
+        net_msg
+    }
+
+    /// the client frame must be masked, the payload < 126
+    fn ws_client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1u8, 2, 3, 4];
+        let mut data = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        data.extend_from_slice(&mask);
+        data.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        data
+    }
+
+    #[test]
+    fn message_round_trip_and_close() {
+        register_custom_func(LuaEngine::instance().get_lua());
+        let _: Option<()> = LuaEngine::instance().get_lua().exec_string(SCRIPT);
+
+        let peer = MioEventMgr::instance().new_loopback_client(8000);
+        let unique = peer.get_unique().clone();
+        LuaEngine::instance().execute_lua();
+        assert_eq!(take_events(), format!("new:{}", unique));
+
+        // the message delivered after the latency
+        peer.set_latency(5);
+        peer.inject_msg(&mut new_td_msg("cmd_ping", vec![Value::from(7u32)]));
+        assert_eq!(MioEventMgr::instance().run_loopback(), 0);
+        peer.advance(5);
+        assert_eq!(MioEventMgr::instance().run_loopback(), 1);
+        assert_eq!(peer.pending_len(), 0);
+        LuaEngine::instance().execute_lua();
+        assert_eq!(take_events(), format!("msg:{}:cmd_ping", unique));
+
+        let mut reply = new_td_msg("cmd_pong", vec![Value::from(7u32), Value::from("ok".to_string())]);
+        assert!(MioEventMgr::instance().send_netmsg(&unique, &mut reply));
+        let mut msgs = peer.take_sent_msgs();
+        assert_eq!(msgs.len(), 1);
+        msgs[0].set_read_data();
+        msgs[0].read_head().unwrap();
+        let (name, args) = tunm_proto::decode_proto(msgs[0].get_buffer()).unwrap();
+        assert_eq!(name, "cmd_pong");
+        assert_eq!(args, vec![Value::from(7u32), Value::from("ok".to_string())]);
+
+        peer.close();
+        assert_eq!(MioEventMgr::instance().run_loopback(), 1);
+        assert!(peer.is_closed());
+        assert!(MioEventMgr::instance().get_socket_event(&unique).is_none());
+        LuaEngine::instance().execute_lua();
+        assert_eq!(take_events(), format!("lost:{}", unique));
+        assert!(!MioEventMgr::instance().send_netmsg(&unique, &mut new_td_msg("cmd_pong", vec![])));
+
+        // the kicked connection is half closed, the message after the kick is dropped and
+        // kick again not change it, lua know the lost after the peer close
+        let peer = MioEventMgr::instance().new_loopback_client(8000);
+        let unique = peer.get_unique().clone();
+        LuaEngine::instance().execute_lua();
+        assert_eq!(take_events(), format!("new:{}", unique));
+        MioEventMgr::instance().add_kick_event(&unique, "kick test".to_string());
+        assert!(peer.is_shutdown());
+        peer.inject_msg(&mut new_td_msg("cmd_ping", vec![Value::from(8u32)]));
+        assert_eq!(MioEventMgr::instance().run_loopback(), 1);
+        MioEventMgr::instance().add_kick_event(&unique, "kick again".to_string());
+        LuaEngine::instance().execute_lua();
+        assert_eq!(take_events(), "");
+        let socket_event = MioEventMgr::instance().get_socket_event(&unique).unwrap();
+        assert_eq!(socket_event.get_in_buffer().data_len(), 0);
+        assert_eq!(socket_event.get_close_reason(), "kick test");
+        peer.close();
+        assert_eq!(MioEventMgr::instance().run_loopback(), 1);
+        LuaEngine::instance().execute_lua();
+        assert_eq!(take_events(), format!("lost:{}", unique));
+
+        // the websocket upgrade, one frame each side, then the client close, the frame
+        // after the close is dropped
+        let peer = MioEventMgr::instance().new_loopback_websocket(8001);
+        let unique = peer.get_unique().clone();
+        peer.inject(b"GET /game HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n");
+        assert_eq!(MioEventMgr::instance().run_loopback(), 1);
+        let response = String::from_utf8(peer.take_sent()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 "));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        LuaEngine::instance().execute_lua();
+        assert_eq!(take_events(), format!("new:{}", unique));
+
+        let mut net_msg = new_td_msg("cmd_ping", vec![Value::from(9u32)]);
+        let wpos = net_msg.get_wpos();
+        peer.inject(&ws_client_frame(WS_OP_BINARY, &net_msg.get_buffer().get_data()[..wpos]));
+        assert_eq!(MioEventMgr::instance().run_loopback(), 1);
+        LuaEngine::instance().execute_lua();
+        assert_eq!(take_events(), format!("msg:{}:cmd_ping", unique));
+
+        assert!(MioEventMgr::instance().send_netmsg(&unique, &mut new_td_msg("cmd_pong", vec![Value::from(9u32)])));
+        let sent = peer.take_sent();
+        // the binary frame is the message without the head
+        assert_eq!(sent[..2], [0x80 | WS_OP_BINARY, sent.len() as u8 - 2]);
+        let mut body = Buffer::new();
+        let _ = body.write(&sent[2..]);
+        let (name, args) = tunm_proto::decode_proto(&mut body).unwrap();
+        assert_eq!((name.as_str(), args), ("cmd_pong", vec![Value::from(9u32)]));
+
+        let mut data = ws_client_frame(WS_OP_CLOSE, &1000u16.to_be_bytes());
+        data.extend(ws_client_frame(WS_OP_BINARY, &net_msg.get_buffer().get_data()[..wpos]));
+        peer.inject(&data);
+        assert_eq!(MioEventMgr::instance().run_loopback(), 1);
+        assert!(peer.is_shutdown());
+        assert_eq!(peer.take_sent(), WsFrame::new_close(1000, "").encode());
+        peer.inject(&ws_client_frame(WS_OP_CLOSE, &1000u16.to_be_bytes()));
+        assert_eq!(MioEventMgr::instance().run_loopback(), 1);
+        assert!(peer.take_sent().is_empty());
+        let socket_event = MioEventMgr::instance().get_socket_event(&unique).unwrap();
+        assert_eq!(socket_event.get_in_buffer().data_len(), 0);
+        assert!(socket_event.get_close_reason().contains("(1000)"));
+        LuaEngine::instance().execute_lua();
+        assert_eq!(take_events(), "");
+        peer.close();
+        assert_eq!(MioEventMgr::instance().run_loopback(), 1);
+        LuaEngine::instance().execute_lua();
+        assert_eq!(take_events(), format!("lost:{}", unique));
+    }
+}
@@ -1,12 +1,24 @@
 mod net_msg;
 mod socket_event;
+mod socket_stats;
+mod loopback;
+mod handshake;
+mod websocket;
 
 pub use self::net_msg::NetMsg;
 pub use self::net_msg::MSG_TYPE_TD;
 pub use self::net_msg::MSG_TYPE_JSON;
 pub use self::net_msg::MSG_TYPE_BIN;
 pub use self::net_msg::MSG_TYPE_TEXT;
+pub use self::net_msg::MSG_TYPE_PROTOBUF;
+pub use self::net_msg::MSG_TYPE_MSGPACK;
 pub use self::socket_event::{SocketEvent, ReadCb, AcceptCb, WriteCb, EndCb};
+pub use self::socket_stats::{SocketStats, STATS_FIELDS};
+pub use self::loopback::{LoopbackStream, LoopbackPeer};
+pub use self::handshake::{HandshakeConfig, HandshakeRequest, HandshakeInfo, HandshakeStep, MSG_TYPE_HANDSHAKE,
+    HANDSHAKE_ACCEPT, HANDSHAKE_DOWNGRADE, HANDSHAKE_REJECT};
+pub use self::websocket::{WsFrame, WsUpgrade, WsSession, ws_deflate, ws_inflate, WS_DEFLATE_EXTENSION, WS_MAX_HANDSHAKE, WS_OP_CONTINUE, WS_OP_TEXT,
+    WS_OP_BINARY, WS_OP_CLOSE, WS_OP_PING, WS_OP_PONG};
 
 
 #[cfg(unix)]
@@ -8,6 +8,8 @@ pub const MSG_TYPE_TD: u8 = 0;
 pub const MSG_TYPE_JSON: u8 = 1;
 pub const MSG_TYPE_BIN: u8 = 2;
 pub const MSG_TYPE_TEXT: u8 = 3;
+pub const MSG_TYPE_PROTOBUF: u8 = 4;
+pub const MSG_TYPE_MSGPACK: u8 = 5;
 
 static HEAD_FILL_UP: [u8; 26] = [0; 26];
 
@@ -23,6 +25,8 @@ pub struct NetMsg {
     to_svr_id: u32,
     real_fd: u32,
     pack_name: String,
+    /// the args decoded by the validation, so the td message is decoded once
+    args: Option<Vec<Value>>,
 }
 
 impl NetMsg {
@@ -41,15 +45,22 @@ impl NetMsg {
             real_fd: 0u32,
             buffer: buffer,
             pack_name: String::new(),
+            args: None,
         }
     }
 
-    pub fn new_by_detail(msg_type: u8, msg_name: String, data: &[u8]) -> NetMsg {
+    /// the body after the head is the varint length and the name, then the varint length and
+    /// the data, the data length was the fixed 2 bytes little endian u16 before the protobuf
+    /// message type, so the client packed the body by hand must write the varint.
+    /// the length is at most u16, so the longer name or data is rejected
+    pub fn new_by_detail(msg_type: u8, msg_name: String, data: &[u8]) -> NetResult<NetMsg> {
+        if msg_name.len() > 0xFFFF || data.len() > 0xFFFF {
+            return Err(make_extension_error("detail data len too large", Some(&format!("name = {}, len = {}", msg_name, data.len()))));
+        }
         let mut buffer = Buffer::new();
         let _ = buffer.write(&HEAD_FILL_UP);
         let _ = encode_str_raw(&mut buffer, &Value::Str(msg_name.clone()));
-        let _ = encode_number(&mut buffer, &Value::U16(data.len() as u16));
-        let _ = buffer.write(data);
+        let _ = encode_str_raw(&mut buffer, &Value::Raw(data.to_vec()));
         let mut net_msg = NetMsg {
             length: buffer.len() as u32,
             cookie: 0u32,
@@ -62,9 +73,10 @@ impl NetMsg {
             real_fd: 0u32,
             buffer: buffer,
             pack_name: msg_name,
+            args: None,
         };
         net_msg.end_msg();
-        net_msg
+        Ok(net_msg)
     }
 
 
@@ -89,6 +101,7 @@ impl NetMsg {
             real_fd: 0u32,
             buffer: buffer,
             pack_name: pack_name,
+            args: None,
         };
         net_msg.end_msg();
         Ok(net_msg)
@@ -128,6 +141,7 @@ impl NetMsg {
             real_fd: real_fd,
             buffer: buffer,
             pack_name: pack_name,
+            args: None,
         })
     }
 
@@ -151,7 +165,9 @@ impl NetMsg {
         self.buffer.set_wpos(wpos);
     }
 
+    /// the buffer may be changed, so the decoded args are dropped
     pub fn get_buffer(&mut self) -> &mut Buffer {
+        self.args = None;
         &mut self.buffer
     }
 
@@ -163,7 +179,7 @@ impl NetMsg {
         self.msg_type = decode_number(&mut self.buffer, tunm_proto::TYPE_U8)?.into();
         self.msg_flag = decode_number(&mut self.buffer, tunm_proto::TYPE_U8)?.into();
         self.from_svr_type = decode_number(&mut self.buffer, tunm_proto::TYPE_U16)?.into();
-        self.real_fd = decode_number(&mut self.buffer, tunm_proto::TYPE_U32)?.into();
+        self.from_svr_id = decode_number(&mut self.buffer, tunm_proto::TYPE_U32)?.into();
         self.to_svr_type = decode_number(&mut self.buffer, tunm_proto::TYPE_U16)?.into();
         self.to_svr_id = decode_number(&mut self.buffer, tunm_proto::TYPE_U32)?.into();
         self.real_fd = decode_number(&mut self.buffer, tunm_proto::TYPE_U32)?.into();
@@ -279,6 +295,14 @@ impl NetMsg {
     pub fn get_pack_name(&self) -> &String {
         &self.pack_name
     }
+
+    pub fn set_args(&mut self, args: Option<Vec<Value>>) {
+        self.args = args;
+    }
+
+    pub fn take_args(&mut self) -> Option<Vec<Value>> {
+        self.args.take()
+    }
 }
 
 
@@ -290,6 +314,7 @@ impl Read for NetMsg {
 
 impl Write for NetMsg {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.args = None;
         self.buffer.write(buf)
     }
     
@@ -303,3 +328,33 @@ impl Write for NetMsg {
 //         println!("drop net_msg!!!!!!!!!!!!!!!!!");
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use tunm_proto;
+    use super::*;
+
+    #[test]
+    fn detail_body_is_varint_len_and_bytes() {
+        let data = vec![7u8; 300];
+        let mut net_msg = NetMsg::new_by_detail(MSG_TYPE_JSON, "ab".to_string(), &data).unwrap();
+        let wpos = net_msg.get_wpos();
+        let body = net_msg.get_buffer().get_data()[26..wpos].to_vec();
+        // the zigzag varint of 2 is [4] and of 300 is [216, 4]
+        let expected = [&[4u8, b'a', b'b', 216, 4][..], &data].concat();
+        assert_eq!(body, expected);
+
+        net_msg.set_read_data();
+        net_msg.read_head().unwrap();
+        let name: String = decode_str_raw(net_msg.get_buffer(), tunm_proto::TYPE_STR).unwrap().into();
+        let raw: Vec<u8> = decode_str_raw(net_msg.get_buffer(), tunm_proto::TYPE_RAW).unwrap().into();
+        assert_eq!((name, raw), ("ab".to_string(), data));
+    }
+
+    #[test]
+    fn detail_longer_than_u16_rejected() {
+        assert!(NetMsg::new_by_detail(MSG_TYPE_BIN, "a".to_string(), &vec![0u8; 0xFFFF]).is_ok());
+        assert!(NetMsg::new_by_detail(MSG_TYPE_BIN, "a".to_string(), &vec![0u8; 0x10000]).is_err());
+        assert!(NetMsg::new_by_detail(MSG_TYPE_BIN, "a".repeat(0x10000), &[]).is_err());
+    }
+}
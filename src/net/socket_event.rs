@@ -1,9 +1,11 @@
 use tunm_proto::Buffer;
 use mio::{Token};
 use mio::net::{TcpListener, TcpStream};
-use crate::net::AsSocket;
+use crate::net::{AsSocket, SocketStats, LoopbackStream, HandshakeInfo, WsSession};
 
+use std::collections::HashMap;
 use std::io::{self, Read, Write};
+use std::net::Shutdown;
 use std::io::Result;
 
 pub type AcceptCb = fn(&mut SocketEvent) -> usize;
@@ -26,10 +28,24 @@ pub struct SocketEvent {
     mio: bool,
     server: Option<TcpListener>,
     client: Option<TcpStream>,
+    loopback: Option<LoopbackStream>,
     pub accept: Option<AcceptCb>,
     pub read: Option<ReadCb>,
     pub write: Option<WriteCb>,
     pub end: Option<EndCb>,
+    stats: SocketStats,
+    /// shutdown the write after the out buffer flushed
+    shutdown_pending: bool,
+    write_shutdown: bool,
+    close_reason: String,
+    close_kind: Option<io::ErrorKind>,
+    /// the first frame checked, the handshake is only allowed as the first frame
+    handshake_checked: bool,
+    handshake: Option<HandshakeInfo>,
+    /// attached by the websocket handshake hook, such as the auth token, the client version
+    meta: HashMap<String, String>,
+    /// the websocket framed by MioEventMgr, the websocket of WebSocketMgr is None
+    ws_session: Option<WsSession>,
 }
 
 impl SocketEvent {
@@ -48,10 +64,20 @@ impl SocketEvent {
             mio: false,
             server: None,
             client: None,
+            loopback: None,
             accept: None,
             read: None,
             write: None,
             end: None,
+            stats: SocketStats::new(),
+            shutdown_pending: false,
+            write_shutdown: false,
+            close_reason: "客户端关闭".to_string(),
+            close_kind: None,
+            handshake_checked: false,
+            handshake: None,
+            meta: HashMap::new(),
+            ws_session: None,
         }
     }
     
@@ -72,13 +98,30 @@ impl SocketEvent {
             mio: false,
             server: None,
             client: Some(client),
+            loopback: None,
             accept: None,
             read: None,
             write: None,
             end: None,
+            stats: SocketStats::new(),
+            shutdown_pending: false,
+            write_shutdown: false,
+            close_reason: "客户端关闭".to_string(),
+            close_kind: None,
+            handshake_checked: false,
+            handshake: None,
+            meta: HashMap::new(),
+            ws_session: None,
         }
     }
     
+    /// the in memory client, the unique is the LoopbackPeer's unique
+    pub fn new_loopback(unique: String, loopback: LoopbackStream, server_port: u16) -> SocketEvent {
+        let mut ev = SocketEvent::new(unique.clone(), unique, server_port);
+        ev.loopback = Some(loopback);
+        ev
+    }
+
     pub fn new_server(server: TcpListener, server_port: u16) -> SocketEvent {
         let token = Token(server.as_socket() as usize);
         SocketEvent {
@@ -95,10 +138,20 @@ impl SocketEvent {
             mio: false,
             server: Some(server),
             client: None,
+            loopback: None,
             accept: None,
             read: None,
             write: None,
             end: None,
+            stats: SocketStats::new(),
+            shutdown_pending: false,
+            write_shutdown: false,
+            close_reason: "客户端关闭".to_string(),
+            close_kind: None,
+            handshake_checked: false,
+            handshake: None,
+            meta: HashMap::new(),
+            ws_session: None,
         }
     }
 
@@ -207,19 +260,142 @@ impl SocketEvent {
         self.client.as_mut()
     }
     
+    pub fn is_loopback(&self) -> bool {
+        self.loopback.is_some()
+    }
+
+    pub fn get_loopback(&self) -> Option<&LoopbackStream> {
+        self.loopback.as_ref()
+    }
+
+    /// the tcp client or the loopback can read and write data
+    pub fn is_stream(&self) -> bool {
+        self.client.is_some() || self.loopback.is_some()
+    }
+
+    fn read_stream(&mut self) -> Result<usize> {
+        let buf = self.in_buffer.get_read_array(2048);
+        match self.client.as_mut() {
+            Some(client) => client.read(buf),
+            None => unwrap_or!(self.loopback.as_mut(), return Ok(0)).read(buf),
+        }
+    }
+
+    fn write_stream(&mut self) -> Result<usize> {
+        let data = self.out_buffer.get_write_data();
+        match self.client.as_mut() {
+            Some(client) => client.write(data),
+            None => unwrap_or!(self.loopback.as_mut(), return Ok(0)).write(data),
+        }
+    }
+
+    pub fn get_stats(&self) -> &SocketStats {
+        &self.stats
+    }
+
+    pub fn get_stats_mut(&mut self) -> &mut SocketStats {
+        &mut self.stats
+    }
+
+    /// the reason and the os error kind of the close, used by the end callback
+    pub fn set_close_info(&mut self, reason: String, kind: Option<io::ErrorKind>) {
+        self.close_reason = reason;
+        self.close_kind = kind;
+    }
+
+    pub fn get_close_reason(&self) -> &String {
+        &self.close_reason
+    }
+
+    pub fn get_close_kind(&self) -> Option<io::ErrorKind> {
+        self.close_kind
+    }
+
+    pub fn is_handshake_checked(&self) -> bool {
+        self.handshake_checked
+    }
+
+    pub fn set_handshake_checked(&mut self, handshake_checked: bool) {
+        self.handshake_checked = handshake_checked;
+    }
+
+    /// the negotiated result, None if the client not send the handshake
+    pub fn get_handshake(&self) -> Option<&HandshakeInfo> {
+        self.handshake.as_ref()
+    }
+
+    pub fn set_handshake(&mut self, handshake: Option<HandshakeInfo>) {
+        self.handshake = handshake;
+    }
+
+    pub fn get_meta(&self) -> &HashMap<String, String> {
+        &self.meta
+    }
+
+    pub fn set_meta(&mut self, meta: HashMap<String, String>) {
+        self.meta = meta;
+    }
+
+    pub fn get_ws_session(&self) -> Option<&WsSession> {
+        self.ws_session.as_ref()
+    }
+
+    pub fn get_ws_session_mut(&mut self) -> Option<&mut WsSession> {
+        self.ws_session.as_mut()
+    }
+
+    pub fn set_ws_session(&mut self, ws_session: Option<WsSession>) {
+        self.ws_session = ws_session;
+    }
+
+    pub fn is_shutdown_pending(&self) -> bool {
+        self.shutdown_pending
+    }
+
+    pub fn set_shutdown_pending(&mut self, shutdown_pending: bool) {
+        self.shutdown_pending = shutdown_pending;
+    }
+
+    pub fn is_write_shutdown(&self) -> bool {
+        self.write_shutdown
+    }
+
+    /// the shutdown_fd called, the data recv after it is dropped
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown_pending || self.write_shutdown
+    }
+
+    /// shutdown the write side, the peer will read eof, we still read until the peer close
+    pub fn shutdown_write(&mut self) -> Result<()> {
+        self.shutdown_pending = false;
+        self.write_shutdown = true;
+        match self.client.as_mut() {
+            Some(client) => client.shutdown(Shutdown::Write),
+            None => {
+                if let Some(loopback) = self.loopback.as_mut() {
+                    loopback.shutdown();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Ok(true) the peer closed gracefully, Ok(false) read all data can read now,
+    /// Err the connection reset or the data too big, the connection should be closed
     pub fn read_data(&mut self) -> Result<bool> {
         let mut bytes_read = 0;
         loop {
-            match self.client.as_mut().unwrap().read(self.in_buffer.get_read_array(2048)) {
+            match self.read_stream() {
                 Ok(0) => {
                     return Ok(true);
                 }
                 Ok(n) => {
                     bytes_read += n;
                     self.in_buffer.write_offset(n);
+                    self.stats.add_bytes_in(n);
                     if bytes_read > 655360 {
                         trace!("too big data");
-                        return Ok(true);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "too big data"));
                     }
 
                     //  else if bytes_read < 2048 {
@@ -231,15 +407,33 @@ impl SocketEvent {
                 Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
                 Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
                 // Other errors we'll consider fatal.
-                Err(_err) => return Ok(true),
+                Err(err) => return Err(err),
             }
         }
         Ok(false)
     }
 
+    /// write the out buffer until empty or would block, Ok(true) all data written,
+    /// Ok(false) the left data need wait writable, Err the connection should be closed
     pub fn write_data(&mut self) -> Result<bool> {
-        let size = self.client.as_mut().unwrap().write(self.out_buffer.get_write_data())?;
-        Ok(self.out_buffer.read_offset(size))
+        if self.write_shutdown {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "write after shutdown"));
+        }
+        loop {
+            if self.out_buffer.data_len() == 0 {
+                return Ok(true);
+            }
+            match self.write_stream() {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(size) => {
+                    self.stats.add_bytes_out(size);
+                    self.out_buffer.read_offset(size);
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
     }
 
     pub fn set_accept(&mut self, accept: Option<AcceptCb>) {
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use tunm_proto::Value;
+use TimeUtils;
+
+/// the field names can be used to sort the socket stats
+pub static STATS_FIELDS: [&str; 8] = ["bytes_in", "bytes_out", "msgs_in", "msgs_out",
+                                     "dispatch_errors", "connect_time", "last_active", "rtt"];
+
+/// traffic statistics of one connection
+#[derive(Debug, Clone, Default)]
+pub struct SocketStats {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub msgs_in: u64,
+    pub msgs_out: u64,
+    pub dispatch_errors: u64,
+    /// connect time in ms
+    pub connect_time: u64,
+    /// last read or write time in ms
+    pub last_active: u64,
+    /// the round trip time in ms of the last websocket ping
+    pub rtt: u64,
+}
+
+impl SocketStats {
+    pub fn new() -> SocketStats {
+        let now = TimeUtils::get_time_ms();
+        SocketStats {
+            connect_time: now,
+            last_active: now,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_bytes_in(&mut self, size: usize) {
+        self.bytes_in += size as u64;
+        self.last_active = TimeUtils::get_time_ms();
+    }
+
+    pub fn add_bytes_out(&mut self, size: usize) {
+        self.bytes_out += size as u64;
+        self.last_active = TimeUtils::get_time_ms();
+    }
+
+    pub fn add_msg_in(&mut self) {
+        self.msgs_in += 1;
+    }
+
+    pub fn add_msg_out(&mut self) {
+        self.msgs_out += 1;
+    }
+
+    pub fn add_dispatch_error(&mut self) {
+        self.dispatch_errors += 1;
+    }
+
+    pub fn set_rtt(&mut self, rtt: u64) {
+        self.rtt = rtt;
+    }
+
+    pub fn get_field(&self, field: &str) -> Option<u64> {
+        match field {
+            "bytes_in" => Some(self.bytes_in),
+            "bytes_out" => Some(self.bytes_out),
+            "msgs_in" => Some(self.msgs_in),
+            "msgs_out" => Some(self.msgs_out),
+            "dispatch_errors" => Some(self.dispatch_errors),
+            "connect_time" => Some(self.connect_time),
+            "last_active" => Some(self.last_active),
+            "rtt" => Some(self.rtt),
+            _ => None,
+        }
+    }
+
+    pub fn to_value_map(&self) -> HashMap<String, Value> {
+        let mut map = HashMap::new();
+        for field in STATS_FIELDS.iter() {
+            map.insert(field.to_string(), Value::U64(self.get_field(field).unwrap_or(0)));
+        }
+        map
+    }
+}
@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::io::Write;
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use flate2::write::DeflateEncoder;
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use httparse;
+use WebsocketConfig;
+
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// the tail removed after the sync flush of the permessage-deflate
+const DEFLATE_TAIL: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+/// the window not shared between the messages, so no context keep for each connection
+pub const WS_DEFLATE_EXTENSION: &str = "permessage-deflate; server_no_context_takeover; client_no_context_takeover";
+/// the upgrade request bigger than it is rejected
+pub const WS_MAX_HANDSHAKE: usize = 8192;
+
+pub const WS_OP_CONTINUE: u8 = 0;
+pub const WS_OP_TEXT: u8 = 1;
+pub const WS_OP_BINARY: u8 = 2;
+pub const WS_OP_CLOSE: u8 = 8;
+pub const WS_OP_PING: u8 = 9;
+pub const WS_OP_PONG: u8 = 10;
+
+/// one websocket frame, the payload of the client frame is unmasked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WsFrame {
+    pub fin: bool,
+    /// the message compressed by the permessage-deflate, only set in the first frame
+    pub rsv1: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+/// the http upgrade request of the websocket
+#[derive(Debug, Clone, Default)]
+pub struct WsUpgrade {
+    /// the path with the query, such as `/game?token=abc`
+    pub resource: String,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub key: String,
+    /// the client offer the permessage-deflate which can be accepted
+    pub deflate: bool,
+}
+
+/// the websocket state of the connection framed by MioEventMgr
+#[derive(Debug, Clone, Default)]
+pub struct WsSession {
+    /// the http upgrade finished, lua know the connection after it
+    pub upgraded: bool,
+    pub text_json: bool,
+    /// the permessage-deflate negotiated in the upgrade
+    pub deflate: bool,
+    pub max_frame: usize,
+    /// close the connection with 1009 if the data not framed is bigger than it
+    pub in_buffer: usize,
+    /// close the connection if the data waiting to be sent is bigger than it
+    pub out_buffer: usize,
+    pub ping_interval: u64,
+    pub ping_max_miss: u32,
+    /// the pongs missed in a row
+    pub ping_miss: u32,
+    /// the ping sent and the pong not received
+    pub ping_wait: bool,
+    /// the token of the ping timer, the old timer of the reused unique is ignored
+    pub ping_timer: u64,
+    /// the token of the handshake timer, close the connection not upgraded in time
+    pub handshake_timer: u64,
+    /// the token of the lua handshake hook call and its timeout timer
+    pub hook_timer: u64,
+    /// the upgrade waiting the answer of the lua handshake hook
+    pub pending_upgrade: Option<WsUpgrade>,
+    /// the opcode, the compressed flag and the payload of the fragmented message
+    pub fragments: Option<(u8, bool, Vec<u8>)>,
+    /// the close frame sent, no more frame can be sent
+    pub closing: bool,
+}
+
+impl WsFrame {
+    pub fn new(opcode: u8, payload: Vec<u8>) -> WsFrame {
+        WsFrame { fin: true, rsv1: false, opcode, payload }
+    }
+
+    /// the data frame compressed if the permessage-deflate negotiated
+    pub fn new_data(opcode: u8, payload: &[u8], deflate: bool) -> WsFrame {
+        if !deflate {
+            return WsFrame::new(opcode, payload.to_vec());
+        }
+        WsFrame { fin: true, rsv1: true, opcode, payload: ws_deflate(payload) }
+    }
+
+    /// Ok(None) the frame not complete, Ok(Some((frame, size))) the size of the data used,
+    /// Err((close code, reason)) the frame invalid or bigger than max_size, the connection should be closed
+    pub fn parse(data: &[u8], max_size: usize) -> Result<Option<(WsFrame, usize)>, (u16, String)> {
+        if data.len() < 2 {
+            return Ok(None);
+        }
+        let (first, second) = (data[0], data[1]);
+        if first & 0x30 != 0 {
+            return Err((1002, "reserved bits set".to_string()));
+        }
+        if second & 0x80 == 0 {
+            return Err((1002, "client frame not masked".to_string()));
+        }
+        let opcode = first & 0x0F;
+        let (len, mut pos) = match second & 0x7F {
+            126 => {
+                if data.len() < 4 {
+                    return Ok(None);
+                }
+                (u16::from_be_bytes([data[2], data[3]]) as u64, 4)
+            }
+            127 => {
+                if data.len() < 10 {
+                    return Ok(None);
+                }
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&data[2..10]);
+                (u64::from_be_bytes(bytes), 10)
+            }
+            len => (len as u64, 2),
+        };
+        if len > max_size as u64 {
+            return Err((1009, format!("frame size {} > {}", len, max_size)));
+        }
+        if opcode >= WS_OP_CLOSE && (len > 125 || first & 0xC0 != 0x80) {
+            return Err((1002, "control frame too big, fragmented or compressed".to_string()));
+        }
+        let len = len as usize;
+        if data.len() < pos + 4 + len {
+            return Ok(None);
+        }
+        let mask = [data[pos], data[pos + 1], data[pos + 2], data[pos + 3]];
+        pos += 4;
+        let payload = data[pos..pos + len].iter().enumerate().map(|(i, b)| b ^ mask[i % 4]).collect();
+        Ok(Some((WsFrame { fin: first & 0x80 != 0, rsv1: first & 0x40 != 0, opcode, payload }, pos + len)))
+    }
+
+    /// the server frame is not masked
+    pub fn encode(&self) -> Vec<u8> {
+        let len = self.payload.len();
+        let mut data = Vec::with_capacity(len + 10);
+        data.push(if self.fin { 0x80 } else { 0 } | if self.rsv1 { 0x40 } else { 0 } | self.opcode);
+        if len < 126 {
+            data.push(len as u8);
+        } else if len <= 0xFFFF {
+            data.push(126);
+            data.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            data.push(127);
+            data.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+        data.extend_from_slice(&self.payload);
+        data
+    }
+
+    /// the close frame with the code and the reason
+    pub fn new_close(code: u16, reason: &str) -> WsFrame {
+        let mut payload = code.to_be_bytes().to_vec();
+        // the control frame payload <= 125, the reason cut at the char boundary keep it utf8
+        let mut len = ::std::cmp::min(reason.len(), 123);
+        while !reason.is_char_boundary(len) {
+            len -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..len]);
+        WsFrame::new(WS_OP_CLOSE, payload)
+    }
+
+    /// the code and the reason of the close frame, the code 1005 if not set
+    pub fn get_close_info(&self) -> (u16, String) {
+        if self.payload.len() < 2 {
+            return (1005, String::new());
+        }
+        let code = u16::from_be_bytes([self.payload[0], self.payload[1]]);
+        (code, String::from_utf8_lossy(&self.payload[2..]).to_string())
+    }
+}
+
+impl WsUpgrade {
+    /// Ok(None) the request not complete, Ok(Some((upgrade, size))) the size of the request,
+    /// Err the request is not the websocket upgrade
+    pub fn parse(data: &[u8]) -> Result<Option<(WsUpgrade, usize)>, String> {
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut req = httparse::Request::new(&mut headers);
+        let size = match req.parse(data) {
+            Ok(httparse::Status::Complete(size)) => size,
+            Ok(httparse::Status::Partial) => return Ok(None),
+            Err(err) => return Err(format!("parse upgrade failed {}", err)),
+        };
+        if req.method != Some("GET") {
+            return Err(format!("method {:?} not GET", req.method));
+        }
+        let headers: Vec<(String, Vec<u8>)> = req.headers.iter()
+            .map(|h| (h.name.to_string(), h.value.to_vec()))
+            .collect();
+        let map: HashMap<String, String> = headers.iter()
+            .map(|(k, v)| (k.to_lowercase(), String::from_utf8_lossy(v).to_string()))
+            .collect();
+        let is_upgrade = map.get("upgrade").map(|u| u.eq_ignore_ascii_case("websocket")).unwrap_or(false);
+        if !is_upgrade {
+            return Err("not the websocket upgrade".to_string());
+        }
+        if map.get("sec-websocket-version").map(|v| v.trim()) != Some("13") {
+            return Err("websocket version not 13".to_string());
+        }
+        let key = unwrap_or!(map.get("sec-websocket-key"), return Err("websocket key missing".to_string()));
+        let deflate = map.get("sec-websocket-extensions").map(|e| Self::accept_deflate(e)).unwrap_or(false);
+        Ok(Some((WsUpgrade {
+            resource: req.path.unwrap_or("/").to_string(),
+            headers,
+            key: key.trim().to_string(),
+            deflate,
+        }, size)))
+    }
+
+    /// the offer limit the server window can't be accepted, the server always compress by the 15 bits
+    pub fn accept_deflate(extensions: &str) -> bool {
+        extensions.split(',').any(|offer| {
+            let mut params = offer.split(';').map(|p| p.trim());
+            params.next() == Some("permessage-deflate")
+                && params.all(|p| !p.starts_with("server_max_window_bits") || p.ends_with("15"))
+        })
+    }
+
+    pub fn get_accept_key(&self) -> String {
+        let mut sha1 = Sha1::new();
+        sha1.input_str(&self.key);
+        sha1.input_str(WS_GUID);
+        let mut hash = [0u8; 20];
+        sha1.result(&mut hash);
+        STANDARD.encode(hash)
+    }
+
+    /// the 101 response to finish the upgrade, accept the permessage-deflate if enabled and offered
+    pub fn accept_response(&self, deflate: bool) -> Vec<u8> {
+        let extension = if deflate {
+            format!("Sec-WebSocket-Extensions: {}\r\n", WS_DEFLATE_EXTENSION)
+        } else {
+            String::new()
+        };
+        format!("HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Accept: {}\r\n{}\r\n",
+                self.get_accept_key(), extension).into_bytes()
+    }
+
+    /// the http error response, the connection closed after it
+    pub fn reject_response(status: u16, reason: &str) -> Vec<u8> {
+        format!("HTTP/1.1 {} {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+                status, reason, reason.len(), reason).into_bytes()
+    }
+}
+
+impl WsSession {
+    pub fn new(config: &WebsocketConfig) -> WsSession {
+        WsSession {
+            text_json: config.is_text_json(),
+            max_frame: config.get_max_frame(),
+            in_buffer: config.get_in_buffer(),
+            out_buffer: config.get_out_buffer(),
+            ping_interval: config.get_ping_interval(),
+            ping_max_miss: config.get_ping_miss(),
+            ..WsSession::default()
+        }
+    }
+}
+
+/// compress the message by the sync flush and remove the tail
+pub fn ws_deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::with_capacity(data.len() / 2 + 16), Compression::default());
+    let _ = encoder.write_all(data);
+    let _ = encoder.flush();
+    let mut compressed = ::std::mem::take(encoder.get_mut());
+    if compressed.ends_with(&DEFLATE_TAIL) {
+        compressed.truncate(compressed.len() - DEFLATE_TAIL.len());
+    }
+    compressed
+}
+
+/// decompress the message with the tail appended, Err if invalid or bigger than max_size.
+/// the message has no final block, so inflate by the sync flush until the input used
+pub fn ws_inflate(data: &[u8], max_size: usize) -> Result<Vec<u8>, String> {
+    let mut input = Vec::with_capacity(data.len() + DEFLATE_TAIL.len());
+    input.extend_from_slice(data);
+    input.extend_from_slice(&DEFLATE_TAIL);
+    let mut decompress = Decompress::new(false);
+    let mut message = Vec::with_capacity(::std::cmp::min(data.len() * 4, max_size) + 64);
+    loop {
+        if message.capacity() - message.len() < 1024 {
+            message.reserve(4096);
+        }
+        let before = (decompress.total_in(), decompress.total_out());
+        let status = decompress.decompress_vec(&input[decompress.total_in() as usize..], &mut message, FlushDecompress::Sync)
+            .map_err(|err| format!("inflate failed {}", err))?;
+        if message.len() > max_size {
+            return Err(format!("message size > {}", max_size));
+        }
+        if status == Status::StreamEnd
+            || (decompress.total_in() as usize == input.len() && message.len() < message.capacity()) {
+            return Ok(message);
+        }
+        if before == (decompress.total_in(), decompress.total_out()) {
+            return Err("inflate failed no progress".to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASK: [u8; 4] = [0x37, 0xFA, 0x21, 0x3D];
+
+    /// the client frame is masked, the length is 7, 7+16 or 7+64 bits by the payload len
+    fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![first];
+        let len = payload.len();
+        if len < 126 {
+            data.push(0x80 | len as u8);
+        } else if len <= 0xFFFF {
+            data.push(0x80 | 126);
+            data.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            data.push(0x80 | 127);
+            data.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+        data.extend_from_slice(&MASK);
+        data.extend(payload.iter().enumerate().map(|(i, b)| b ^ MASK[i % 4]));
+        data
+    }
+
+    #[test]
+    fn parse_masked_frame() {
+        // the masked "Hello" of RFC 6455 5.7
+        let data = [0x81, 0x85, 0x37, 0xFA, 0x21, 0x3D, 0x7F, 0x9F, 0x4D, 0x51, 0x58];
+        let (frame, size) = WsFrame::parse(&data, 1024).unwrap().unwrap();
+        assert_eq!(size, data.len());
+        assert_eq!(frame, WsFrame::new(WS_OP_TEXT, b"Hello".to_vec()));
+    }
+
+    #[test]
+    fn parse_extended_length() {
+        for len in [125usize, 126, 0xFFFF, 0x10000] {
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let data = client_frame(0x82, &payload);
+            let (frame, size) = WsFrame::parse(&data, 0x10000).unwrap().unwrap();
+            assert_eq!((frame.opcode, frame.payload, size), (WS_OP_BINARY, payload, data.len()));
+        }
+        let data = client_frame(0x82, &[0u8; 0x10001]);
+        assert_eq!(WsFrame::parse(&data, 0x10000).unwrap_err().0, 1009);
+    }
+
+    #[test]
+    fn parse_partial_frame() {
+        let mut data = client_frame(0x82, &[1u8; 300]);
+        data.extend_from_slice(&client_frame(0x89, b"ping"));
+        let first = data.len() - 10;
+        for end in 0..first {
+            assert_eq!(WsFrame::parse(&data[..end], 1024), Ok(None));
+        }
+        let (frame, size) = WsFrame::parse(&data, 1024).unwrap().unwrap();
+        assert_eq!((frame.payload.len(), size), (300, first));
+        let (frame, size) = WsFrame::parse(&data[first..], 1024).unwrap().unwrap();
+        assert_eq!((frame.opcode, frame.payload, size), (WS_OP_PING, b"ping".to_vec(), 10));
+    }
+
+    #[test]
+    fn parse_invalid_frame() {
+        // the control frame can't be bigger than 125, fragmented or compressed
+        assert_eq!(WsFrame::parse(&client_frame(0x89, &[0u8; 126]), 1024).unwrap_err().0, 1002);
+        assert_eq!(WsFrame::parse(&client_frame(0x09, b"ping"), 1024).unwrap_err().0, 1002);
+        assert_eq!(WsFrame::parse(&client_frame(0xC8, b""), 1024).unwrap_err().0, 1002);
+        assert!(WsFrame::parse(&client_frame(0x89, &[0u8; 125]), 1024).unwrap().is_some());
+        // the rsv2 and rsv3 not negotiated
+        assert_eq!(WsFrame::parse(&client_frame(0xA2, b"a"), 1024).unwrap_err().0, 1002);
+        assert_eq!(WsFrame::parse(&client_frame(0x92, b"a"), 1024).unwrap_err().0, 1002);
+        let (frame, _) = WsFrame::parse(&client_frame(0xC2, b"a"), 1024).unwrap().unwrap();
+        assert!(frame.rsv1);
+        // the client frame must be masked
+        let err = WsFrame::parse(&WsFrame::new(WS_OP_TEXT, b"a".to_vec()).encode(), 1024).unwrap_err();
+        assert_eq!(err, (1002, "client frame not masked".to_string()));
+    }
+
+    #[test]
+    fn encode_server_frame() {
+        assert_eq!(WsFrame::new(WS_OP_TEXT, b"Hello".to_vec()).encode(), b"\x81\x05Hello".to_vec());
+        let data = WsFrame::new(WS_OP_BINARY, vec![0u8; 256]).encode();
+        assert_eq!(&data[..4], &[0x82, 126, 0x01, 0x00]);
+        let data = WsFrame::new(WS_OP_BINARY, vec![0u8; 0x10000]).encode();
+        assert_eq!(&data[..10], &[0x82, 127, 0, 0, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(data.len(), 10 + 0x10000);
+    }
+
+    #[test]
+    fn close_reason_cut_at_char_boundary() {
+        let frame = WsFrame::new_close(1000, "bye");
+        assert_eq!(frame.get_close_info(), (1000, "bye".to_string()));
+        assert_eq!(WsFrame::new(WS_OP_CLOSE, vec![]).get_close_info(), (1005, String::new()));
+
+        // 41 chars of 3 bytes is 123 bytes, the 42th char is cut whole
+        let reason = "关".repeat(42);
+        let frame = WsFrame::new_close(1009, &reason);
+        assert_eq!(frame.payload.len(), 125);
+        assert_eq!(frame.get_close_info(), (1009, "关".repeat(41)));
+        let reason = format!("a{}", reason);
+        let frame = WsFrame::new_close(1009, &reason);
+        assert_eq!(frame.payload.len(), 2 + 1 + 40 * 3);
+        assert!(::std::str::from_utf8(&frame.payload[2..]).is_ok());
+    }
+
+    #[test]
+    fn upgrade_accept_key() {
+        let request = b"GET /chat?token=abc HTTP/1.1\r\nHost: server.example.com\r\nUpgrade: websocket\r\n\
+            Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+        let (upgrade, size) = WsUpgrade::parse(request).unwrap().unwrap();
+        assert_eq!(size, request.len());
+        assert_eq!(upgrade.resource, "/chat?token=abc");
+        assert_eq!(upgrade.get_accept_key(), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        let response = String::from_utf8(upgrade.accept_response(false)).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 "));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        assert_eq!(WsUpgrade::parse(&request[..40]).unwrap().map(|(_, size)| size), None);
+        let request = String::from_utf8_lossy(request).replace("Version: 13", "Version: 8");
+        assert!(WsUpgrade::parse(request.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn deflate_round_trip() {
+        let text: Vec<u8> = b"{\"name\": \"cmd_login\", \"args\": [\"account\"]}".iter().cycle().take(100000).cloned().collect();
+        let noise: Vec<u8> = (0..5000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        for data in [vec![], b"Hello".to_vec(), text, noise] {
+            let compressed = ws_deflate(&data);
+            assert!(!compressed.ends_with(&DEFLATE_TAIL));
+            assert_eq!(ws_inflate(&compressed, data.len()).unwrap(), data);
+        }
+        // the "Hello" compressed by the client of RFC 7692 7.2.3.1, the tail removed
+        assert_eq!(ws_inflate(&[0xF2, 0x48, 0xCD, 0xC9, 0xC9, 0x07, 0x00], 5).unwrap(), b"Hello".to_vec());
+
+        let frame = WsFrame::new_data(WS_OP_TEXT, b"Hello", true);
+        assert!(frame.rsv1);
+        assert_eq!(ws_inflate(&frame.payload, 5).unwrap(), b"Hello".to_vec());
+        assert_eq!(WsFrame::new_data(WS_OP_TEXT, b"Hello", false), WsFrame::new(WS_OP_TEXT, b"Hello".to_vec()));
+    }
+
+    #[test]
+    fn inflate_oversize_and_invalid() {
+        let compressed = ws_deflate(&[0u8; 100000]);
+        assert!(compressed.len() < 1000);
+        assert!(ws_inflate(&compressed, 100000).is_ok());
+        assert_eq!(ws_inflate(&compressed, 99999).unwrap_err(), "message size > 99999");
+        assert!(ws_inflate(&[0xFF, 0xFF, 0xFF, 0xFF], 1024).is_err());
+    }
+
+    #[test]
+    fn accept_deflate_offer() {
+        assert!(WsUpgrade::accept_deflate("permessage-deflate"));
+        assert!(WsUpgrade::accept_deflate("permessage-deflate; client_max_window_bits"));
+        assert!(WsUpgrade::accept_deflate("permessage-deflate; client_max_window_bits=10"));
+        assert!(WsUpgrade::accept_deflate("permessage-deflate; server_max_window_bits=15; server_no_context_takeover"));
+        assert!(!WsUpgrade::accept_deflate("permessage-deflate; server_max_window_bits=10"));
+        assert!(!WsUpgrade::accept_deflate("x-webkit-deflate-frame"));
+        // the next offer is accepted if the first is refused
+        assert!(WsUpgrade::accept_deflate("permessage-deflate; server_max_window_bits=10, permessage-deflate"));
+
+        let request = "GET / HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Version: 13\r\nSec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n\r\n";
+        let (upgrade, _) = WsUpgrade::parse(request.as_bytes()).unwrap().unwrap();
+        assert!(upgrade.deflate);
+        let response = String::from_utf8(upgrade.accept_response(true)).unwrap();
+        assert!(response.contains(&format!("Sec-WebSocket-Extensions: {}\r\n", WS_DEFLATE_EXTENSION)));
+        assert!(!String::from_utf8(upgrade.accept_response(false)).unwrap().contains("Extensions"));
+    }
+}
@@ -0,0 +1,386 @@
+-- the client sdk of the tunm td protocol, generated by proto_gen from protocol.txt, do not edit
+-- need lua 5.3 for the integer and string.pack
+
+local M = {}
+
+M.MSG_TYPE_TD = 0
+M.HEAD_LEN = 26
+
+local TYPE_NIL = 0
+local TYPE_BOOL = 1
+local TYPE_U8 = 2
+local TYPE_I8 = 3
+local TYPE_U16 = 4
+local TYPE_I16 = 5
+local TYPE_U32 = 6
+local TYPE_I32 = 7
+local TYPE_U64 = 8
+local TYPE_I64 = 9
+local TYPE_VARINT = 10
+local TYPE_FLOAT = 11
+local TYPE_DOUBLE = 12
+local TYPE_STR = 13
+local TYPE_STR_IDX = 14
+local TYPE_RAW = 15
+local TYPE_ARR = 16
+local TYPE_MAP = 17
+
+-- the head layout, length cookie msg_type msg_flag from_svr_type from_svr_id to_svr_type to_svr_id real_fd
+local HEAD_FORMAT = "<I4I4BBI2I4I2I4I4"
+
+local FIELD_PATTERNS
+local PROTO_ARGS
+
+local function new_writer()
+    return { bytes = {}, strs = {}, str_idx = {} }
+end
+
+local function write_u8(writer, val)
+    writer.bytes[#writer.bytes + 1] = string.char(val & 0xFF)
+end
+
+local function write_varint(writer, val)
+    local real
+    if val >= 0 then
+        real = val * 2
+    else
+        real = (-(val + 1)) * 2 + 1
+    end
+    repeat
+        local data = real & 0x7F
+        real = real >> 7
+        if real > 0 then
+            data = data | 0x80
+        end
+        writer.bytes[#writer.bytes + 1] = string.char(data)
+    until real == 0
+end
+
+local function write_raw(writer, data)
+    write_varint(writer, #data)
+    writer.bytes[#writer.bytes + 1] = data
+end
+
+local function write_str_index(writer, val)
+    local idx = writer.str_idx[val]
+    if not idx then
+        idx = #writer.strs
+        writer.strs[idx + 1] = val
+        writer.str_idx[val] = idx
+    end
+    write_u8(writer, TYPE_STR_IDX)
+    write_varint(writer, idx)
+end
+
+--- the sequence table is the array, the other table is the map, the empty table is the map
+local function is_array(value)
+    local len = #value
+    if len == 0 then
+        return false
+    end
+    local count = 0
+    for k, _ in pairs(value) do
+        if math.type(k) ~= "integer" or k < 1 or k > len then
+            return false
+        end
+        count = count + 1
+    end
+    return count == len
+end
+
+local encode_field
+
+local function encode_map(writer, map)
+    local keys = {}
+    for k, _ in pairs(map) do
+        keys[#keys + 1] = tostring(k)
+    end
+    table.sort(keys)
+    write_u8(writer, TYPE_MAP)
+    write_varint(writer, #keys)
+    for _, key in ipairs(keys) do
+        local value = map[key]
+        if value == nil then
+            value = map[math.tointeger(tonumber(key))]
+        end
+        write_str_index(writer, key)
+        encode_field(writer, value, FIELD_PATTERNS[key])
+    end
+end
+
+local function encode_integer(writer, value)
+    write_u8(writer, TYPE_VARINT)
+    write_varint(writer, math.tointeger(value) or math.floor(value))
+end
+
+local function encode_float(writer, value)
+    write_u8(writer, TYPE_FLOAT)
+    local val = value * 1000
+    write_varint(writer, math.tointeger(val >= 0 and math.floor(val) or math.ceil(val)))
+end
+
+--- encode by the pattern of protocol.txt, the value without pattern is encoded by its type
+encode_field = function(writer, value, pattern)
+    if value == nil then
+        write_u8(writer, TYPE_NIL)
+        return
+    end
+    if pattern and pattern:sub(-2) == "[]" then
+        local list = type(value) == "table" and value or {}
+        write_u8(writer, TYPE_ARR)
+        write_varint(writer, #list)
+        for i = 1, #list do
+            encode_field(writer, list[i], pattern:sub(1, -3))
+        end
+        return
+    end
+    if pattern == "u8" or pattern == "i8" then
+        write_u8(writer, pattern == "u8" and TYPE_U8 or TYPE_I8)
+        write_u8(writer, math.tointeger(value) or math.floor(tonumber(value)))
+        return
+    elseif pattern == "u16" or pattern == "i16" or pattern == "u32" or pattern == "i32" then
+        encode_integer(writer, tonumber(value))
+        return
+    elseif pattern == "float" then
+        encode_float(writer, tonumber(value))
+        return
+    elseif pattern == "str" then
+        write_str_index(writer, tostring(value))
+        return
+    elseif pattern == "raw" then
+        write_u8(writer, TYPE_RAW)
+        write_raw(writer, tostring(value))
+        return
+    elseif pattern == "map" then
+        encode_map(writer, type(value) == "table" and value or {})
+        return
+    end
+
+    local ty = type(value)
+    if ty == "boolean" then
+        write_u8(writer, TYPE_BOOL)
+        write_u8(writer, value and 1 or 0)
+    elseif ty == "number" then
+        if math.type(value) == "integer" or value == math.floor(value) then
+            encode_integer(writer, value)
+        else
+            encode_float(writer, value)
+        end
+    elseif ty == "string" then
+        write_str_index(writer, value)
+    elseif ty == "table" then
+        if is_array(value) then
+            write_u8(writer, TYPE_ARR)
+            write_varint(writer, #value)
+            for i = 1, #value do
+                encode_field(writer, value[i])
+            end
+        else
+            encode_map(writer, value)
+        end
+    else
+        error("tunm proto unsupport type " .. ty)
+    end
+end
+
+--- encode the whole message with the head, the args are encoded by the patterns,
+--- the n is the args count to keep the nil tail
+function M.encode_message(name, args, patterns, head, n)
+    n = n or #args
+    local body = new_writer()
+    write_u8(body, TYPE_ARR)
+    write_varint(body, n)
+    for i = 1, n do
+        encode_field(body, args[i], patterns and patterns[i])
+    end
+
+    local out = new_writer()
+    write_raw(out, name)
+    write_varint(out, #body.strs)
+    for _, str in ipairs(body.strs) do
+        write_raw(out, str)
+    end
+    local data = table.concat(out.bytes) .. table.concat(body.bytes)
+
+    head = head or {}
+    return string.pack(HEAD_FORMAT, #data + M.HEAD_LEN, head.cookie or 0, head.msg_type or M.MSG_TYPE_TD,
+        head.msg_flag or 0, head.from_svr_type or 0, head.from_svr_id or 0, head.to_svr_type or 0,
+        head.to_svr_id or 0, head.real_fd or 0) .. data
+end
+
+--- read the head, nil if the data less than HEAD_LEN
+function M.read_head(data)
+    if #data < M.HEAD_LEN then
+        return nil
+    end
+    local head = {}
+    head.length, head.cookie, head.msg_type, head.msg_flag, head.from_svr_type, head.from_svr_id,
+        head.to_svr_type, head.to_svr_id, head.real_fd = string.unpack(HEAD_FORMAT, data)
+    return head
+end
+
+local function new_reader(data, pos)
+    return { data = data, pos = pos, strs = {} }
+end
+
+local function read_u8(reader)
+    if reader.pos > #reader.data then
+        error("tunm proto read out of range")
+    end
+    local val = reader.data:byte(reader.pos)
+    reader.pos = reader.pos + 1
+    return val
+end
+
+local function read_fixed(reader, format, size)
+    if reader.pos + size - 1 > #reader.data then
+        error("tunm proto read out of range")
+    end
+    local val = string.unpack(format, reader.data, reader.pos)
+    reader.pos = reader.pos + size
+    return val
+end
+
+local function read_varint(reader)
+    local real = 0
+    local shift = 0
+    while true do
+        local data = read_u8(reader)
+        if shift > 63 then
+            error("tunm proto too big varint")
+        end
+        real = real | ((data & 0x7F) << shift)
+        shift = shift + 7
+        if data & 0x80 == 0 then
+            break
+        end
+    end
+    if real & 1 == 1 then
+        return -(real >> 1) - 1
+    end
+    return real >> 1
+end
+
+local function read_raw(reader)
+    local len = read_varint(reader)
+    if len < 0 or reader.pos + len - 1 > #reader.data then
+        error("tunm proto read out of range")
+    end
+    local val = reader.data:sub(reader.pos, reader.pos + len - 1)
+    reader.pos = reader.pos + len
+    return val
+end
+
+local function read_field(reader)
+    local ty = read_u8(reader)
+    if ty == TYPE_NIL then
+        return nil
+    elseif ty == TYPE_BOOL then
+        return read_u8(reader) == 1
+    elseif ty == TYPE_U8 then
+        return read_fixed(reader, "<B", 1)
+    elseif ty == TYPE_I8 then
+        return read_fixed(reader, "<b", 1)
+    elseif ty == TYPE_U16 then
+        return read_fixed(reader, "<I2", 2)
+    elseif ty == TYPE_I16 then
+        return read_fixed(reader, "<i2", 2)
+    elseif ty == TYPE_U32 then
+        return read_fixed(reader, "<I4", 4)
+    elseif ty == TYPE_I32 then
+        return read_fixed(reader, "<i4", 4)
+    elseif ty == TYPE_U64 or ty == TYPE_I64 then
+        return read_fixed(reader, "<i8", 8)
+    elseif ty == TYPE_VARINT then
+        return read_varint(reader)
+    elseif ty == TYPE_FLOAT then
+        return read_varint(reader) / 1000
+    elseif ty == TYPE_DOUBLE then
+        return read_varint(reader) / 1000000
+    elseif ty == TYPE_STR or ty == TYPE_RAW then
+        return read_raw(reader)
+    elseif ty == TYPE_STR_IDX then
+        local idx = read_varint(reader)
+        local val = reader.strs[idx + 1]
+        if val == nil then
+            error("tunm proto unknow str idx " .. idx)
+        end
+        return val
+    elseif ty == TYPE_ARR then
+        local len = read_varint(reader)
+        local arr = {}
+        for i = 1, len do
+            arr[i] = read_field(reader)
+        end
+        return arr, len
+    elseif ty == TYPE_MAP then
+        local len = read_varint(reader)
+        local map = {}
+        for _ = 1, len do
+            local key = read_field(reader)
+            local value = read_field(reader)
+            if key ~= nil then
+                map[key] = value
+            end
+        end
+        return map
+    end
+    error("tunm proto unknow type " .. ty)
+end
+
+--- decode the whole message with the head, return name, args, head and the args count
+function M.decode_message(data)
+    local head = M.read_head(data)
+    if head == nil or head.length > #data then
+        error("tunm proto message not complete")
+    end
+    local reader = new_reader(data:sub(1, head.length), M.HEAD_LEN + 1)
+    local name = read_raw(reader)
+    local str_len = read_varint(reader)
+    for i = 1, str_len do
+        reader.strs[i] = read_raw(reader)
+    end
+    if read_u8(reader) ~= TYPE_ARR then
+        error("tunm proto is not array")
+    end
+    local len = read_varint(reader)
+    local args = {}
+    for i = 1, len do
+        args[i] = read_field(reader)
+    end
+    return name, args, head, len
+end
+
+--- split the stream data to the messages, keep the incomplete tail,
+--- the frame failed to decode is skipped and reported to reader.on_error(err, frame)
+function M.new_frame_reader()
+    local buffer = ""
+    local reader = {}
+    function reader.push(data)
+        buffer = buffer .. data
+        local messages = {}
+        while true do
+            local head = M.read_head(buffer)
+            if head == nil or head.length > #buffer then
+                break
+            end
+            local length = math.max(head.length, M.HEAD_LEN)
+            local frame = buffer:sub(1, length)
+            buffer = buffer:sub(length + 1)
+            local ok, name, args, msg_head, len = pcall(M.decode_message, frame)
+            if ok then
+                messages[#messages + 1] = { name = name, args = args, head = msg_head, n = len }
+            elseif reader.on_error then
+                reader.on_error(name, frame)
+            end
+        end
+        return messages
+    end
+    return reader
+end
+
+--@FIELDS@
+
+--@MESSAGES@
+
+return M
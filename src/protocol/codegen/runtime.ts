@@ -0,0 +1,390 @@
+// the client sdk of the tunm td protocol, generated by proto_gen from protocol.txt, do not edit
+
+export const MSG_TYPE_TD = 0;
+export const HEAD_LEN = 26;
+
+const TYPE_NIL = 0;
+const TYPE_BOOL = 1;
+const TYPE_U8 = 2;
+const TYPE_I8 = 3;
+const TYPE_U16 = 4;
+const TYPE_I16 = 5;
+const TYPE_U32 = 6;
+const TYPE_I32 = 7;
+const TYPE_U64 = 8;
+const TYPE_I64 = 9;
+const TYPE_VARINT = 10;
+const TYPE_FLOAT = 11;
+const TYPE_DOUBLE = 12;
+const TYPE_STR = 13;
+const TYPE_STR_IDX = 14;
+const TYPE_RAW = 15;
+const TYPE_ARR = 16;
+const TYPE_MAP = 17;
+
+export type ProtoValue = null | boolean | number | string | Uint8Array | ProtoValue[] | ProtoMap;
+export interface ProtoMap {
+    [key: string]: ProtoValue | undefined;
+}
+
+/// the 26 bytes head before every message, all numbers are little endian
+export interface NetMsgHead {
+    length: number;
+    cookie: number;
+    msgType: number;
+    msgFlag: number;
+    fromSvrType: number;
+    fromSvrId: number;
+    toSvrType: number;
+    toSvrId: number;
+    realFd: number;
+}
+
+export interface DecodedMessage {
+    head: NetMsgHead;
+    name: string;
+    args: ProtoValue[];
+}
+
+const encoder = new TextEncoder();
+const decoder = new TextDecoder();
+
+class Writer {
+    bytes: number[] = [];
+    strs: string[] = [];
+    strIdx: Map<string, number> = new Map();
+
+    u8(val: number) {
+        this.bytes.push(val & 0xFF);
+    }
+
+    /// the zigzag varint, the number must be the safe integer
+    varint(val: number) {
+        let real = val >= 0 ? val * 2 : (-(val + 1)) * 2 + 1;
+        do {
+            let data = real % 128;
+            real = Math.floor(real / 128);
+            if (real > 0) {
+                data |= 0x80;
+            }
+            this.bytes.push(data);
+        } while (real > 0);
+    }
+
+    raw(data: Uint8Array) {
+        this.varint(data.length);
+        for (let i = 0; i < data.length; i++) {
+            this.bytes.push(data[i]);
+        }
+    }
+
+    strIndex(val: string) {
+        let idx = this.strIdx.get(val);
+        if (idx === undefined) {
+            idx = this.strs.length;
+            this.strs.push(val);
+            this.strIdx.set(val, idx);
+        }
+        this.u8(TYPE_STR_IDX);
+        this.varint(idx);
+    }
+}
+
+class Reader {
+    pos: number = 0;
+    strs: string[] = [];
+
+    constructor(private data: Uint8Array) {
+    }
+
+    u8(): number {
+        if (this.pos >= this.data.length) {
+            throw new Error("tunm proto read out of range");
+        }
+        return this.data[this.pos++];
+    }
+
+    uint(size: number): number {
+        let val = 0;
+        for (let i = 0; i < size; i++) {
+            val += this.u8() * Math.pow(2, 8 * i);
+        }
+        return val;
+    }
+
+    int(size: number): number {
+        const val = this.uint(size);
+        const max = Math.pow(2, 8 * size);
+        return val >= max / 2 ? val - max : val;
+    }
+
+    varint(): number {
+        let real = 0;
+        let mul = 1;
+        for (;;) {
+            const data = this.u8();
+            real += (data & 0x7F) * mul;
+            mul *= 128;
+            if ((data & 0x80) == 0) {
+                break;
+            }
+        }
+        return real % 2 == 1 ? -Math.floor(real / 2) - 1 : Math.floor(real / 2);
+    }
+
+    raw(): Uint8Array {
+        const len = this.varint();
+        if (len < 0 || this.pos + len > this.data.length) {
+            throw new Error("tunm proto read out of range");
+        }
+        const val = this.data.slice(this.pos, this.pos + len);
+        this.pos += len;
+        return val;
+    }
+
+    str(): string {
+        return decoder.decode(this.raw());
+    }
+
+    field(): ProtoValue {
+        const ty = this.u8();
+        switch (ty) {
+            case TYPE_NIL: return null;
+            case TYPE_BOOL: return this.u8() == 1;
+            case TYPE_U8: return this.uint(1);
+            case TYPE_I8: return this.int(1);
+            case TYPE_U16: return this.uint(2);
+            case TYPE_I16: return this.int(2);
+            case TYPE_U32: return this.uint(4);
+            case TYPE_I32: return this.int(4);
+            case TYPE_U64: return this.uint(8);
+            case TYPE_I64: return this.int(8);
+            case TYPE_VARINT: return this.varint();
+            case TYPE_FLOAT: return this.varint() / 1000;
+            case TYPE_DOUBLE: return this.varint() / 1000000;
+            case TYPE_STR: return this.str();
+            case TYPE_STR_IDX: {
+                const idx = this.varint();
+                if (idx < 0 || idx >= this.strs.length) {
+                    throw new Error("tunm proto unknow str idx " + idx);
+                }
+                return this.strs[idx];
+            }
+            case TYPE_RAW: return this.raw();
+            case TYPE_ARR: {
+                const len = this.varint();
+                const arr: ProtoValue[] = [];
+                for (let i = 0; i < len; i++) {
+                    arr.push(this.field());
+                }
+                return arr;
+            }
+            case TYPE_MAP: {
+                const len = this.varint();
+                const map: ProtoMap = {};
+                for (let i = 0; i < len; i++) {
+                    const key = this.field();
+                    map[String(key)] = this.field();
+                }
+                return map;
+            }
+        }
+        throw new Error("tunm proto unknow type " + ty);
+    }
+}
+
+function encodeMap(writer: Writer, map: ProtoMap) {
+    const keys = Object.keys(map).filter(key => map[key] !== undefined);
+    writer.u8(TYPE_MAP);
+    writer.varint(keys.length);
+    for (const key of keys) {
+        writer.strIndex(key);
+        encodeField(writer, map[key] as ProtoValue, FIELD_PATTERNS[key]);
+    }
+}
+
+/// encode by the pattern of protocol.txt, the value without pattern is encoded by its type
+function encodeField(writer: Writer, value: ProtoValue, pattern?: string) {
+    if (value === null || value === undefined) {
+        writer.u8(TYPE_NIL);
+        return;
+    }
+    if (pattern !== undefined && pattern.endsWith("[]")) {
+        const list = Array.isArray(value) ? value : [];
+        writer.u8(TYPE_ARR);
+        writer.varint(list.length);
+        for (const sub of list) {
+            encodeField(writer, sub, pattern.substring(0, pattern.length - 2));
+        }
+        return;
+    }
+    switch (pattern) {
+        case "u8":
+            writer.u8(TYPE_U8);
+            writer.u8(Number(value));
+            return;
+        case "i8":
+            writer.u8(TYPE_I8);
+            writer.u8(Number(value));
+            return;
+        case "u16":
+        case "i16":
+        case "u32":
+        case "i32":
+            writer.u8(TYPE_VARINT);
+            writer.varint(Math.trunc(Number(value)));
+            return;
+        case "float":
+            writer.u8(TYPE_FLOAT);
+            writer.varint(Math.trunc(Number(value) * 1000));
+            return;
+        case "str":
+            writer.strIndex(String(value));
+            return;
+        case "raw":
+            writer.u8(TYPE_RAW);
+            writer.raw(value instanceof Uint8Array ? value : encoder.encode(String(value)));
+            return;
+        case "map":
+            encodeMap(writer, value as ProtoMap);
+            return;
+    }
+    if (typeof value === "boolean") {
+        writer.u8(TYPE_BOOL);
+        writer.u8(value ? 1 : 0);
+    } else if (typeof value === "number") {
+        if (Number.isInteger(value)) {
+            writer.u8(TYPE_VARINT);
+            writer.varint(value);
+        } else {
+            writer.u8(TYPE_FLOAT);
+            writer.varint(Math.trunc(value * 1000));
+        }
+    } else if (typeof value === "string") {
+        writer.strIndex(value);
+    } else if (value instanceof Uint8Array) {
+        writer.u8(TYPE_RAW);
+        writer.raw(value);
+    } else if (Array.isArray(value)) {
+        writer.u8(TYPE_ARR);
+        writer.varint(value.length);
+        for (const sub of value) {
+            encodeField(writer, sub);
+        }
+    } else {
+        encodeMap(writer, value);
+    }
+}
+
+function writeUint(bytes: number[], pos: number, val: number, size: number) {
+    for (let i = 0; i < size; i++) {
+        bytes[pos + i] = Math.floor(val / Math.pow(2, 8 * i)) & 0xFF;
+    }
+}
+
+/// encode the whole message with the head, the args are encoded by the patterns
+export function encodeMessage(name: string, args: ProtoValue[], patterns?: string[], head?: Partial<NetMsgHead>): Uint8Array {
+    const body = new Writer();
+    body.u8(TYPE_ARR);
+    body.varint(args.length);
+    args.forEach((arg, i) => encodeField(body, arg, patterns ? patterns[i] : undefined));
+
+    const out = new Writer();
+    out.bytes = new Array(HEAD_LEN).fill(0);
+    out.raw(encoder.encode(name));
+    out.varint(body.strs.length);
+    for (const str of body.strs) {
+        out.raw(encoder.encode(str));
+    }
+    for (const data of body.bytes) {
+        out.bytes.push(data);
+    }
+
+    const h = head || {};
+    writeUint(out.bytes, 0, out.bytes.length, 4);
+    writeUint(out.bytes, 4, h.cookie || 0, 4);
+    writeUint(out.bytes, 8, h.msgType || MSG_TYPE_TD, 1);
+    writeUint(out.bytes, 9, h.msgFlag || 0, 1);
+    writeUint(out.bytes, 10, h.fromSvrType || 0, 2);
+    writeUint(out.bytes, 12, h.fromSvrId || 0, 4);
+    writeUint(out.bytes, 16, h.toSvrType || 0, 2);
+    writeUint(out.bytes, 18, h.toSvrId || 0, 4);
+    writeUint(out.bytes, 22, h.realFd || 0, 4);
+    return Uint8Array.from(out.bytes);
+}
+
+/// read the head, null if the data less than HEAD_LEN
+export function readHead(data: Uint8Array): NetMsgHead | null {
+    if (data.length < HEAD_LEN) {
+        return null;
+    }
+    const reader = new Reader(data);
+    return {
+        length: reader.uint(4),
+        cookie: reader.uint(4),
+        msgType: reader.uint(1),
+        msgFlag: reader.uint(1),
+        fromSvrType: reader.uint(2),
+        fromSvrId: reader.uint(4),
+        toSvrType: reader.uint(2),
+        toSvrId: reader.uint(4),
+        realFd: reader.uint(4),
+    };
+}
+
+/// decode the whole message with the head
+export function decodeMessage(data: Uint8Array): DecodedMessage {
+    const head = readHead(data);
+    if (head === null || head.length > data.length) {
+        throw new Error("tunm proto message not complete");
+    }
+    const reader = new Reader(data.subarray(0, head.length));
+    reader.pos = HEAD_LEN;
+    const name = reader.str();
+    const strLen = reader.varint();
+    for (let i = 0; i < strLen; i++) {
+        reader.strs.push(reader.str());
+    }
+    const args = reader.field();
+    if (!Array.isArray(args)) {
+        throw new Error("tunm proto is not array");
+    }
+    return { head, name, args };
+}
+
+/// split the stream data to the messages, keep the incomplete tail,
+/// the frame failed to decode is skipped and reported to onError
+export class FrameReader {
+    private buffer: Uint8Array = new Uint8Array(0);
+    onError: ((err: Error, frame: Uint8Array) => void) | null = null;
+
+    push(data: Uint8Array): DecodedMessage[] {
+        const merge = new Uint8Array(this.buffer.length + data.length);
+        merge.set(this.buffer);
+        merge.set(data, this.buffer.length);
+        this.buffer = merge;
+
+        const messages: DecodedMessage[] = [];
+        for (;;) {
+            const head = readHead(this.buffer);
+            if (head === null || head.length > this.buffer.length) {
+                break;
+            }
+            const length = Math.max(head.length, HEAD_LEN);
+            const frame = this.buffer.slice(0, length);
+            this.buffer = this.buffer.slice(length);
+            try {
+                messages.push(decodeMessage(frame));
+            } catch (err) {
+                if (this.onError !== null) {
+                    this.onError(err as Error, frame);
+                }
+            }
+        }
+        return messages;
+    }
+}
+
+/*@FIELDS@*/
+
+/*@MESSAGES@*/
@@ -1,20 +1,40 @@
 
 use NetMsg;
 use td_rlua::{self};
-use {NetResult};
+use {NetResult, make_extension_error};
 
-pub trait EngineProtocol: Sized {
-    fn pack_protocol(lua: *mut td_rlua::lua_State, index: i32) -> Option<NetMsg>;
-    fn unpack_protocol(lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<i32>;
-    fn convert_string(lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<String>;
+/// the protocol registered by msg_type in ProtocolMgr, the engine or the user can register
+/// the custom protocol without change the engine
+pub trait EngineProtocol {
+    /// the protocol name used by the config list
+    fn get_name(&self) -> &str;
+    /// pack the lua args begin at index to the NetMsg
+    fn pack_protocol(&mut self, lua: *mut td_rlua::lua_State, index: i32) -> Option<NetMsg>;
+    /// push the NetMsg to lua, return the num of the values pushed
+    fn unpack_protocol(&mut self, lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<i32>;
+    fn convert_string(&mut self, lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<String>;
+    /// load the schema such as the protobuf descriptors, the protocol without schema return error
+    fn load_schema(&mut self, _path: &str) -> NetResult<()> {
+        Err(make_extension_error("protocol not support schema", Some(self.get_name())))
+    }
 }
 
 mod proto_rt;
 mod proto_json;
 mod proto_bin;
 mod proto_text;
+mod proto_protobuf;
+mod proto_msgpack;
+mod proto_schema;
+mod proto_codegen;
+mod proto_compat;
 
 pub use self::proto_rt::ProtoRt;
 pub use self::proto_json::ProtoJson;
 pub use self::proto_bin::ProtoBin;
 pub use self::proto_text::ProtoText;
+pub use self::proto_protobuf::ProtoProtobuf;
+pub use self::proto_msgpack::ProtoMsgpack;
+pub use self::proto_schema::{ProtoSchema, SchemaConfig, SchemaType, SchemaPattern, SchemaProto};
+pub use self::proto_codegen::ProtoCodegen;
+pub use self::proto_compat::{ProtoCompat, CompatReport, CompatChange, CompatKind};
@@ -8,7 +8,11 @@ use LuaUtils;
 pub struct ProtoBin;
 
 impl EngineProtocol for ProtoBin {
-    fn pack_protocol(lua: *mut td_rlua::lua_State, index: i32) -> Option<NetMsg> {
+    fn get_name(&self) -> &str {
+        "bin"
+    }
+
+    fn pack_protocol(&mut self, lua: *mut td_rlua::lua_State, index: i32) -> Option<NetMsg> {
         unsafe {
             let name: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, index), return None);
             if td_rlua::lua_isstring(lua, index + 1) == 0 {
@@ -16,12 +20,11 @@ impl EngineProtocol for ProtoBin {
             }
 
             let val = unwrap_or!(LuaUtils::read_str_to_vec(lua, index + 1), return None);
-            let net_msg = NetMsg::new_by_detail(MSG_TYPE_BIN, name, &val[..]);
-            Some(net_msg)
+            NetMsg::new_by_detail(MSG_TYPE_BIN, name, &val[..]).ok()
         }
     }
 
-    fn unpack_protocol(lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<i32> {
+    fn unpack_protocol(&mut self, lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<i32> {
         net_msg.set_read_data();
         let name: String = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?.into();
         let raw: Value = decode_str_raw(net_msg.get_buffer(), TYPE_RAW)?;
@@ -31,7 +34,7 @@ impl EngineProtocol for ProtoBin {
     }
 
 
-    fn convert_string(_: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<String> {
+    fn convert_string(&mut self, _: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<String> {
         net_msg.set_read_data();
         let _: String = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?.into();
         let raw: String = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?.into();
@@ -0,0 +1,271 @@
+use std::fmt::Write;
+use super::{ProtoSchema, SchemaPattern, SchemaProto, SchemaType};
+use {NetResult, make_extension_error};
+
+const RUNTIME_TS: &str = include_str!("codegen/runtime.ts");
+const RUNTIME_CS: &str = include_str!("codegen/runtime.cs");
+const RUNTIME_LUA: &str = include_str!("codegen/runtime.lua");
+
+/// generate the client sdk of the td protocol by the protocol.txt, the sdk contains the
+/// head framing, the encoder and decoder of every message, support `ts`, `cs` and `lua`
+pub struct ProtoCodegen;
+
+/// the name such as `cmd_login` to `CmdLogin`
+fn pascal_name(name: &str) -> String {
+    let mut result = String::new();
+    for part in name.split(|c: char| !c.is_ascii_alphanumeric()).filter(|p| !p.is_empty()) {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            result.push(first.to_ascii_uppercase());
+            result.extend(chars);
+        }
+    }
+    if result.chars().next().map(|c| c.is_ascii_digit()).unwrap_or(true) {
+        result.insert(0, 'P');
+    }
+    result
+}
+
+/// the name can be used as the identifier of the lua function
+fn snake_name(name: &str) -> String {
+    let result: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if result.chars().next().map(|c| c.is_ascii_digit()).unwrap_or(true) {
+        format!("p_{}", result)
+    } else {
+        result
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("{:?}", name)
+}
+
+fn sorted_fields(schema: &ProtoSchema) -> Vec<(&String, &SchemaPattern)> {
+    let mut fields: Vec<_> = schema.get_fields().iter().collect();
+    fields.sort_by(|a, b| a.0.cmp(b.0));
+    fields
+}
+
+fn sorted_protos(schema: &ProtoSchema) -> Vec<(&String, &SchemaProto)> {
+    let mut protos: Vec<_> = schema.get_protos().iter().collect();
+    protos.sort_by(|a, b| a.0.cmp(b.0));
+    protos
+}
+
+fn proto_desc(name: &str, proto: &SchemaProto) -> String {
+    let args: Vec<String> = proto.args.iter().map(|a| a.get_name()).collect();
+    format!("{}, msg_type {}, args [{}]", name, proto.msg_type, args.join(", "))
+}
+
+fn fill_template(template: &str, fields_mark: &str, fields: &str, messages_mark: &str, messages: &str) -> String {
+    template.replacen(fields_mark, fields.trim_end(), 1).replacen(messages_mark, messages.trim_end(), 1)
+}
+
+fn ts_type(pattern: &SchemaPattern) -> String {
+    let ty = match pattern.ty {
+        SchemaType::None => "ProtoValue",
+        SchemaType::Str => "string",
+        SchemaType::Raw => "Uint8Array",
+        SchemaType::Map => "ProtoFields",
+        _ => "number",
+    };
+    if pattern.is_array {
+        format!("{}[]", ty)
+    } else {
+        ty.to_string()
+    }
+}
+
+fn cs_type(pattern: &SchemaPattern) -> String {
+    let ty = match pattern.ty {
+        SchemaType::None => "object",
+        SchemaType::U8 => "byte",
+        SchemaType::I8 => "sbyte",
+        SchemaType::U16 => "ushort",
+        SchemaType::I16 => "short",
+        SchemaType::U32 => "uint",
+        SchemaType::I32 => "int",
+        SchemaType::Float => "float",
+        SchemaType::Str => "string",
+        SchemaType::Raw => "byte[]",
+        SchemaType::Map => "Dictionary<string, object>",
+    };
+    if pattern.is_array {
+        format!("List<{}>", ty)
+    } else {
+        ty.to_string()
+    }
+}
+
+/// the c# expression convert the decoded object `value` to the type of pattern
+fn cs_convert(ty: SchemaType, value: &str) -> String {
+    match ty {
+        SchemaType::None => value.to_string(),
+        SchemaType::Float => format!("(float)TunmProto.ToDouble({})", value),
+        SchemaType::Str => format!("TunmProto.ToStr({})", value),
+        SchemaType::Raw => format!("TunmProto.ToRaw({})", value),
+        SchemaType::Map => format!("TunmProto.ToMap({})", value),
+        _ => format!("({})TunmProto.ToLong({})", cs_type(&SchemaPattern { ty, is_array: false }), value),
+    }
+}
+
+impl ProtoCodegen {
+    /// the languages can be generated
+    pub fn get_langs() -> &'static [&'static str] {
+        &["ts", "cs", "lua"]
+    }
+
+    pub fn get_file_name(lang: &str) -> Option<&'static str> {
+        match lang {
+            "ts" => Some("tunm_proto.ts"),
+            "cs" => Some("TunmProto.cs"),
+            "lua" => Some("tunm_proto.lua"),
+            _ => None,
+        }
+    }
+
+    pub fn generate(schema: &ProtoSchema, lang: &str) -> NetResult<String> {
+        match lang {
+            "ts" => Ok(ProtoCodegen::generate_ts(schema)),
+            "cs" => Ok(ProtoCodegen::generate_cs(schema)),
+            "lua" => Ok(ProtoCodegen::generate_lua(schema)),
+            _ => Err(make_extension_error("codegen unknow lang", Some(lang))),
+        }
+    }
+
+    pub fn generate_ts(schema: &ProtoSchema) -> String {
+        let mut fields = String::new();
+        let _ = writeln!(fields, "const FIELD_PATTERNS: {{ [key: string]: string }} = {{");
+        for (name, pattern) in sorted_fields(schema) {
+            let _ = writeln!(fields, "    {}: {},", quote(name), quote(&pattern.get_name()));
+        }
+        let _ = writeln!(fields, "}};\n");
+        let _ = writeln!(fields, "/// the map value declared by the field of protocol.txt");
+        let _ = writeln!(fields, "export interface ProtoFields extends ProtoMap {{");
+        for (name, pattern) in sorted_fields(schema) {
+            let _ = writeln!(fields, "    {}?: {};", quote(name), ts_type(pattern));
+        }
+        let _ = writeln!(fields, "}}");
+
+        let protos = sorted_protos(schema);
+        let mut messages = String::new();
+        let _ = writeln!(messages, "export const PROTO_ARGS: {{ [name: string]: string[] }} = {{");
+        for &(name, proto) in &protos {
+            let args: Vec<String> = proto.args.iter().map(|a| quote(&a.get_name())).collect();
+            let _ = writeln!(messages, "    {}: [{}],", quote(name), args.join(", "));
+        }
+        let _ = writeln!(messages, "}};");
+        for &(name, proto) in &protos {
+            let pascal = pascal_name(name);
+            let types: Vec<String> = proto.args.iter().map(ts_type).collect();
+            let params: Vec<String> = types.iter().enumerate().map(|(i, t)| format!("arg{}: {}", i + 1, t)).collect();
+            let args: Vec<String> = (0..types.len()).map(|i| format!("arg{}", i + 1)).collect();
+            let _ = writeln!(messages, "\n/// {}", proto_desc(name, proto));
+            let _ = writeln!(messages, "export interface {}Message {{", pascal);
+            let _ = writeln!(messages, "    head: NetMsgHead;");
+            let _ = writeln!(messages, "    name: {};", quote(name));
+            let _ = writeln!(messages, "    args: [{}];", types.join(", "));
+            let _ = writeln!(messages, "}}\n");
+            let mut params = params;
+            params.push("head?: Partial<NetMsgHead>".to_string());
+            let _ = writeln!(messages, "export function encode{}({}): Uint8Array {{", pascal, params.join(", "));
+            let _ = writeln!(messages, "    return encodeMessage({}, [{}], PROTO_ARGS[{}], head);", quote(name), args.join(", "), quote(name));
+            let _ = writeln!(messages, "}}");
+        }
+        let union: Vec<String> = protos.iter().map(|&(name, _)| format!("{}Message", pascal_name(name))).collect();
+        let _ = writeln!(messages, "\nexport type ProtoMessage = {};\n", if union.is_empty() { "never".to_string() } else { union.join("\n    | ") });
+        let _ = writeln!(messages, "/// decode the message declared in protocol.txt, throw if the message unknow");
+        let _ = writeln!(messages, "export function decodeProto(data: Uint8Array): ProtoMessage {{");
+        let _ = writeln!(messages, "    const message = decodeMessage(data);");
+        let _ = writeln!(messages, "    if (!(message.name in PROTO_ARGS)) {{");
+        let _ = writeln!(messages, "        throw new Error(\"tunm proto unknow message \" + message.name);");
+        let _ = writeln!(messages, "    }}");
+        let _ = writeln!(messages, "    return message as ProtoMessage;");
+        let _ = writeln!(messages, "}}");
+        fill_template(RUNTIME_TS, "/*@FIELDS@*/", &fields, "/*@MESSAGES@*/", &messages)
+    }
+
+    pub fn generate_cs(schema: &ProtoSchema) -> String {
+        let protos = sorted_protos(schema);
+        let mut fields = String::new();
+        let _ = writeln!(fields, "    public static partial class TunmProto");
+        let _ = writeln!(fields, "    {{");
+        let _ = writeln!(fields, "        static readonly Dictionary<string, string> FieldPatterns = new Dictionary<string, string>");
+        let _ = writeln!(fields, "        {{");
+        for (name, pattern) in sorted_fields(schema) {
+            let _ = writeln!(fields, "            {{ {}, {} }},", quote(name), quote(&pattern.get_name()));
+        }
+        let _ = writeln!(fields, "        }};\n");
+        let _ = writeln!(fields, "        public static readonly Dictionary<string, string[]> ProtoArgs = new Dictionary<string, string[]>");
+        let _ = writeln!(fields, "        {{");
+        for &(name, proto) in &protos {
+            let args: Vec<String> = proto.args.iter().map(|a| quote(&a.get_name())).collect();
+            let _ = writeln!(fields, "            {{ {}, new string[] {{ {} }} }},", quote(name), args.join(", "));
+        }
+        let _ = writeln!(fields, "        }};");
+        let _ = writeln!(fields, "    }}");
+
+        let mut messages = String::new();
+        for &(name, proto) in &protos {
+            let pascal = pascal_name(name);
+            let _ = writeln!(messages, "    /// {}", proto_desc(name, proto));
+            let _ = writeln!(messages, "    public sealed class {}", pascal);
+            let _ = writeln!(messages, "    {{");
+            let _ = writeln!(messages, "        public const string Name = {};", quote(name));
+            for (i, pattern) in proto.args.iter().enumerate() {
+                let _ = writeln!(messages, "        public {} Arg{};", cs_type(pattern), i + 1);
+            }
+            let args: Vec<String> = (0..proto.args.len()).map(|i| format!("Arg{}", i + 1)).collect();
+            let _ = writeln!(messages, "\n        public byte[] Encode(NetMsgHead head = null)");
+            let _ = writeln!(messages, "        {{");
+            let _ = writeln!(messages, "            return TunmProto.EncodeMessage(Name, new List<object> {{ {} }}, TunmProto.ProtoArgs[Name], head);", args.join(", "));
+            let _ = writeln!(messages, "        }}\n");
+            let _ = writeln!(messages, "        public static {} From(ProtoMessage message)", pascal);
+            let _ = writeln!(messages, "        {{");
+            let _ = writeln!(messages, "            {} msg = new {}();", pascal, pascal);
+            for (i, pattern) in proto.args.iter().enumerate() {
+                let value = format!("TunmProto.Arg(message, {})", i);
+                let convert = if pattern.is_array {
+                    format!("TunmProto.ToList({}, v => {})", value, cs_convert(pattern.ty, "v"))
+                } else {
+                    cs_convert(pattern.ty, &value)
+                };
+                let _ = writeln!(messages, "            msg.Arg{} = {};", i + 1, convert);
+            }
+            let _ = writeln!(messages, "            return msg;");
+            let _ = writeln!(messages, "        }}");
+            let _ = writeln!(messages, "    }}\n");
+        }
+        fill_template(RUNTIME_CS, "/*@FIELDS@*/", &fields, "/*@MESSAGES@*/", &messages)
+    }
+
+    pub fn generate_lua(schema: &ProtoSchema) -> String {
+        let protos = sorted_protos(schema);
+        let mut fields = String::new();
+        let _ = writeln!(fields, "FIELD_PATTERNS = {{");
+        for (name, pattern) in sorted_fields(schema) {
+            let _ = writeln!(fields, "    [{}] = {},", quote(name), quote(&pattern.get_name()));
+        }
+        let _ = writeln!(fields, "}}");
+        let _ = writeln!(fields, "M.FIELD_PATTERNS = FIELD_PATTERNS");
+
+        let mut messages = String::new();
+        let _ = writeln!(messages, "PROTO_ARGS = {{");
+        for &(name, proto) in &protos {
+            let args: Vec<String> = proto.args.iter().map(|a| quote(&a.get_name())).collect();
+            let _ = writeln!(messages, "    [{}] = {{ {} }},", quote(name), args.join(", "));
+        }
+        let _ = writeln!(messages, "}}");
+        let _ = writeln!(messages, "M.PROTO_ARGS = PROTO_ARGS");
+        for &(name, proto) in &protos {
+            let mut params: Vec<String> = (0..proto.args.len()).map(|i| format!("arg{}", i + 1)).collect();
+            let args = params.join(", ");
+            params.push("head".to_string());
+            let _ = writeln!(messages, "\n--- {}", proto_desc(name, proto));
+            let _ = writeln!(messages, "function M.encode_{}({})", snake_name(name), params.join(", "));
+            let _ = writeln!(messages, "    return M.encode_message({}, {{ {} }}, PROTO_ARGS[{}], head, {})", quote(name), args, quote(name), proto.args.len());
+            let _ = writeln!(messages, "end");
+        }
+        fill_template(RUNTIME_LUA, "--@FIELDS@", &fields, "--@MESSAGES@", &messages)
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::fmt;
+use super::{ProtoSchema, SchemaType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompatKind {
+    FieldAdded,
+    FieldRemoved,
+    /// the index used by the other field in the old protocol
+    FieldIndexReused,
+    FieldIndexChanged,
+    FieldTypeChanged,
+    ProtoAdded,
+    ProtoRemoved,
+    ProtoMsgTypeChanged,
+    ProtoArgAdded,
+    ProtoArgRemoved,
+    ProtoArgTypeChanged,
+}
+
+/// one difference between the old and the new protocol, the breaking change make the old
+/// client or server can't talk with the new one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatChange {
+    pub kind: CompatKind,
+    pub breaking: bool,
+    /// the field or proto name
+    pub name: String,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CompatReport {
+    pub changes: Vec<CompatChange>,
+}
+
+/// diff two protocol.txt, such as
+/// `ProtoCompat::diff(&ProtoSchema::load("old/protocol.txt")?, &ProtoSchema::load("config/protocol.txt")?)`
+pub struct ProtoCompat;
+
+impl CompatKind {
+    pub fn get_name(&self) -> &'static str {
+        match *self {
+            CompatKind::FieldAdded => "field_added",
+            CompatKind::FieldRemoved => "field_removed",
+            CompatKind::FieldIndexReused => "field_index_reused",
+            CompatKind::FieldIndexChanged => "field_index_changed",
+            CompatKind::FieldTypeChanged => "field_type_changed",
+            CompatKind::ProtoAdded => "proto_added",
+            CompatKind::ProtoRemoved => "proto_removed",
+            CompatKind::ProtoMsgTypeChanged => "proto_msg_type_changed",
+            CompatKind::ProtoArgAdded => "proto_arg_added",
+            CompatKind::ProtoArgRemoved => "proto_arg_removed",
+            CompatKind::ProtoArgTypeChanged => "proto_arg_type_changed",
+        }
+    }
+}
+
+impl fmt::Display for CompatChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {} {}", if self.breaking { "breaking" } else { "compatible" }, self.kind.get_name(), self.detail)
+    }
+}
+
+impl CompatReport {
+    fn add(&mut self, kind: CompatKind, breaking: bool, name: &str, detail: String) {
+        self.changes.push(CompatChange { kind, breaking, name: name.to_string(), detail });
+    }
+
+    pub fn is_breaking(&self) -> bool {
+        self.changes.iter().any(|c| c.breaking)
+    }
+
+    pub fn get_breaking(&self) -> Vec<&CompatChange> {
+        self.changes.iter().filter(|c| c.breaking).collect()
+    }
+
+    pub fn get_compatible(&self) -> Vec<&CompatChange> {
+        self.changes.iter().filter(|c| !c.breaking).collect()
+    }
+}
+
+fn sorted_keys<V>(map: &HashMap<String, V>) -> Vec<&String> {
+    let mut keys: Vec<_> = map.keys().collect();
+    keys.sort();
+    keys
+}
+
+impl ProtoCompat {
+    /// the changes sorted by the field and proto name, the type change is compatible only when
+    /// the new type accept all the values of the old type, such as `u8` to `u16`
+    pub fn diff(old: &ProtoSchema, new: &ProtoSchema) -> CompatReport {
+        let mut report = CompatReport::default();
+        ProtoCompat::diff_fields(old, new, &mut report);
+        ProtoCompat::diff_protos(old, new, &mut report);
+        report
+    }
+
+    fn diff_fields(old: &ProtoSchema, new: &ProtoSchema, report: &mut CompatReport) {
+        let mut old_index_names = HashMap::new();
+        for name in sorted_keys(old.get_fields()) {
+            if let Some(index) = old.get_field_index(name) {
+                old_index_names.entry(index).or_insert(name);
+            }
+        }
+
+        // the old message encode the arg declared by the field name, so the field still used
+        for name in sorted_keys(old.get_fields()) {
+            if new.get_field(name).is_some() {
+                continue;
+            }
+            let mut users: Vec<&String> = old.get_protos().iter()
+                .filter(|(_, proto)| proto.arg_fields.iter().any(|f| f.as_ref() == Some(name)))
+                .map(|(proto_name, _)| proto_name)
+                .collect();
+            users.sort();
+            if users.is_empty() {
+                report.add(CompatKind::FieldRemoved, false, name, format!("field {} removed", name));
+            } else {
+                let users: Vec<&str> = users.iter().map(|u| u.as_str()).collect();
+                report.add(CompatKind::FieldRemoved, true, name,
+                    format!("field {} removed but used by the args of {}", name, users.join(", ")));
+            }
+        }
+
+        for name in sorted_keys(new.get_fields()) {
+            let pattern = &new.get_fields()[name];
+            let index = new.get_field_index(name);
+            if let Some(index) = index {
+                if let Some(old_name) = old_index_names.get(&index) {
+                    if *old_name != name {
+                        report.add(CompatKind::FieldIndexReused, true, name,
+                            format!("field {} index {} used by {} in old", name, index, old_name));
+                    }
+                }
+            }
+            let old_pattern = match old.get_field(name) {
+                Some(old_pattern) => old_pattern,
+                None => {
+                    report.add(CompatKind::FieldAdded, false, name, format!("field {} added as {}", name, pattern.get_name()));
+                    continue;
+                }
+            };
+            let old_index = old.get_field_index(name);
+            if old_index != index {
+                report.add(CompatKind::FieldIndexChanged, true, name,
+                    format!("field {} index {:?} to {:?}", name, old_index, index));
+            }
+            if old_pattern != pattern {
+                report.add(CompatKind::FieldTypeChanged, !pattern.accept(old_pattern), name,
+                    format!("field {} {} to {}", name, old_pattern.get_name(), pattern.get_name()));
+            }
+        }
+    }
+
+    fn diff_protos(old: &ProtoSchema, new: &ProtoSchema, report: &mut CompatReport) {
+        for name in sorted_keys(old.get_protos()) {
+            if new.get_proto(name).is_none() {
+                report.add(CompatKind::ProtoRemoved, true, name, format!("proto {} removed", name));
+            }
+        }
+
+        for name in sorted_keys(new.get_protos()) {
+            let proto = &new.get_protos()[name];
+            let old_proto = match old.get_proto(name) {
+                Some(old_proto) => old_proto,
+                None => {
+                    report.add(CompatKind::ProtoAdded, false, name, format!("proto {} added", name));
+                    continue;
+                }
+            };
+            if old_proto.msg_type != proto.msg_type {
+                report.add(CompatKind::ProtoMsgTypeChanged, true, name,
+                    format!("proto {} msg_type {} to {}", name, old_proto.msg_type, proto.msg_type));
+            }
+            for (i, old_pattern) in old_proto.args.iter().enumerate() {
+                match proto.args.get(i) {
+                    None => {
+                        report.add(CompatKind::ProtoArgRemoved, true, name,
+                            format!("proto {} arg {} {} removed", name, i + 1, old_pattern.get_name()));
+                    }
+                    Some(pattern) if pattern != old_pattern => {
+                        report.add(CompatKind::ProtoArgTypeChanged, !pattern.accept(old_pattern), name,
+                            format!("proto {} arg {} {} to {}", name, i + 1, old_pattern.get_name(), pattern.get_name()));
+                    }
+                    _ => (),
+                }
+            }
+            // the old message without the new arg is valid only when the new arg can be nil
+            for (i, pattern) in proto.args.iter().enumerate().skip(old_proto.args.len()) {
+                report.add(CompatKind::ProtoArgAdded, pattern.ty != SchemaType::None, name,
+                    format!("proto {} arg {} {} added", name, i + 1, pattern.get_name()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(fields: &str, protos: &str) -> ProtoSchema {
+        ProtoSchema::parse(&format!("{{\"field\": {{{}}}, \"proto\": {{{}}}}}", fields, protos)).unwrap()
+    }
+
+    fn changes(report: &CompatReport) -> Vec<(CompatKind, bool, &str)> {
+        report.changes.iter().map(|c| (c.kind, c.breaking, c.name.as_str())).collect()
+    }
+
+    #[test]
+    fn field_added_and_removed() {
+        let old = schema(r#""name": {"index": 1, "pattern": "str"}, "level": {"index": 2, "pattern": "u8"}"#,
+            r#""cmd_login": {"msg_type": "logic", "args": ["str"]}"#);
+        let new = schema(r#""level": {"index": 2, "pattern": "u8"}, "exp": {"index": 3, "pattern": "u32"}"#,
+            r#""cmd_login": {"msg_type": "logic", "args": ["str"]}, "cmd_logout": {"msg_type": "logic", "args": []}"#);
+        let report = ProtoCompat::diff(&old, &new);
+        assert_eq!(changes(&report), vec![
+            (CompatKind::FieldRemoved, false, "name"),
+            (CompatKind::FieldAdded, false, "exp"),
+            (CompatKind::ProtoAdded, false, "cmd_logout"),
+        ]);
+        assert!(!report.is_breaking());
+
+        // the removed field still used by the args of the old proto
+        let old = schema(r#""name": {"index": 1, "pattern": "str"}, "level": {"index": 2, "pattern": "u8"}"#,
+            r#""cmd_login": {"msg_type": "logic", "args": ["name"]}"#);
+        let new = schema(r#""level": {"index": 2, "pattern": "u8"}"#,
+            r#""cmd_login": {"msg_type": "logic", "args": ["str"]}"#);
+        let report = ProtoCompat::diff(&old, &new);
+        assert_eq!(changes(&report), vec![(CompatKind::FieldRemoved, true, "name")]);
+        assert!(report.changes[0].detail.contains("cmd_login"));
+    }
+
+    #[test]
+    fn field_retyped() {
+        let old = schema(r#""level": {"index": 1, "pattern": "u8"}, "name": {"index": 2, "pattern": "str"}"#, "");
+        let new = schema(r#""level": {"index": 1, "pattern": "u16"}, "name": {"index": 2, "pattern": "u32"}"#, "");
+        let report = ProtoCompat::diff(&old, &new);
+        assert_eq!(changes(&report), vec![
+            (CompatKind::FieldTypeChanged, false, "level"),
+            (CompatKind::FieldTypeChanged, true, "name"),
+        ]);
+        // the narrow type can't hold the old value
+        let report = ProtoCompat::diff(&new, &old);
+        assert!(report.changes.iter().all(|c| c.breaking));
+    }
+
+    #[test]
+    fn field_index_changed_and_reused() {
+        let old = schema(r#""level": {"index": 1, "pattern": "u8"}, "name": {"index": 2, "pattern": "str"}"#, "");
+        let new = schema(r#""level": {"index": 3, "pattern": "u8"}, "exp": {"index": 1, "pattern": "u32"}"#, "");
+        let report = ProtoCompat::diff(&old, &new);
+        assert_eq!(changes(&report), vec![
+            (CompatKind::FieldRemoved, false, "name"),
+            (CompatKind::FieldIndexReused, true, "exp"),
+            (CompatKind::FieldAdded, false, "exp"),
+            (CompatKind::FieldIndexChanged, true, "level"),
+        ]);
+        assert!(report.is_breaking());
+        assert_eq!(report.get_compatible().len(), 2);
+    }
+
+    #[test]
+    fn proto_args_changed() {
+        let old = schema("", r#""cmd_login": {"msg_type": "logic", "args": ["u8", "str"]}"#);
+        let new = schema("", r#""cmd_login": {"msg_type": "logic", "args": ["u16", "str", "none", "map"]}"#);
+        let report = ProtoCompat::diff(&old, &new);
+        let kinds: Vec<(CompatKind, bool)> = report.changes.iter().map(|c| (c.kind, c.breaking)).collect();
+        assert_eq!(kinds, vec![
+            (CompatKind::ProtoArgTypeChanged, false),
+            (CompatKind::ProtoArgAdded, false),
+            (CompatKind::ProtoArgAdded, true),
+        ]);
+
+        let new = schema("", r#""cmd_login": {"msg_type": "gate", "args": ["u8"]}"#);
+        let report = ProtoCompat::diff(&old, &new);
+        let kinds: Vec<CompatKind> = report.changes.iter().map(|c| c.kind).collect();
+        assert_eq!(kinds, vec![CompatKind::ProtoMsgTypeChanged, CompatKind::ProtoArgRemoved]);
+        assert!(report.is_breaking());
+    }
+
+    #[test]
+    fn old_duplicate_key_last_wins() {
+        let data = r#"{"field": {"level": {"index": 1, "pattern": "u8"}, "level": {"index": 1, "pattern": "u16"}}}"#;
+        assert!(ProtoSchema::parse(data).is_err());
+        let (old, duplicates) = ProtoSchema::parse_lenient(data).unwrap();
+        assert_eq!(duplicates, vec!["duplicate field \"level\"".to_string()]);
+        let new = schema(r#""level": {"index": 1, "pattern": "u16"}"#, "");
+        assert!(ProtoCompat::diff(&old, &new).changes.is_empty());
+    }
+}
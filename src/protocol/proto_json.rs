@@ -1,29 +1,36 @@
 use tunm_proto::*;
-use td_rlua::{self, Lua, LuaPush};
+use td_rlua::{self, LuaPush};
+use serde_json::{self, Map};
+use serde_json::Value as JsonValue;
 use super::EngineProtocol;
-use {NetMsg, MSG_TYPE_JSON};
-use {NetResult, LuaWrapperValue};
+use {NetMsg, MSG_TYPE_JSON, make_extension_error};
+use {NetResult, LuaWrapperValue, JsonUtils, GlobalConfig};
 
 pub struct ProtoJson;
 
 impl EngineProtocol for ProtoJson {
-    ///depend lua function arg_to_encode
-    fn pack_protocol(lua: *mut td_rlua::lua_State, index: i32) -> Option<NetMsg> {
-        unsafe {
-            for i in 1 .. index {
-                td_rlua::lua_remove(lua, i);
-            }
-
-            let name: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, 1), return None);
+    fn get_name(&self) -> &str {
+        "json"
+    }
 
-            let mut lua = Lua::from_existing_state(lua, false);
-            let json: String = unwrap_or!(lua.exec_func("arg_to_encode"), return None);
-            let net_msg = NetMsg::new_by_detail(MSG_TYPE_JSON, name, &json.as_bytes()[..]);
-            Some(net_msg)
+    /// the json is the array `[name, args...]`, the unpack push the json string
+    fn pack_protocol(&mut self, lua: *mut td_rlua::lua_State, index: i32) -> Option<NetMsg> {
+        let name: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, index), return None);
+        let empty_as_array = GlobalConfig::instance().json_empty_array.unwrap_or(false);
+        let json = JsonUtils::lua_args_to_json(lua, index, empty_as_array);
+        if json.is_none() {
+            println!("data convert failed name = {:?}", name);
+            return None;
+        }
+        let json = JsonUtils::to_string(&json.unwrap());
+        if json.len() > 0xFFFF {
+            println!("pack message({}) size > 0xFFFF fail!", name);
+            return None;
         }
+        NetMsg::new_by_detail(MSG_TYPE_JSON, name, json.as_bytes()).ok()
     }
 
-    fn unpack_protocol(lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<i32> {
+    fn unpack_protocol(&mut self, lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<i32> {
         net_msg.set_read_data();
         let name: String = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?.into();
         let raw: Value = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?;
@@ -32,10 +39,63 @@ impl EngineProtocol for ProtoJson {
         return Ok(2);
     }
 
-    fn convert_string(_: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<String> {
+    fn convert_string(&mut self, _: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<String> {
         net_msg.set_read_data();
         let _: String = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?.into();
         let raw: String = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?.into();
         return Ok(raw);
     }
-}
\ No newline at end of file
+}
+
+impl ProtoJson {
+    /// the text is packed as the data of the message, the longer text can't be packed,
+    /// the websocket close the text frame bigger than it with 1009
+    pub const MAX_TEXT_LEN: usize = 0xFFFF;
+
+    /// the websocket text frame `{"name": name, "args": [args...]}` or `[name, args...]` to the
+    /// json message, the args not array is the only one arg
+    pub fn new_by_text(text: &str) -> NetResult<NetMsg> {
+        let value: JsonValue = serde_json::from_str(text)
+            .map_err(|err| make_extension_error("parse json text failed", Some(&err.to_string())))?;
+        let list = match value {
+            JsonValue::Array(list) => list,
+            JsonValue::Object(mut map) => {
+                let name = unwrap_or!(map.remove("name"), JsonValue::Null);
+                match map.remove("args") {
+                    Some(JsonValue::Array(mut args)) => {
+                        args.insert(0, name);
+                        args
+                    }
+                    None | Some(JsonValue::Null) => vec![name],
+                    Some(arg) => vec![name, arg],
+                }
+            }
+            _ => vec![],
+        };
+        let name = match list.first() {
+            Some(JsonValue::String(name)) => name.clone(),
+            _ => return Err(make_extension_error("json text no message name", None)),
+        };
+        let json = JsonUtils::to_string(&JsonValue::Array(list));
+        NetMsg::new_by_detail(MSG_TYPE_JSON, name, json.as_bytes())
+    }
+
+    /// the json message to the websocket text frame `{"name": name, "args": [args...]}`
+    pub fn to_text(net_msg: &mut NetMsg) -> NetResult<String> {
+        net_msg.set_read_data();
+        let name: String = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?.into();
+        let json: String = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?.into();
+        net_msg.set_read_data();
+        let mut args: Vec<JsonValue> = match serde_json::from_str(&json) {
+            Ok(JsonValue::Array(args)) => args,
+            _ => return Err(make_extension_error("json message not array", None)),
+        };
+        if !args.is_empty() {
+            args.remove(0);
+        }
+        let mut map = Map::new();
+        map.insert("name".to_string(), JsonValue::String(name));
+        map.insert("args".to_string(), JsonValue::Array(args));
+        Ok(JsonUtils::to_string(&JsonValue::Object(map)))
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use rmpv;
+use tunm_proto::*;
+use td_rlua::{self, LuaPush, LuaRead};
+use super::EngineProtocol;
+use {NetMsg, MSG_TYPE_MSGPACK, LuaUtils};
+use {NetResult, LuaWrapperValue, LuaWrapperTableValue, make_extension_error};
+
+/// the lua table may reference itself, stop the convert at this depth
+const MAX_DEPTH: usize = 64;
+
+/// the messagepack message, the args after the name are packed as the msgpack array,
+/// the sequence table is the array and the other table is the map, the empty table is the map,
+/// the string not utf8 is the binary
+pub struct ProtoMsgpack;
+
+fn lua_to_msgpack(lua: *mut td_rlua::lua_State, index: i32, depth: usize) -> Option<rmpv::Value> {
+    unsafe {
+        let value = match td_rlua::lua_type(lua, index) {
+            td_rlua::LUA_TBOOLEAN => rmpv::Value::Boolean(td_rlua::lua_toboolean(lua, index) != 0),
+            td_rlua::LUA_TNUMBER => {
+                if LuaUtils::is_integer(lua, index) {
+                    let val: i64 = unwrap_or!(LuaRead::lua_read_at_position(lua, index), return None);
+                    rmpv::Value::from(val)
+                } else {
+                    let val: f64 = unwrap_or!(LuaRead::lua_read_at_position(lua, index), return None);
+                    rmpv::Value::F64(val)
+                }
+            }
+            td_rlua::LUA_TSTRING => {
+                let val = unwrap_or!(LuaUtils::read_str_to_vec(lua, index), return None);
+                match String::from_utf8(val) {
+                    Ok(val) => rmpv::Value::from(val),
+                    Err(err) => rmpv::Value::Binary(err.into_bytes()),
+                }
+            }
+            td_rlua::LUA_TTABLE => {
+                if depth > MAX_DEPTH || td_rlua::lua_checkstack(lua, 3) == 0 {
+                    println!("msgpack table nested too deep");
+                    return None;
+                }
+                let index = td_rlua::lua_absindex(lua, index);
+                match LuaUtils::get_array_len(lua, index) {
+                    Some(len) if len > 0 => {
+                        let mut val = Vec::with_capacity(len);
+                        for i in 1..(len + 1) {
+                            td_rlua::lua_rawgeti(lua, index, i as i32);
+                            let sub_val = lua_to_msgpack(lua, -1, depth + 1);
+                            td_rlua::lua_settop(lua, -2);
+                            val.push(sub_val?);
+                        }
+                        rmpv::Value::Array(val)
+                    }
+                    _ => {
+                        let mut val = vec![];
+                        td_rlua::lua_pushnil(lua);
+                        while td_rlua::lua_next(lua, index) != 0 {
+                            let sub_val = lua_to_msgpack(lua, -1, depth + 1);
+                            let key = lua_to_msgpack(lua, -2, depth + 1);
+                            td_rlua::lua_settop(lua, -2);
+                            match (key, sub_val) {
+                                (Some(key), Some(sub_val)) => val.push((key, sub_val)),
+                                _ => {
+                                    td_rlua::lua_settop(lua, -2);
+                                    return None;
+                                }
+                            }
+                        }
+                        rmpv::Value::Map(val)
+                    }
+                }
+            }
+            _ => rmpv::Value::Nil,
+        };
+        Some(value)
+    }
+}
+
+/// all the args begin at index as the msgpack array
+fn lua_args_to_msgpack(lua: *mut td_rlua::lua_State, index: i32) -> Option<rmpv::Value> {
+    let size = unsafe { td_rlua::lua_gettop(lua) };
+    let mut args = vec![];
+    for i in index..(size + 1) {
+        args.push(lua_to_msgpack(lua, i, 0)?);
+    }
+    Some(rmpv::Value::Array(args))
+}
+
+fn msgpack_to_value(value: rmpv::Value) -> Value {
+    match value {
+        rmpv::Value::Nil => Value::Nil,
+        rmpv::Value::Boolean(val) => Value::Bool(val),
+        rmpv::Value::Integer(val) => {
+            match val.as_i64() {
+                Some(val) => Value::I64(val),
+                None => Value::U64(val.as_u64().unwrap_or(0)),
+            }
+        }
+        rmpv::Value::F32(val) => Value::Float(val),
+        rmpv::Value::F64(val) => Value::Double(val),
+        rmpv::Value::String(val) => {
+            if val.is_str() {
+                Value::Str(val.into_str().unwrap_or_default())
+            } else {
+                Value::Raw(val.into_bytes())
+            }
+        }
+        rmpv::Value::Binary(val) => Value::Raw(val),
+        rmpv::Value::Array(val) => Value::Arr(val.into_iter().map(msgpack_to_value).collect()),
+        rmpv::Value::Map(val) => {
+            let mut map = HashMap::new();
+            for (k, v) in val {
+                map.insert(msgpack_to_value(k), msgpack_to_value(v));
+            }
+            Value::Map(map)
+        }
+        rmpv::Value::Ext(_, val) => Value::Raw(val),
+    }
+}
+
+fn decode_msgpack(net_msg: &mut NetMsg) -> NetResult<(String, rmpv::Value)> {
+    net_msg.set_read_data();
+    let name: String = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?.into();
+    let raw = match decode_str_raw(net_msg.get_buffer(), TYPE_RAW)? {
+        Value::Raw(raw) => raw,
+        _ => vec![],
+    };
+    match rmpv::decode::read_value(&mut &raw[..]) {
+        Ok(value) => Ok((name, value)),
+        Err(err) => Err(make_extension_error("decode msgpack failed", Some(&err.to_string()))),
+    }
+}
+
+impl EngineProtocol for ProtoMsgpack {
+    fn get_name(&self) -> &str {
+        "msgpack"
+    }
+
+    fn pack_protocol(&mut self, lua: *mut td_rlua::lua_State, index: i32) -> Option<NetMsg> {
+        let name: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, index), return None);
+        let args = lua_args_to_msgpack(lua, index + 1);
+        if args.is_none() {
+            println!("data convert failed name = {:?}", name);
+            return None;
+        }
+        let mut data = vec![];
+        unwrap_or!(rmpv::encode::write_value(&mut data, &args.unwrap()).ok(), return None);
+        if data.len() > 0xFFFF {
+            println!("pack message({}) size > 0xFFFF fail!", name);
+            return None;
+        }
+        NetMsg::new_by_detail(MSG_TYPE_MSGPACK, name, &data[..]).ok()
+    }
+
+    fn unpack_protocol(&mut self, lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<i32> {
+        let (name, value) = decode_msgpack(net_msg)?;
+        name.push_to_lua(lua);
+        match msgpack_to_value(value) {
+            Value::Arr(args) => LuaWrapperTableValue(args).push_to_lua(lua),
+            value => LuaWrapperValue(value).push_to_lua(lua),
+        };
+        Ok(2)
+    }
+
+    fn convert_string(&mut self, _: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<String> {
+        let (name, value) = decode_msgpack(net_msg)?;
+        Ok(format!("{} {}", name, value))
+    }
+}
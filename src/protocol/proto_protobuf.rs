@@ -0,0 +1,385 @@
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+use libc;
+use prost::Message;
+use prost::bytes::Bytes;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor, FieldDescriptor, Kind, MapKey, ReflectMessage};
+use prost_reflect::Value as PbValue;
+use tunm_proto::*;
+use td_rlua::{self, LuaPush, LuaRead};
+use super::EngineProtocol;
+use {NetMsg, MSG_TYPE_PROTOBUF, LuaUtils};
+use {NetResult, make_extension_error};
+
+/// the lua table may reference itself, stop the convert at this depth
+const MAX_DEPTH: usize = 64;
+
+/// the protobuf message, the pack_name is the full name of the message such as `game.Login`,
+/// the descriptors are loaded from the .proto files or the compiled descriptor set,
+/// lua table field name is the protobuf field name, the enum is the number or the value name
+pub struct ProtoProtobuf {
+    pool: DescriptorPool,
+}
+
+impl Default for ProtoProtobuf {
+    fn default() -> ProtoProtobuf {
+        ProtoProtobuf::new()
+    }
+}
+
+impl ProtoProtobuf {
+    pub fn new() -> ProtoProtobuf {
+        ProtoProtobuf {
+            pool: DescriptorPool::new(),
+        }
+    }
+
+    pub fn get_pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
+    /// load the compiled descriptor set, such as `protoc --descriptor_set_out=game.pb --include_imports`
+    pub fn load_descriptor_set(&mut self, data: &[u8]) -> NetResult<()> {
+        let mut pool = self.pool.clone();
+        if let Err(err) = pool.decode_file_descriptor_set(data) {
+            return Err(make_extension_error("load descriptor set failed", Some(&err.to_string())));
+        }
+        self.pool = pool;
+        Ok(())
+    }
+
+    /// compile the .proto files, the import will be searched in the includes
+    pub fn load_proto_files(&mut self, files: &[String], includes: &[String]) -> NetResult<()> {
+        let set = match protox::compile(files, includes) {
+            Ok(set) => set,
+            Err(err) => return Err(make_extension_error("compile proto files failed", Some(&err.to_string()))),
+        };
+        let mut pool = self.pool.clone();
+        if let Err(err) = pool.add_file_descriptor_set(set) {
+            return Err(make_extension_error("load proto files failed", Some(&err.to_string())));
+        }
+        self.pool = pool;
+        Ok(())
+    }
+
+    /// the path is a .proto file, a directory of .proto files or the compiled descriptor set
+    pub fn load_path(&mut self, path: &str) -> NetResult<()> {
+        let file_path = Path::new(path);
+        if file_path.is_dir() {
+            let mut files = vec![];
+            for entry in fs::read_dir(file_path)?.flatten() {
+                let entry_path = entry.path();
+                if entry_path.extension().map(|e| e == "proto").unwrap_or(false) {
+                    files.push(entry_path.to_string_lossy().to_string());
+                }
+            }
+            files.sort();
+            return self.load_proto_files(&files, &[path.to_string()]);
+        }
+
+        if file_path.extension().map(|e| e == "proto").unwrap_or(false) {
+            let include = file_path.parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+            let include = if include.is_empty() { ".".to_string() } else { include };
+            return self.load_proto_files(&[path.to_string()], &[include]);
+        }
+
+        let data = fs::read(file_path)?;
+        self.load_descriptor_set(&data)
+    }
+
+    fn find_message(&self, name: &str) -> NetResult<MessageDescriptor> {
+        match self.pool.get_message_by_name(name) {
+            Some(desc) => Ok(desc),
+            None => Err(make_extension_error("unknow protobuf message", Some(name))),
+        }
+    }
+
+    fn decode_message(&self, net_msg: &mut NetMsg) -> NetResult<(String, DynamicMessage)> {
+        net_msg.set_read_data();
+        let name: String = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?.into();
+        let raw = match decode_str_raw(net_msg.get_buffer(), TYPE_RAW)? {
+            Value::Raw(raw) => raw,
+            _ => vec![],
+        };
+        let desc = self.find_message(&name)?;
+        match DynamicMessage::decode(desc, &raw[..]) {
+            Ok(message) => Ok((name, message)),
+            Err(err) => Err(make_extension_error("decode protobuf failed", Some(&err.to_string()))),
+        }
+    }
+}
+
+fn lua_type_name(lua: *mut td_rlua::lua_State, index: i32) -> String {
+    unsafe {
+        let name = td_rlua::lua_typename(lua, td_rlua::lua_type(lua, index));
+        ::std::ffi::CStr::from_ptr(name).to_string_lossy().to_string()
+    }
+}
+
+fn type_error(field: &str, expect: &str, lua: *mut td_rlua::lua_State, index: i32) -> String {
+    format!("field {} expect {} but {}", field, expect, lua_type_name(lua, index))
+}
+
+fn is_type(lua: *mut td_rlua::lua_State, index: i32, ty: libc::c_int) -> bool {
+    unsafe { td_rlua::lua_type(lua, index) == ty }
+}
+
+fn read_integer(lua: *mut td_rlua::lua_State, index: i32, field: &str) -> Result<i64, String> {
+    if !is_type(lua, index, td_rlua::LUA_TNUMBER) {
+        return Err(type_error(field, "integer", lua, index));
+    }
+    match i64::lua_read_at_position(lua, index) {
+        Some(val) => Ok(val),
+        None => Err(format!("field {} expect integer but float", field)),
+    }
+}
+
+fn read_number(lua: *mut td_rlua::lua_State, index: i32, field: &str) -> Result<f64, String> {
+    if !is_type(lua, index, td_rlua::LUA_TNUMBER) {
+        return Err(type_error(field, "number", lua, index));
+    }
+    Ok(f64::lua_read_at_position(lua, index).unwrap_or(0.0))
+}
+
+fn lua_to_single(lua: *mut td_rlua::lua_State, index: i32, kind: &Kind, field: &str, depth: usize) -> Result<PbValue, String> {
+    let value = match *kind {
+        Kind::Double => PbValue::F64(read_number(lua, index, field)?),
+        Kind::Float => PbValue::F32(read_number(lua, index, field)? as f32),
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => PbValue::I32(read_integer(lua, index, field)? as i32),
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => PbValue::I64(read_integer(lua, index, field)?),
+        Kind::Uint32 | Kind::Fixed32 => PbValue::U32(read_integer(lua, index, field)? as u32),
+        Kind::Uint64 | Kind::Fixed64 => PbValue::U64(read_integer(lua, index, field)? as u64),
+        Kind::Bool => {
+            if !is_type(lua, index, td_rlua::LUA_TBOOLEAN) {
+                return Err(type_error(field, "boolean", lua, index));
+            }
+            PbValue::Bool(unsafe { td_rlua::lua_toboolean(lua, index) != 0 })
+        }
+        Kind::String => {
+            if !is_type(lua, index, td_rlua::LUA_TSTRING) {
+                return Err(type_error(field, "string", lua, index));
+            }
+            PbValue::String(String::lua_read_at_position(lua, index).unwrap_or_default())
+        }
+        Kind::Bytes => {
+            if !is_type(lua, index, td_rlua::LUA_TSTRING) {
+                return Err(type_error(field, "string", lua, index));
+            }
+            PbValue::Bytes(Bytes::from(LuaUtils::read_str_to_vec(lua, index).unwrap_or_default()))
+        }
+        Kind::Message(ref desc) => PbValue::Message(lua_to_message(lua, index, desc, depth + 1)?),
+        Kind::Enum(ref desc) => {
+            if is_type(lua, index, td_rlua::LUA_TSTRING) {
+                let name = String::lua_read_at_position(lua, index).unwrap_or_default();
+                match desc.get_value_by_name(&name) {
+                    Some(value) => PbValue::EnumNumber(value.number()),
+                    None => return Err(format!("field {} unknow enum value {}", field, name)),
+                }
+            } else {
+                PbValue::EnumNumber(read_integer(lua, index, field)? as i32)
+            }
+        }
+    };
+    Ok(value)
+}
+
+fn value_to_map_key(value: PbValue) -> Option<MapKey> {
+    match value {
+        PbValue::Bool(v) => Some(MapKey::Bool(v)),
+        PbValue::I32(v) => Some(MapKey::I32(v)),
+        PbValue::I64(v) => Some(MapKey::I64(v)),
+        PbValue::U32(v) => Some(MapKey::U32(v)),
+        PbValue::U64(v) => Some(MapKey::U64(v)),
+        PbValue::String(v) => Some(MapKey::String(v)),
+        _ => None,
+    }
+}
+
+fn lua_to_field(lua: *mut td_rlua::lua_State, index: i32, field: &FieldDescriptor, depth: usize) -> Result<PbValue, String> {
+    if !field.is_list() && !field.is_map() {
+        return lua_to_single(lua, index, &field.kind(), field.name(), depth);
+    }
+    if !is_type(lua, index, td_rlua::LUA_TTABLE) {
+        return Err(type_error(field.name(), "table", lua, index));
+    }
+    if depth > MAX_DEPTH || !unsafe { td_rlua::lua_checkstack(lua, 3) != 0 } {
+        return Err(format!("field {} nested too deep", field.name()));
+    }
+    let index = unsafe { td_rlua::lua_absindex(lua, index) };
+    if field.is_list() {
+        let mut list = vec![];
+        let len = unsafe { td_rlua::lua_rawlen(lua, index) };
+        for i in 1..(len + 1) {
+            unsafe { td_rlua::lua_rawgeti(lua, index, i as i32) };
+            let value = lua_to_single(lua, -1, &field.kind(), field.name(), depth);
+            unsafe { td_rlua::lua_settop(lua, -2) };
+            list.push(value?);
+        }
+        return Ok(PbValue::List(list));
+    }
+
+    let entry = match field.kind() {
+        Kind::Message(entry) => entry,
+        _ => return Err(format!("field {} map entry error", field.name())),
+    };
+    let key_kind = entry.map_entry_key_field().kind();
+    let value_kind = entry.map_entry_value_field().kind();
+    let mut map = ::std::collections::HashMap::new();
+    unsafe { td_rlua::lua_pushnil(lua) };
+    while unsafe { td_rlua::lua_next(lua, index) } != 0 {
+        // the key may be a number, the lua_next need the origin key, so convert the value first
+        let value = lua_to_single(lua, -1, &value_kind, field.name(), depth);
+        let key = lua_to_single(lua, -2, &key_kind, field.name(), depth);
+        unsafe { td_rlua::lua_settop(lua, -2) };
+        let (key, value) = match (key, value) {
+            (Ok(key), Ok(value)) => (key, value),
+            (Err(err), _) | (_, Err(err)) => {
+                unsafe { td_rlua::lua_settop(lua, -2) };
+                return Err(err);
+            }
+        };
+        let key = unwrap_or!(value_to_map_key(key), {
+            unsafe { td_rlua::lua_settop(lua, -2) };
+            return Err(format!("field {} map key error", field.name()));
+        });
+        map.insert(key, value);
+    }
+    Ok(PbValue::Map(map))
+}
+
+fn lua_to_message(lua: *mut td_rlua::lua_State, index: i32, desc: &MessageDescriptor, depth: usize) -> Result<DynamicMessage, String> {
+    if !is_type(lua, index, td_rlua::LUA_TTABLE) {
+        return Err(type_error(desc.full_name(), "table", lua, index));
+    }
+    if depth > MAX_DEPTH || !unsafe { td_rlua::lua_checkstack(lua, 3) != 0 } {
+        return Err(format!("message {} nested too deep", desc.full_name()));
+    }
+    let index = unsafe { td_rlua::lua_absindex(lua, index) };
+    let mut message = DynamicMessage::new(desc.clone());
+    for field in desc.fields() {
+        let name = unwrap_or!(CString::new(field.name()).ok(), continue);
+        unsafe { td_rlua::lua_getfield(lua, index, name.as_ptr()) };
+        if is_type(lua, -1, td_rlua::LUA_TNIL) {
+            unsafe { td_rlua::lua_settop(lua, -2) };
+            continue;
+        }
+        let value = lua_to_field(lua, -1, &field, depth);
+        unsafe { td_rlua::lua_settop(lua, -2) };
+        message.set_field(&field, value?);
+    }
+    Ok(message)
+}
+
+fn push_map_key(lua: *mut td_rlua::lua_State, key: &MapKey) {
+    match *key {
+        MapKey::Bool(v) => v.push_to_lua(lua),
+        MapKey::I32(v) => v.push_to_lua(lua),
+        MapKey::I64(v) => v.push_to_lua(lua),
+        MapKey::U32(v) => v.push_to_lua(lua),
+        MapKey::U64(v) => v.push_to_lua(lua),
+        MapKey::String(ref v) => push_bytes(lua, v.as_bytes()),
+    };
+}
+
+fn push_bytes(lua: *mut td_rlua::lua_State, val: &[u8]) -> i32 {
+    unsafe { td_rlua::lua_pushlstring(lua, val.as_ptr() as *const libc::c_char, val.len()) };
+    1
+}
+
+fn push_value(lua: *mut td_rlua::lua_State, value: &PbValue) {
+    match *value {
+        PbValue::Bool(v) => v.push_to_lua(lua),
+        PbValue::I32(v) => v.push_to_lua(lua),
+        PbValue::I64(v) => v.push_to_lua(lua),
+        PbValue::U32(v) => v.push_to_lua(lua),
+        PbValue::U64(v) => v.push_to_lua(lua),
+        PbValue::F32(v) => v.push_to_lua(lua),
+        PbValue::F64(v) => v.push_to_lua(lua),
+        PbValue::String(ref v) => push_bytes(lua, v.as_bytes()),
+        PbValue::Bytes(ref v) => push_bytes(lua, &v[..]),
+        PbValue::EnumNumber(v) => v.push_to_lua(lua),
+        PbValue::Message(ref v) => push_message(lua, v),
+        PbValue::List(ref list) => {
+            unsafe {
+                td_rlua::lua_checkstack(lua, 3);
+                td_rlua::lua_createtable(lua, list.len() as i32, 0);
+            }
+            for (i, v) in list.iter().enumerate() {
+                push_value(lua, v);
+                unsafe { td_rlua::lua_rawseti(lua, -2, (i + 1) as i32) };
+            }
+            1
+        }
+        PbValue::Map(ref map) => {
+            unsafe {
+                td_rlua::lua_checkstack(lua, 3);
+                td_rlua::lua_createtable(lua, 0, map.len() as i32);
+            }
+            for (k, v) in map.iter() {
+                push_map_key(lua, k);
+                push_value(lua, v);
+                unsafe { td_rlua::lua_settable(lua, -3) };
+            }
+            1
+        }
+    };
+}
+
+/// the field not set and has presence is nil, other fields are pushed with the default value
+fn push_message(lua: *mut td_rlua::lua_State, message: &DynamicMessage) -> i32 {
+    unsafe {
+        td_rlua::lua_checkstack(lua, 3);
+        td_rlua::lua_createtable(lua, 0, 0);
+    }
+    for field in message.descriptor().fields() {
+        if field.supports_presence() && !message.has_field(&field) {
+            continue;
+        }
+        let name = unwrap_or!(CString::new(field.name()).ok(), continue);
+        push_value(lua, &message.get_field(&field));
+        unsafe { td_rlua::lua_setfield(lua, -2, name.as_ptr()) };
+    }
+    1
+}
+
+impl EngineProtocol for ProtoProtobuf {
+    fn get_name(&self) -> &str {
+        "protobuf"
+    }
+
+    fn pack_protocol(&mut self, lua: *mut td_rlua::lua_State, index: i32) -> Option<NetMsg> {
+        let name: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, index), return None);
+        let desc = match self.find_message(&name) {
+            Ok(desc) => desc,
+            Err(err) => {
+                println!("pack protobuf failed name = {:?} err = {:?}", name, err);
+                return None;
+            }
+        };
+        let message = match lua_to_message(lua, index + 1, &desc, 0) {
+            Ok(message) => message,
+            Err(err) => {
+                println!("pack protobuf failed name = {:?} err = {:?}", name, err);
+                return None;
+            }
+        };
+        NetMsg::new_by_detail(MSG_TYPE_PROTOBUF, name, &message.encode_to_vec()).ok()
+    }
+
+    fn unpack_protocol(&mut self, lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<i32> {
+        let (name, message) = self.decode_message(net_msg)?;
+        name.push_to_lua(lua);
+        push_message(lua, &message);
+        Ok(2)
+    }
+
+    fn convert_string(&mut self, _: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<String> {
+        let (name, message) = self.decode_message(net_msg)?;
+        Ok(format!("{} {{ {} }}", name, message.to_text_format()))
+    }
+
+    fn load_schema(&mut self, path: &str) -> NetResult<()> {
+        self.load_path(path)
+    }
+}
@@ -1,13 +1,17 @@
-use td_rlua::{self, Lua, LuaPush};
-use tunm_proto;
+use td_rlua::{self, LuaPush};
+use tunm_proto::{self, Value};
 use super::EngineProtocol;
-use {NetMsg, NetUtils};
+use {NetMsg, NetUtils, JsonUtils};
 use {NetResult, LuaWrapperTableValue};
 
 pub struct ProtoRt;
 
 impl EngineProtocol for ProtoRt {
-    fn pack_protocol(lua: *mut td_rlua::lua_State, index: i32) -> Option<NetMsg> {
+    fn get_name(&self) -> &str {
+        "td"
+    }
+
+    fn pack_protocol(&mut self, lua: *mut td_rlua::lua_State, index: i32) -> Option<NetMsg> {
         let name: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, index), return None);
         let value = NetUtils::lua_convert_value(lua, index + 1);
         if value.is_none() {
@@ -26,7 +30,13 @@ impl EngineProtocol for ProtoRt {
         Some(net_msg)
     }
 
-    fn unpack_protocol(lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<i32> {
+    fn unpack_protocol(&mut self, lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<i32> {
+        // the args already decoded by the validation
+        if let Some(val) = net_msg.take_args() {
+            net_msg.get_pack_name().clone().push_to_lua(lua);
+            LuaWrapperTableValue(val).push_to_lua(lua);
+            return Ok(2);
+        }
         net_msg.set_read_data();
         if let Ok((name, val)) = tunm_proto::decode_proto(net_msg.get_buffer()) {
             name.push_to_lua(lua);
@@ -37,17 +47,12 @@ impl EngineProtocol for ProtoRt {
         }
     }
 
-    fn convert_string(lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<String> {
+    /// the json array `[name, args...]` as the ProtoJson
+    fn convert_string(&mut self, _: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<String> {
         net_msg.set_read_data();
-        if let Ok((name, val)) = tunm_proto::decode_proto(net_msg.get_buffer()) {
-            unsafe {
-                td_rlua::lua_settop(lua, 0);
-                name.push_to_lua(lua);
-                LuaWrapperTableValue(val).push_to_lua(lua);
-                let mut lua = Lua::from_existing_state(lua, false);
-                let json: String = unwrap_or!(lua.exec_func("arg_to_encode"), return Ok("".to_string()));
-                return Ok(json);
-            }
+        if let Ok((name, mut val)) = tunm_proto::decode_proto(net_msg.get_buffer()) {
+            val.insert(0, Value::Str(name));
+            return Ok(JsonUtils::to_string(&JsonUtils::value_to_json(Value::Arr(val))));
         } else {
             return Ok("".to_string());
         }
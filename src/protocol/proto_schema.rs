@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::fmt;
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::{Visitor, MapAccess};
+use serde_json::{self, Value as JsonValue};
+use tunm_proto::Value;
+use {FileUtils, NetResult, make_extension_error};
+
+/// the validation settings in the config, such as
+/// `proto_schema: {path: config/protocol.txt, max_str_len: 4096, kick: true}`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SchemaConfig {
+    pub path: String,
+    /// the max bytes of every str or raw in the message
+    pub max_str_len: Option<usize>,
+    /// the max len of every array in the message
+    pub max_array_len: Option<usize>,
+    /// kick the connection when the message invalid, default only drop the message
+    pub kick: Option<bool>,
+    /// the message not declared in the schema is invalid, default pass
+    pub reject_unknown: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaType {
+    None,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    Float,
+    Str,
+    Raw,
+    Map,
+}
+
+/// the pattern such as `u8` or `map[]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchemaPattern {
+    pub ty: SchemaType,
+    pub is_array: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaProto {
+    pub msg_type: String,
+    pub args: Vec<SchemaPattern>,
+    /// the arg declared by the field name such as `account` use the pattern of the field,
+    /// None if declared by the pattern such as `str`
+    pub arg_fields: Vec<Option<String>>,
+}
+
+/// the message schema of the protocol.txt, the `field` declare the value pattern of the map key,
+/// the `proto` declare the args pattern of the message
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProtoSchema {
+    fields: HashMap<String, SchemaPattern>,
+    indexes: HashMap<String, u32>,
+    protos: HashMap<String, SchemaProto>,
+}
+
+impl SchemaType {
+    fn by_name(name: &str) -> Option<SchemaType> {
+        let ty = match name {
+            "none" => SchemaType::None,
+            "u8" => SchemaType::U8,
+            "i8" => SchemaType::I8,
+            "u16" => SchemaType::U16,
+            "i16" => SchemaType::I16,
+            "u32" => SchemaType::U32,
+            "i32" => SchemaType::I32,
+            "float" => SchemaType::Float,
+            "str" => SchemaType::Str,
+            "raw" => SchemaType::Raw,
+            "map" => SchemaType::Map,
+            _ => return None,
+        };
+        Some(ty)
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match *self {
+            SchemaType::None => "none",
+            SchemaType::U8 => "u8",
+            SchemaType::I8 => "i8",
+            SchemaType::U16 => "u16",
+            SchemaType::I16 => "i16",
+            SchemaType::U32 => "u32",
+            SchemaType::I32 => "i32",
+            SchemaType::Float => "float",
+            SchemaType::Str => "str",
+            SchemaType::Raw => "raw",
+            SchemaType::Map => "map",
+        }
+    }
+
+    /// every value valid for the other type is valid for this type too, such as `u32` accept `u16`
+    pub fn accept(&self, other: SchemaType) -> bool {
+        match (*self, other) {
+            (a, b) if a == b => true,
+            (SchemaType::None, _) => true,
+            (SchemaType::Raw, SchemaType::Str) => true,
+            (SchemaType::Float, b) => b.range().is_some(),
+            (a, b) => {
+                match (a.range(), b.range()) {
+                    (Some((min, max)), Some((other_min, other_max))) => min <= other_min && max >= other_max,
+                    _ => false,
+                }
+            }
+        }
+    }
+
+    fn range(&self) -> Option<(i64, i64)> {
+        match *self {
+            SchemaType::U8 => Some((0, u8::MAX as i64)),
+            SchemaType::I8 => Some((i8::MIN as i64, i8::MAX as i64)),
+            SchemaType::U16 => Some((0, u16::MAX as i64)),
+            SchemaType::I16 => Some((i16::MIN as i64, i16::MAX as i64)),
+            SchemaType::U32 => Some((0, u32::MAX as i64)),
+            SchemaType::I32 => Some((i32::MIN as i64, i32::MAX as i64)),
+            _ => None,
+        }
+    }
+}
+
+impl SchemaPattern {
+    pub fn parse(pattern: &str) -> Option<SchemaPattern> {
+        let pattern = pattern.trim();
+        if let Some(name) = pattern.strip_suffix("[]") {
+            return SchemaType::by_name(name).map(|ty| SchemaPattern { ty, is_array: true });
+        }
+        SchemaType::by_name(pattern).map(|ty| SchemaPattern { ty, is_array: false })
+    }
+
+    pub fn accept(&self, other: &SchemaPattern) -> bool {
+        self.ty == SchemaType::None || (self.is_array == other.is_array && self.ty.accept(other.ty))
+    }
+
+    pub fn get_name(&self) -> String {
+        if self.is_array {
+            format!("{}[]", self.ty.get_name())
+        } else {
+            self.ty.get_name().to_string()
+        }
+    }
+}
+
+fn value_name(value: &Value) -> &'static str {
+    match *value {
+        Value::Nil => "nil",
+        Value::Bool(_) => "bool",
+        Value::U8(_) => "u8",
+        Value::I8(_) => "i8",
+        Value::U16(_) => "u16",
+        Value::I16(_) => "i16",
+        Value::U32(_) => "u32",
+        Value::I32(_) => "i32",
+        Value::U64(_) => "u64",
+        Value::I64(_) => "i64",
+        Value::Varint(_) => "varint",
+        Value::Float(_) => "float",
+        Value::Double(_) => "double",
+        Value::Str(_) => "str",
+        Value::Raw(_) => "raw",
+        Value::Arr(_) => "arr",
+        Value::Map(_) => "map",
+    }
+}
+
+fn value_integer(value: &Value) -> Option<i128> {
+    let val = match *value {
+        Value::U8(val) => val as i128,
+        Value::I8(val) => val as i128,
+        Value::U16(val) => val as i128,
+        Value::I16(val) => val as i128,
+        Value::U32(val) => val as i128,
+        Value::I32(val) => val as i128,
+        Value::U64(val) => val as i128,
+        Value::I64(val) => val as i128,
+        Value::Varint(val) => val as i128,
+        _ => return None,
+    };
+    Some(val)
+}
+
+fn parse_error<T>(detail: String) -> NetResult<T> {
+    Err(make_extension_error("parse proto schema failed", Some(&detail)))
+}
+
+/// the json object in order, serde_json silently keep the last one of the duplicate key,
+/// here the last one is kept too but the duplicate key is recorded
+#[derive(Default)]
+struct UniqueMap(Vec<(String, JsonValue)>, Vec<String>);
+
+struct UniqueMapVisitor;
+
+impl<'de> Visitor<'de> for UniqueMapVisitor {
+    type Value = UniqueMap;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a json object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<UniqueMap, A::Error> {
+        let mut entries: Vec<(String, JsonValue)> = vec![];
+        let mut duplicates = vec![];
+        let mut keys: HashMap<String, usize> = HashMap::new();
+        while let Some((key, value)) = access.next_entry::<String, JsonValue>()? {
+            if let Some(&i) = keys.get(&key) {
+                entries[i].1 = value;
+                duplicates.push(key);
+                continue;
+            }
+            keys.insert(key.clone(), entries.len());
+            entries.push((key, value));
+        }
+        Ok(UniqueMap(entries, duplicates))
+    }
+}
+
+impl<'de> Deserialize<'de> for UniqueMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<UniqueMap, D::Error> {
+        deserializer.deserialize_map(UniqueMapVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+struct SchemaFile {
+    #[serde(default)]
+    field: UniqueMap,
+    #[serde(default)]
+    proto: UniqueMap,
+}
+
+impl ProtoSchema {
+    pub fn load(path: &str) -> NetResult<ProtoSchema> {
+        let data = FileUtils::get_file_data(path)?;
+        ProtoSchema::parse(&String::from_utf8_lossy(&data))
+    }
+
+    /// the old protocol may be written before the duplicate key check, such as the baseline
+    /// of the compat diff, the last one of the duplicate is used and the duplicates returned
+    pub fn load_lenient(path: &str) -> NetResult<(ProtoSchema, Vec<String>)> {
+        let data = FileUtils::get_file_data(path)?;
+        ProtoSchema::parse_lenient(&String::from_utf8_lossy(&data))
+    }
+
+    /// the duplicate field or proto name is an error
+    pub fn parse(data: &str) -> NetResult<ProtoSchema> {
+        let (schema, duplicates) = ProtoSchema::parse_lenient(data)?;
+        if let Some(duplicate) = duplicates.first() {
+            return parse_error(duplicate.clone());
+        }
+        Ok(schema)
+    }
+
+    /// the duplicate is such as `duplicate field "COLUMN_NAME"`
+    pub fn parse_lenient(data: &str) -> NetResult<(ProtoSchema, Vec<String>)> {
+        let file: SchemaFile = match serde_json::from_str(data) {
+            Ok(file) => file,
+            Err(err) => return parse_error(err.to_string()),
+        };
+        let mut duplicates: Vec<String> = file.field.1.iter().map(|key| format!("duplicate field {:?}", key)).collect();
+        duplicates.extend(file.proto.1.iter().map(|key| format!("duplicate proto {:?}", key)));
+        let mut schema = ProtoSchema::default();
+        for (name, field) in &file.field.0 {
+            let pattern = field.get("pattern").and_then(|p| p.as_str()).unwrap_or("");
+            let pattern = unwrap_or!(SchemaPattern::parse(pattern),
+                return parse_error(format!("field {} unknow pattern {:?}", name, pattern)));
+            schema.fields.insert(name.clone(), pattern);
+            if let Some(index) = field.get("index").and_then(|i| i.as_u64()) {
+                schema.indexes.insert(name.clone(), index as u32);
+            }
+        }
+        for (name, proto) in &file.proto.0 {
+            let msg_type = proto.get("msg_type").and_then(|t| t.as_str()).unwrap_or("").to_string();
+            let mut args = vec![];
+            let mut arg_fields = vec![];
+            for arg in proto.get("args").and_then(|a| a.as_array()).iter().flat_map(|a| a.iter()) {
+                let arg = arg.as_str().unwrap_or("");
+                if let Some(pattern) = SchemaPattern::parse(arg) {
+                    args.push(pattern);
+                    arg_fields.push(None);
+                } else if let Some(pattern) = schema.fields.get(arg) {
+                    args.push(*pattern);
+                    arg_fields.push(Some(arg.to_string()));
+                } else {
+                    return parse_error(format!("proto {} unknow pattern {:?}", name, arg));
+                }
+            }
+            schema.protos.insert(name.clone(), SchemaProto { msg_type, args, arg_fields });
+        }
+        Ok((schema, duplicates))
+    }
+
+    /// the error of the definition can't be found by parse, such as two fields use one index
+    pub fn check(&self) -> Result<(), String> {
+        let mut names: Vec<&String> = self.indexes.keys().collect();
+        names.sort();
+        let mut used: HashMap<u32, &String> = HashMap::new();
+        for name in names {
+            let index = self.indexes[name];
+            if let Some(other) = used.insert(index, name) {
+                return Err(format!("field {} and {} use the same index {}", other, name, index));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<&SchemaPattern> {
+        self.fields.get(name)
+    }
+
+    /// the `index` of the field, the index of the removed field must not be reused
+    pub fn get_field_index(&self, name: &str) -> Option<u32> {
+        self.indexes.get(name).cloned()
+    }
+
+    pub fn get_proto(&self, name: &str) -> Option<&SchemaProto> {
+        self.protos.get(name)
+    }
+
+    pub fn get_fields(&self) -> &HashMap<String, SchemaPattern> {
+        &self.fields
+    }
+
+    pub fn get_protos(&self) -> &HashMap<String, SchemaProto> {
+        &self.protos
+    }
+
+    /// check the decoded message, the error is the detail reason such as
+    /// `cmd_login arg 1.account expect str but u32`
+    pub fn validate(&self, name: &str, args: &[Value], config: &SchemaConfig) -> Result<(), String> {
+        let proto = match self.protos.get(name) {
+            Some(proto) => proto,
+            None => {
+                if config.reject_unknown.unwrap_or(false) {
+                    return Err(format!("{} not declared", name));
+                }
+                return Ok(());
+            }
+        };
+        if args.len() > proto.args.len() {
+            return Err(format!("{} expect {} args but {}", name, proto.args.len(), args.len()));
+        }
+        for (i, pattern) in proto.args.iter().enumerate() {
+            let path = format!("{} arg {}", name, i + 1);
+            match args.get(i) {
+                None | Some(&Value::Nil) if pattern.ty != SchemaType::None => {
+                    return Err(format!("{} missing, expect {}", path, pattern.get_name()));
+                }
+                None => (),
+                Some(value) => self.check_pattern(&path, pattern, value, config)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn check_pattern(&self, path: &str, pattern: &SchemaPattern, value: &Value, config: &SchemaConfig) -> Result<(), String> {
+        if !pattern.is_array {
+            return self.check_value(path, pattern.ty, value, config);
+        }
+        match *value {
+            Value::Arr(ref list) => {
+                if let Some(max) = config.max_array_len {
+                    if list.len() > max {
+                        return Err(format!("{} array len {} > {}", path, list.len(), max));
+                    }
+                }
+                for (i, sub_value) in list.iter().enumerate() {
+                    self.check_value(&format!("{}[{}]", path, i + 1), pattern.ty, sub_value, config)?;
+                }
+                Ok(())
+            }
+            // the empty lua table is encoded as the map
+            Value::Map(ref map) if map.is_empty() => Ok(()),
+            _ => Err(format!("{} expect {} but {}", path, pattern.get_name(), value_name(value))),
+        }
+    }
+
+    fn check_value(&self, path: &str, ty: SchemaType, value: &Value, config: &SchemaConfig) -> Result<(), String> {
+        let mismatch = || Err(format!("{} expect {} but {}", path, ty.get_name(), value_name(value)));
+        match ty {
+            SchemaType::None => Ok(()),
+            SchemaType::Float => {
+                match *value {
+                    Value::Float(_) | Value::Double(_) => Ok(()),
+                    _ if value_integer(value).is_some() => Ok(()),
+                    _ => mismatch(),
+                }
+            }
+            SchemaType::Str | SchemaType::Raw => {
+                let len = match *value {
+                    Value::Str(ref val) => val.len(),
+                    Value::Raw(ref val) if ty == SchemaType::Raw => val.len(),
+                    _ => return mismatch(),
+                };
+                if let Some(max) = config.max_str_len {
+                    if len > max {
+                        return Err(format!("{} {} len {} > {}", path, ty.get_name(), len, max));
+                    }
+                }
+                Ok(())
+            }
+            SchemaType::Map => {
+                let map = match *value {
+                    Value::Map(ref map) => map,
+                    _ => return mismatch(),
+                };
+                for (key, sub_value) in map {
+                    let key = match *key {
+                        Value::Str(ref key) => key,
+                        _ => continue,
+                    };
+                    if let Some(pattern) = self.fields.get(key) {
+                        if *sub_value != Value::Nil {
+                            self.check_pattern(&format!("{}.{}", path, key), pattern, sub_value, config)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            _ => {
+                let (min, max) = ty.range().unwrap_or((i64::MIN, i64::MAX));
+                let val = match *value {
+                    Value::Bool(_) if ty == SchemaType::U8 || ty == SchemaType::I8 => return Ok(()),
+                    _ => unwrap_or!(value_integer(value), return mismatch()),
+                };
+                if val < min as i128 || val > max as i128 {
+                    return Err(format!("{} value {} out of {} range", path, val, ty.get_name()));
+                }
+                Ok(())
+            }
+        }
+    }
+}
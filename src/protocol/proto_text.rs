@@ -7,19 +7,22 @@ use {NetResult, LuaWrapperValue};
 pub struct ProtoText;
 
 impl EngineProtocol for ProtoText {
-    fn pack_protocol(lua: *mut td_rlua::lua_State, index: i32) -> Option<NetMsg> {
+    fn get_name(&self) -> &str {
+        "text"
+    }
+
+    fn pack_protocol(&mut self, lua: *mut td_rlua::lua_State, index: i32) -> Option<NetMsg> {
         unsafe {
             let name: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, index), return None);
             if td_rlua::lua_isstring(lua, index + 1) == 0 {
                 return None;
             }
             let text: String = unwrap_or!(td_rlua::LuaRead::lua_read_at_position(lua, index + 1), return None);
-            let net_msg = NetMsg::new_by_detail(MSG_TYPE_TEXT, name, &text.as_bytes()[..]);
-            Some(net_msg)
+            NetMsg::new_by_detail(MSG_TYPE_TEXT, name, &text.as_bytes()[..]).ok()
         }
     }
 
-    fn unpack_protocol(lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<i32> {
+    fn unpack_protocol(&mut self, lua: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<i32> {
                 net_msg.set_read_data();
         let name: String = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?.into();
         let raw: Value = decode_str_raw(net_msg.get_buffer(), TYPE_RAW)?;
@@ -28,7 +31,7 @@ impl EngineProtocol for ProtoText {
         return Ok(2);
     }
 
-    fn convert_string(_: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<String> {
+    fn convert_string(&mut self, _: *mut td_rlua::lua_State, net_msg: &mut NetMsg) -> NetResult<String> {
         net_msg.set_read_data();
         let _: String = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?.into();
         let raw: String = decode_str_raw(net_msg.get_buffer(), TYPE_STR)?.into();
@@ -19,6 +19,11 @@ impl Hash for LuaWrapperValue {
 
 
 
+/// the nested table need the key, value and table slots, the lua stack won't grow itself
+fn check_stack(lua: *mut lua_State, size: i32) -> bool {
+    unsafe { td_rlua::lua_checkstack(lua, size) != 0 }
+}
+
 impl LuaPush for LuaWrapperValue {
     fn push_to_lua(self, lua: *mut lua_State) -> i32 {
         match self.0 {
@@ -43,13 +48,26 @@ impl LuaPush for LuaWrapperValue {
                 1
             }
             Value::Map(mut val) => {
+                if !check_stack(lua, 3) {
+                    return ().push_to_lua(lua);
+                }
                 let mut wrapper_val: HashMap<LuaWrapperValue, LuaWrapperValue> = HashMap::new();
                 for (k, v) in val.drain() {
+                    // lua table can't use nil or nan as key, drop it instead of lua panic
+                    match k {
+                        Value::Nil => continue,
+                        Value::Float(f) if f.is_nan() => continue,
+                        Value::Double(f) if f.is_nan() => continue,
+                        _ => (),
+                    }
                     wrapper_val.insert(LuaWrapperValue(k), LuaWrapperValue(v));
                 }
                 wrapper_val.push_to_lua(lua)
             }
             Value::Arr(mut val) => {
+                if !check_stack(lua, 3) {
+                    return ().push_to_lua(lua);
+                }
                 let mut wrapper_val: Vec<LuaWrapperValue> = vec![];
                 for v in val.drain(..) {
                     wrapper_val.push(LuaWrapperValue(v));
@@ -76,6 +94,7 @@ pub struct LuaWrapperTableValue(pub Vec<Value>);
 impl LuaPush for LuaWrapperTableValue {
     fn push_to_lua(mut self, lua: *mut lua_State) -> i32 {
         unsafe {
+            check_stack(lua, 3);
             td_rlua::lua_newtable(lua);
             for (i, v) in self.0.drain(..).enumerate() {
                 td_rlua::lua_pushnumber(lua, (i + 1) as f64);
@@ -0,0 +1,200 @@
+use libc;
+use serde_json::{self, Map, Number};
+use serde_json::Value as JsonValue;
+use td_rlua::{self, lua_State, LuaPush, LuaRead};
+use tunm_proto::Value;
+use LuaUtils;
+
+/// the lua table may reference itself, stop the convert at this depth
+const MAX_DEPTH: usize = 64;
+
+/// convert between the lua value, the tunm_proto value and the json value,
+/// the sequence table is the array, the empty table is the object unless empty_as_array
+pub struct JsonUtils;
+
+fn read_key(lua: *mut lua_State, index: i32) -> Option<String> {
+    unsafe {
+        match td_rlua::lua_type(lua, index) {
+            td_rlua::LUA_TSTRING => {
+                let val = LuaUtils::read_str_to_vec(lua, index)?;
+                Some(String::from_utf8_lossy(&val).to_string())
+            }
+            td_rlua::LUA_TNUMBER => {
+                if LuaUtils::is_integer(lua, index) {
+                    let val: i64 = LuaRead::lua_read_at_position(lua, index)?;
+                    Some(val.to_string())
+                } else {
+                    let val: f64 = LuaRead::lua_read_at_position(lua, index)?;
+                    Some(val.to_string())
+                }
+            }
+            td_rlua::LUA_TBOOLEAN => Some((td_rlua::lua_toboolean(lua, index) != 0).to_string()),
+            _ => None,
+        }
+    }
+}
+
+fn lua_to_json(lua: *mut lua_State, index: i32, empty_as_array: bool, depth: usize) -> Option<JsonValue> {
+    unsafe {
+        let value = match td_rlua::lua_type(lua, index) {
+            td_rlua::LUA_TBOOLEAN => JsonValue::Bool(td_rlua::lua_toboolean(lua, index) != 0),
+            td_rlua::LUA_TNUMBER => {
+                if LuaUtils::is_integer(lua, index) {
+                    let val: i64 = LuaRead::lua_read_at_position(lua, index)?;
+                    JsonValue::from(val)
+                } else {
+                    let val: f64 = LuaRead::lua_read_at_position(lua, index)?;
+                    Number::from_f64(val).map(JsonValue::Number).unwrap_or(JsonValue::Null)
+                }
+            }
+            td_rlua::LUA_TSTRING => {
+                let val = LuaUtils::read_str_to_vec(lua, index)?;
+                JsonValue::String(String::from_utf8_lossy(&val).to_string())
+            }
+            td_rlua::LUA_TTABLE => {
+                if depth > MAX_DEPTH || td_rlua::lua_checkstack(lua, 3) == 0 {
+                    println!("json table nested too deep");
+                    return None;
+                }
+                let index = td_rlua::lua_absindex(lua, index);
+                match LuaUtils::get_array_len(lua, index) {
+                    Some(0) if empty_as_array => JsonValue::Array(vec![]),
+                    Some(len) if len > 0 => {
+                        let mut val = Vec::with_capacity(len);
+                        for i in 1..(len + 1) {
+                            td_rlua::lua_rawgeti(lua, index, i as i32);
+                            let sub_val = lua_to_json(lua, -1, empty_as_array, depth + 1);
+                            td_rlua::lua_settop(lua, -2);
+                            val.push(sub_val?);
+                        }
+                        JsonValue::Array(val)
+                    }
+                    _ => {
+                        let mut val = Map::new();
+                        td_rlua::lua_pushnil(lua);
+                        while td_rlua::lua_next(lua, index) != 0 {
+                            let sub_val = lua_to_json(lua, -1, empty_as_array, depth + 1);
+                            let key = read_key(lua, -2);
+                            td_rlua::lua_settop(lua, -2);
+                            match (key, sub_val) {
+                                (Some(key), Some(sub_val)) => {
+                                    val.insert(key, sub_val);
+                                }
+                                _ => {
+                                    td_rlua::lua_settop(lua, -2);
+                                    return None;
+                                }
+                            }
+                        }
+                        JsonValue::Object(val)
+                    }
+                }
+            }
+            _ => JsonValue::Null,
+        };
+        Some(value)
+    }
+}
+
+fn lua_args_to_json(lua: *mut lua_State, index: i32, empty_as_array: bool) -> Option<JsonValue> {
+    let size = unsafe { td_rlua::lua_gettop(lua) };
+    let mut args = vec![];
+    for i in index..(size + 1) {
+        args.push(lua_to_json(lua, i, empty_as_array, 0)?);
+    }
+    Some(JsonValue::Array(args))
+}
+
+fn push_json(lua: *mut lua_State, value: JsonValue) -> i32 {
+    match value {
+        JsonValue::Null => ().push_to_lua(lua),
+        JsonValue::Bool(val) => val.push_to_lua(lua),
+        JsonValue::Number(val) => {
+            if let Some(val) = val.as_i64() {
+                val.push_to_lua(lua)
+            } else {
+                val.as_f64().unwrap_or(0.0).push_to_lua(lua)
+            }
+        }
+        JsonValue::String(val) => val.push_to_lua(lua),
+        JsonValue::Array(val) => {
+            unsafe {
+                td_rlua::lua_checkstack(lua, 3);
+                td_rlua::lua_createtable(lua, val.len() as i32, 0);
+            }
+            for (i, v) in val.into_iter().enumerate() {
+                push_json(lua, v);
+                unsafe { td_rlua::lua_rawseti(lua, -2, (i + 1) as i32) };
+            }
+            1
+        }
+        JsonValue::Object(val) => {
+            unsafe {
+                td_rlua::lua_checkstack(lua, 3);
+                td_rlua::lua_createtable(lua, 0, val.len() as i32);
+            }
+            for (k, v) in val {
+                unsafe { td_rlua::lua_pushlstring(lua, k.as_ptr() as *const libc::c_char, k.len()) };
+                push_json(lua, v);
+                unsafe { td_rlua::lua_settable(lua, -3) };
+            }
+            1
+        }
+    }
+}
+
+fn value_key(value: Value) -> String {
+    match value {
+        Value::Str(val) => val,
+        Value::Raw(val) => String::from_utf8_lossy(&val).to_string(),
+        val => match JsonUtils::value_to_json(val) {
+            JsonValue::String(val) => val,
+            val => val.to_string(),
+        },
+    }
+}
+
+impl JsonUtils {
+    /// the lua value at index to json, None if the table nested too deep or has the table key
+    pub fn lua_to_json(lua: *mut lua_State, index: i32, empty_as_array: bool) -> Option<JsonValue> {
+        lua_to_json(lua, index, empty_as_array, 0)
+    }
+
+    /// all the lua values begin at index to the json array
+    pub fn lua_args_to_json(lua: *mut lua_State, index: i32, empty_as_array: bool) -> Option<JsonValue> {
+        lua_args_to_json(lua, index, empty_as_array)
+    }
+
+    /// push the json value to lua, the null is nil
+    pub fn push_json(lua: *mut lua_State, value: JsonValue) -> i32 {
+        push_json(lua, value)
+    }
+
+    pub fn value_to_json(value: Value) -> JsonValue {
+        match value {
+            Value::Nil => JsonValue::Null,
+            Value::Bool(val) => JsonValue::Bool(val),
+            Value::U8(val) => JsonValue::from(val),
+            Value::I8(val) => JsonValue::from(val),
+            Value::U16(val) => JsonValue::from(val),
+            Value::I16(val) => JsonValue::from(val),
+            Value::U32(val) => JsonValue::from(val),
+            Value::I32(val) => JsonValue::from(val),
+            Value::U64(val) => JsonValue::from(val),
+            Value::I64(val) => JsonValue::from(val),
+            Value::Varint(val) => JsonValue::from(val),
+            Value::Float(val) => Number::from_f64(val as f64).map(JsonValue::Number).unwrap_or(JsonValue::Null),
+            Value::Double(val) => Number::from_f64(val).map(JsonValue::Number).unwrap_or(JsonValue::Null),
+            Value::Str(val) => JsonValue::String(val),
+            Value::Raw(val) => JsonValue::String(String::from_utf8_lossy(&val).to_string()),
+            Value::Arr(val) => JsonValue::Array(val.into_iter().map(JsonUtils::value_to_json).collect()),
+            Value::Map(val) => {
+                JsonValue::Object(val.into_iter().map(|(k, v)| (value_key(k), JsonUtils::value_to_json(v))).collect())
+            }
+        }
+    }
+
+    pub fn to_string(value: &JsonValue) -> String {
+        serde_json::to_string(value).unwrap_or_default()
+    }
+}
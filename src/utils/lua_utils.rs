@@ -7,6 +7,42 @@ pub struct LuaUtils {
     
 }
 
+extern "C" {
+    fn lua_isinteger(lua: *mut libc::c_void, index: libc::c_int) -> libc::c_int;
+}
+
+fn is_integer(lua: *mut lua_State, index: i32) -> bool {
+    unsafe { lua_isinteger(lua as *mut libc::c_void, index) != 0 }
+}
+
+/// the keys must be 1..n without hole, the n may be 0 for the empty table
+fn array_len(lua: *mut lua_State, index: i32) -> Option<usize> {
+    unsafe {
+        if td_rlua::lua_type(lua, index) != td_rlua::LUA_TTABLE || td_rlua::lua_checkstack(lua, 2) == 0 {
+            return None;
+        }
+        let index = td_rlua::lua_absindex(lua, index);
+        let mut count = 0;
+        let mut max = 0;
+        td_rlua::lua_pushnil(lua);
+        while td_rlua::lua_next(lua, index) != 0 {
+            let key = if is_integer(lua, -2) { td_rlua::lua_tointegerx(lua, -2, ptr::null_mut()) } else { 0 };
+            td_rlua::lua_settop(lua, -2);
+            if key <= 0 {
+                td_rlua::lua_settop(lua, -2);
+                return None;
+            }
+            count += 1;
+            max = ::std::cmp::max(max, key as usize);
+        }
+        if max == count {
+            Some(count)
+        } else {
+            None
+        }
+    }
+}
+
 impl LuaUtils {
     pub fn read_str_to_vec(lua: *mut lua_State, index: i32) -> Option<Vec<u8>> {
         let mut size: libc::size_t = unsafe { mem::MaybeUninit::uninit().assume_init() };
@@ -20,4 +56,14 @@ impl LuaUtils {
         }
         Some(dst)
     }
-}
\ No newline at end of file
+
+    /// is the number at index stored as the lua integer, the float 1.0 is not
+    pub fn is_integer(lua: *mut lua_State, index: i32) -> bool {
+        is_integer(lua, index)
+    }
+
+    /// the length if the table is a sequence, the table with other keys or holes is a map
+    pub fn get_array_len(lua: *mut lua_State, index: i32) -> Option<usize> {
+        array_len(lua, index)
+    }
+}
@@ -5,6 +5,7 @@ pub mod net_utils;
 pub mod telnet_utils;
 pub mod log_utils;
 pub mod lua_utils;
+pub mod json_utils;
 
 pub use self::file_utils::FileUtils;
 pub use self::time_utils::TimeUtils;
@@ -13,3 +14,4 @@ pub use self::net_utils::NetUtils;
 pub use self::telnet_utils::TelnetUtils;
 pub use self::log_utils::LogUtils;
 pub use self::lua_utils::LuaUtils;
+pub use self::json_utils::JsonUtils;
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use crate::net::SocketEvent;
+use crate::net::{SocketEvent, STATS_FIELDS};
 use {MioEventMgr, LuaEngine};
 
 const BS: u8 = 8u8;
@@ -89,6 +89,27 @@ impl TelnetUtils {
         let _ = Self::send_client_msg(&client.unique, data.as_bytes());
     }
 
+    /// the command handled by engine, not send to lua
+    /// socket_stats [field] [num] : dump the top num connections order by field,
+    /// the line not exactly in this form such as `socket_stats_reset()` or `socket_stats = nil` is lua
+    fn exec_builtin(unique: &String, line: &str) -> bool {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.first() != Some(&"socket_stats") || args.len() > 3 {
+            return false;
+        }
+        let field = args.get(1).cloned().unwrap_or("bytes_in");
+        let num = match args.get(2) {
+            Some(num) => unwrap_or!(num.parse::<usize>().ok(), return false),
+            None => 10,
+        };
+        if !STATS_FIELDS.contains(&field) {
+            return false;
+        }
+        let info = MioEventMgr::instance().dump_socket_stats(field, num);
+        Self::send_client_msg(unique, info.as_bytes());
+        true
+    }
+
     pub fn check(client: &mut ClientInfo) -> bool {
         if client.records.len() > 1 && String::from_utf8_lossy(&client.records[0]) == "tunm" &&
            String::from_utf8_lossy(&client.records[1]) == "tunm" {
@@ -201,10 +222,11 @@ impl TelnetUtils {
                     }
                 }
 
-                let convert =
-                    LuaEngine::convert_excute_string(String::from_utf8_lossy(&client.data)
-                                                         .to_string());
-                LuaEngine::instance().apply_exec_string(convert);
+                let line = String::from_utf8_lossy(&client.data).to_string();
+                if !Self::exec_builtin(&client.unique, &line) {
+                    let convert = LuaEngine::convert_excute_string(line);
+                    LuaEngine::instance().apply_exec_string(convert);
+                }
                 client.data.clear();
                 client.ircdnum = -1;
                 client.ipos = 0;
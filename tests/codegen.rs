@@ -0,0 +1,142 @@
+extern crate tunm;
+extern crate tunm_proto;
+extern crate td_rlua;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use td_rlua::{Lua, RawString};
+use tunm::{MioEventMgr, NetMsg, ProtoCodegen, ProtoSchema};
+use tunm_proto::{Buffer, Value};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("codegen").join(name)
+}
+
+fn load_schema() -> ProtoSchema {
+    ProtoSchema::load(fixture("protocol.txt").to_str().unwrap()).unwrap()
+}
+
+/// run with `UPDATE_GOLDEN=1` to rewrite the golden file after the template changed
+fn check_golden(lang: &str) {
+    let code = ProtoCodegen::generate(&load_schema(), lang).unwrap();
+    let path = fixture(ProtoCodegen::get_file_name(lang).unwrap());
+    if env::var("UPDATE_GOLDEN").is_ok() {
+        fs::write(&path, &code).unwrap();
+    }
+    let golden = fs::read_to_string(&path).unwrap();
+    assert!(code == golden, "{} not match the generated, run with UPDATE_GOLDEN=1 if the change is expected", path.display());
+}
+
+#[test]
+fn golden_ts() {
+    check_golden("ts");
+}
+
+#[test]
+fn golden_cs() {
+    check_golden("cs");
+}
+
+#[test]
+fn golden_lua() {
+    check_golden("lua");
+}
+
+fn encode(name: &str, args: Vec<Value>) -> Vec<u8> {
+    let mut net_msg = NetMsg::new();
+    tunm_proto::encode_proto(net_msg.get_buffer(), &name.to_string(), args).unwrap();
+    net_msg.end_msg();
+    let wpos = net_msg.get_wpos();
+    net_msg.get_buffer().get_data()[..wpos].to_vec()
+}
+
+fn decode(data: &[u8]) -> Vec<(String, Vec<Value>)> {
+    let mut buffer = Buffer::new();
+    buffer.write_all(data).unwrap();
+    let mut messages = vec![];
+    while let Some(frame) = MioEventMgr::get_next_message(&mut buffer) {
+        let mut net_msg = NetMsg::new_by_data(&frame).unwrap();
+        net_msg.set_read_data();
+        messages.push(tunm_proto::decode_proto(net_msg.get_buffer()).unwrap());
+    }
+    assert_eq!(buffer.data_len(), 0);
+    messages
+}
+
+fn new_map(items: Vec<(&str, Value)>) -> Value {
+    let mut map = HashMap::new();
+    for (key, value) in items {
+        map.insert(Value::from(key.to_string()), value);
+    }
+    Value::Map(map)
+}
+
+fn str_value(val: &str) -> Value {
+    Value::from(val.to_string())
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+}
+
+const LUA_ROUND_TRIP: &str = r#"
+    local TP = load(SDK_CODE)()
+    local reader = TP.new_frame_reader()
+    local errors = 0
+    reader.on_error = function(err, frame)
+        errors = errors + 1
+    end
+    local out = {}
+    for _, chunk in ipairs({ CHUNK1, CHUNK2 }) do
+        for _, m in ipairs(reader.push(chunk)) do
+            out[#out + 1] = TP.encode_message(m.name, m.args, TP.PROTO_ARGS[m.name], m.head, m.n)
+        end
+    end
+    ERRORS = errors
+    OUT_HEX = (table.concat(out):gsub(".", function(c) return string.format("%02x", c:byte()) end))
+"#;
+
+/// the messages encoded by tunm_proto are split by the lua sdk frame reader, then encoded
+/// by the lua sdk again, the result decoded by tunm_proto must be the same
+#[test]
+fn lua_round_trip() {
+    let messages = vec![
+        ("cmd_login", vec![str_value("r1001"), Value::from(30000u32), new_map(vec![
+            ("rid", str_value("r1001")),
+            ("lv", Value::U8(9)),
+            ("gold", Value::from(500u32)),
+            ("rate", Value::Float(1.5)),
+            ("avatar", Value::Raw(vec![0, 1, 255])),
+            ("tags", Value::Arr(vec![str_value("vip"), str_value("r1001")])),
+            ("items", Value::Arr(vec![new_map(vec![("gold", Value::from(7u32))])])),
+        ])]),
+        ("cmd_numbers", vec![Value::U8(255), Value::I8(-128), Value::U16(65535), Value::I16(-32768),
+                             Value::I32(i32::MIN), Value::Float(-2.25)]),
+        ("msg_arrays", vec![Value::Arr(vec![Value::U8(1), Value::U8(2)]), Value::Arr(vec![]),
+                            Value::Arr(vec![new_map(vec![("lv", Value::U8(3))])])]),
+        ("msg_any", vec![Value::Raw(vec![0, 0, 10, 13]), Value::Nil]),
+    ];
+    let frames: Vec<Vec<u8>> = messages.iter().map(|(name, args)| encode(name, args.clone())).collect();
+    let expected = decode(&frames.concat());
+
+    // the bad frame is skipped, the frames before and after it are kept
+    let mut bad = frames[1][..26].to_vec();
+    bad[0] = 29;
+    bad.extend_from_slice(&[0xFF, 0xFF, 0xFF]);
+    let stream = [frames[0].clone(), frames[1].clone(), bad, frames[2].clone(), frames[3].clone()].concat();
+    let split = frames[0].len() + frames[1].len() + 40;
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.set("SDK_CODE", ProtoCodegen::generate(&load_schema(), "lua").unwrap());
+    lua.set("CHUNK1", RawString(stream[..split].to_vec()));
+    lua.set("CHUNK2", RawString(stream[split..].to_vec()));
+    let _: Option<()> = lua.exec_string(LUA_ROUND_TRIP);
+    assert_eq!(lua.query::<i32, _>("ERRORS"), Some(1));
+    let out = from_hex(&lua.query::<String, _>("OUT_HEX").unwrap());
+    assert_eq!(decode(&out), expected);
+}
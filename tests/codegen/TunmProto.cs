@@ -0,0 +1,651 @@
+// the client sdk of the tunm td protocol, generated by proto_gen from protocol.txt, do not edit
+using System;
+using System.Collections;
+using System.Collections.Generic;
+using System.Text;
+
+namespace Tunm
+{
+    /// the 26 bytes head before every message, all numbers are little endian
+    public class NetMsgHead
+    {
+        public uint Length;
+        public uint Cookie;
+        public byte MsgType;
+        public byte MsgFlag;
+        public ushort FromSvrType;
+        public uint FromSvrId;
+        public ushort ToSvrType;
+        public uint ToSvrId;
+        public uint RealFd;
+    }
+
+    public class ProtoMessage
+    {
+        public NetMsgHead Head;
+        public string Name;
+        public List<object> Args;
+    }
+
+    /// the values are null, bool, long, double, string, byte[], List<object> and Dictionary<string, object>
+    public static partial class TunmProto
+    {
+        public const byte MSG_TYPE_TD = 0;
+        public const int HEAD_LEN = 26;
+
+        const byte TYPE_NIL = 0;
+        const byte TYPE_BOOL = 1;
+        const byte TYPE_U8 = 2;
+        const byte TYPE_I8 = 3;
+        const byte TYPE_U16 = 4;
+        const byte TYPE_I16 = 5;
+        const byte TYPE_U32 = 6;
+        const byte TYPE_I32 = 7;
+        const byte TYPE_U64 = 8;
+        const byte TYPE_I64 = 9;
+        const byte TYPE_VARINT = 10;
+        const byte TYPE_FLOAT = 11;
+        const byte TYPE_DOUBLE = 12;
+        const byte TYPE_STR = 13;
+        const byte TYPE_STR_IDX = 14;
+        const byte TYPE_RAW = 15;
+        const byte TYPE_ARR = 16;
+        const byte TYPE_MAP = 17;
+
+        class Writer
+        {
+            public List<byte> Bytes = new List<byte>();
+            public List<string> Strs = new List<string>();
+            Dictionary<string, int> strIdx = new Dictionary<string, int>();
+
+            public void U8(long val)
+            {
+                Bytes.Add((byte)(val & 0xFF));
+            }
+
+            public void Varint(long val)
+            {
+                ulong real = val >= 0 ? (ulong)val * 2 : (ulong)(-(val + 1)) * 2 + 1;
+                do
+                {
+                    byte data = (byte)(real & 0x7F);
+                    real >>= 7;
+                    if (real > 0)
+                    {
+                        data |= 0x80;
+                    }
+                    Bytes.Add(data);
+                } while (real > 0);
+            }
+
+            public void Raw(byte[] data)
+            {
+                Varint(data.Length);
+                Bytes.AddRange(data);
+            }
+
+            public void StrIndex(string val)
+            {
+                int idx;
+                if (!strIdx.TryGetValue(val, out idx))
+                {
+                    idx = Strs.Count;
+                    Strs.Add(val);
+                    strIdx[val] = idx;
+                }
+                U8(TYPE_STR_IDX);
+                Varint(idx);
+            }
+        }
+
+        class Reader
+        {
+            byte[] data;
+            int length;
+            public int Pos;
+            public List<string> Strs = new List<string>();
+
+            public Reader(byte[] data, int length)
+            {
+                this.data = data;
+                this.length = length;
+            }
+
+            public byte U8()
+            {
+                if (Pos >= length)
+                {
+                    throw new Exception("tunm proto read out of range");
+                }
+                return data[Pos++];
+            }
+
+            public ulong UInt(int size)
+            {
+                ulong val = 0;
+                for (int i = 0; i < size; i++)
+                {
+                    val |= (ulong)U8() << (8 * i);
+                }
+                return val;
+            }
+
+            public long Varint()
+            {
+                ulong real = 0;
+                int shift = 0;
+                while (true)
+                {
+                    byte data = U8();
+                    if (shift > 63)
+                    {
+                        throw new Exception("tunm proto too big varint");
+                    }
+                    real |= (ulong)(data & 0x7F) << shift;
+                    shift += 7;
+                    if ((data & 0x80) == 0)
+                    {
+                        break;
+                    }
+                }
+                return real % 2 == 1 ? -(long)(real / 2) - 1 : (long)(real / 2);
+            }
+
+            public byte[] Raw()
+            {
+                long len = Varint();
+                if (len < 0 || Pos + len > length)
+                {
+                    throw new Exception("tunm proto read out of range");
+                }
+                byte[] val = new byte[len];
+                Array.Copy(data, Pos, val, 0, len);
+                Pos += (int)len;
+                return val;
+            }
+
+            public string Str()
+            {
+                return Encoding.UTF8.GetString(Raw());
+            }
+
+            public object Field()
+            {
+                byte ty = U8();
+                switch (ty)
+                {
+                    case TYPE_NIL: return null;
+                    case TYPE_BOOL: return U8() == 1;
+                    case TYPE_U8: return (long)U8();
+                    case TYPE_I8: return (long)(sbyte)U8();
+                    case TYPE_U16: return (long)(ushort)UInt(2);
+                    case TYPE_I16: return (long)(short)UInt(2);
+                    case TYPE_U32: return (long)(uint)UInt(4);
+                    case TYPE_I32: return (long)(int)UInt(4);
+                    case TYPE_U64: return (long)UInt(8);
+                    case TYPE_I64: return (long)UInt(8);
+                    case TYPE_VARINT: return Varint();
+                    case TYPE_FLOAT: return Varint() / 1000.0;
+                    case TYPE_DOUBLE: return Varint() / 1000000.0;
+                    case TYPE_STR: return Str();
+                    case TYPE_STR_IDX:
+                        {
+                            long idx = Varint();
+                            if (idx < 0 || idx >= Strs.Count)
+                            {
+                                throw new Exception("tunm proto unknow str idx " + idx);
+                            }
+                            return Strs[(int)idx];
+                        }
+                    case TYPE_RAW: return Raw();
+                    case TYPE_ARR:
+                        {
+                            long len = Varint();
+                            List<object> arr = new List<object>();
+                            for (long i = 0; i < len; i++)
+                            {
+                                arr.Add(Field());
+                            }
+                            return arr;
+                        }
+                    case TYPE_MAP:
+                        {
+                            long len = Varint();
+                            Dictionary<string, object> map = new Dictionary<string, object>();
+                            for (long i = 0; i < len; i++)
+                            {
+                                object key = Field();
+                                map[Convert.ToString(key)] = Field();
+                            }
+                            return map;
+                        }
+                }
+                throw new Exception("tunm proto unknow type " + ty);
+            }
+        }
+
+        static void EncodeMap(Writer writer, IDictionary<string, object> map)
+        {
+            List<string> keys = new List<string>();
+            foreach (KeyValuePair<string, object> pair in map)
+            {
+                if (pair.Value != null)
+                {
+                    keys.Add(pair.Key);
+                }
+            }
+            writer.U8(TYPE_MAP);
+            writer.Varint(keys.Count);
+            foreach (string key in keys)
+            {
+                writer.StrIndex(key);
+                string pattern;
+                FieldPatterns.TryGetValue(key, out pattern);
+                EncodeField(writer, map[key], pattern);
+            }
+        }
+
+        static bool IsInteger(object value)
+        {
+            return value is sbyte || value is byte || value is short || value is ushort
+                || value is int || value is uint || value is long || value is ulong;
+        }
+
+        /// encode by the pattern of protocol.txt, the value without pattern is encoded by its type
+        static void EncodeField(Writer writer, object value, string pattern)
+        {
+            if (value == null)
+            {
+                writer.U8(TYPE_NIL);
+                return;
+            }
+            if (pattern != null && pattern.EndsWith("[]"))
+            {
+                IList list = value as IList ?? new List<object>();
+                writer.U8(TYPE_ARR);
+                writer.Varint(list.Count);
+                foreach (object sub in list)
+                {
+                    EncodeField(writer, sub, pattern.Substring(0, pattern.Length - 2));
+                }
+                return;
+            }
+            switch (pattern)
+            {
+                case "u8":
+                    writer.U8(TYPE_U8);
+                    writer.U8(Convert.ToInt64(value));
+                    return;
+                case "i8":
+                    writer.U8(TYPE_I8);
+                    writer.U8(Convert.ToInt64(value));
+                    return;
+                case "u16":
+                case "i16":
+                case "u32":
+                case "i32":
+                    writer.U8(TYPE_VARINT);
+                    writer.Varint(Convert.ToInt64(value));
+                    return;
+                case "float":
+                    writer.U8(TYPE_FLOAT);
+                    writer.Varint((long)(Convert.ToDouble(value) * 1000));
+                    return;
+                case "str":
+                    writer.StrIndex(Convert.ToString(value));
+                    return;
+                case "raw":
+                    writer.U8(TYPE_RAW);
+                    writer.Raw(value as byte[] ?? Encoding.UTF8.GetBytes(Convert.ToString(value)));
+                    return;
+                case "map":
+                    EncodeMap(writer, value as IDictionary<string, object> ?? new Dictionary<string, object>());
+                    return;
+            }
+            if (value is bool)
+            {
+                writer.U8(TYPE_BOOL);
+                writer.U8((bool)value ? 1 : 0);
+            }
+            else if (IsInteger(value))
+            {
+                writer.U8(TYPE_VARINT);
+                writer.Varint(Convert.ToInt64(value));
+            }
+            else if (value is float || value is double || value is decimal)
+            {
+                writer.U8(TYPE_FLOAT);
+                writer.Varint((long)(Convert.ToDouble(value) * 1000));
+            }
+            else if (value is string)
+            {
+                writer.StrIndex((string)value);
+            }
+            else if (value is byte[])
+            {
+                writer.U8(TYPE_RAW);
+                writer.Raw((byte[])value);
+            }
+            else if (value is IDictionary<string, object>)
+            {
+                EncodeMap(writer, (IDictionary<string, object>)value);
+            }
+            else if (value is IList)
+            {
+                IList list = (IList)value;
+                writer.U8(TYPE_ARR);
+                writer.Varint(list.Count);
+                foreach (object sub in list)
+                {
+                    EncodeField(writer, sub, null);
+                }
+            }
+            else
+            {
+                throw new Exception("tunm proto unsupport type " + value.GetType());
+            }
+        }
+
+        static void WriteUInt(byte[] bytes, int pos, ulong val, int size)
+        {
+            for (int i = 0; i < size; i++)
+            {
+                bytes[pos + i] = (byte)((val >> (8 * i)) & 0xFF);
+            }
+        }
+
+        /// encode the whole message with the head, the args are encoded by the patterns
+        public static byte[] EncodeMessage(string name, IList<object> args, string[] patterns = null, NetMsgHead head = null)
+        {
+            Writer body = new Writer();
+            body.U8(TYPE_ARR);
+            body.Varint(args.Count);
+            for (int i = 0; i < args.Count; i++)
+            {
+                EncodeField(body, args[i], patterns != null && i < patterns.Length ? patterns[i] : null);
+            }
+
+            Writer output = new Writer();
+            output.Bytes.AddRange(new byte[HEAD_LEN]);
+            output.Raw(Encoding.UTF8.GetBytes(name));
+            output.Varint(body.Strs.Count);
+            foreach (string str in body.Strs)
+            {
+                output.Raw(Encoding.UTF8.GetBytes(str));
+            }
+            output.Bytes.AddRange(body.Bytes);
+
+            byte[] bytes = output.Bytes.ToArray();
+            NetMsgHead h = head ?? new NetMsgHead();
+            WriteUInt(bytes, 0, (ulong)bytes.Length, 4);
+            WriteUInt(bytes, 4, h.Cookie, 4);
+            WriteUInt(bytes, 8, h.MsgType, 1);
+            WriteUInt(bytes, 9, h.MsgFlag, 1);
+            WriteUInt(bytes, 10, h.FromSvrType, 2);
+            WriteUInt(bytes, 12, h.FromSvrId, 4);
+            WriteUInt(bytes, 16, h.ToSvrType, 2);
+            WriteUInt(bytes, 18, h.ToSvrId, 4);
+            WriteUInt(bytes, 22, h.RealFd, 4);
+            return bytes;
+        }
+
+        /// read the head, null if the data less than HEAD_LEN
+        public static NetMsgHead ReadHead(byte[] data, int length)
+        {
+            if (length < HEAD_LEN)
+            {
+                return null;
+            }
+            Reader reader = new Reader(data, length);
+            NetMsgHead head = new NetMsgHead();
+            head.Length = (uint)reader.UInt(4);
+            head.Cookie = (uint)reader.UInt(4);
+            head.MsgType = (byte)reader.UInt(1);
+            head.MsgFlag = (byte)reader.UInt(1);
+            head.FromSvrType = (ushort)reader.UInt(2);
+            head.FromSvrId = (uint)reader.UInt(4);
+            head.ToSvrType = (ushort)reader.UInt(2);
+            head.ToSvrId = (uint)reader.UInt(4);
+            head.RealFd = (uint)reader.UInt(4);
+            return head;
+        }
+
+        /// decode the whole message with the head
+        public static ProtoMessage DecodeMessage(byte[] data)
+        {
+            NetMsgHead head = ReadHead(data, data.Length);
+            if (head == null || head.Length > data.Length)
+            {
+                throw new Exception("tunm proto message not complete");
+            }
+            Reader reader = new Reader(data, (int)head.Length);
+            reader.Pos = HEAD_LEN;
+            ProtoMessage message = new ProtoMessage();
+            message.Head = head;
+            message.Name = reader.Str();
+            long strLen = reader.Varint();
+            for (long i = 0; i < strLen; i++)
+            {
+                reader.Strs.Add(reader.Str());
+            }
+            message.Args = reader.Field() as List<object>;
+            if (message.Args == null)
+            {
+                throw new Exception("tunm proto is not array");
+            }
+            return message;
+        }
+
+        public static long ToLong(object value)
+        {
+            return value == null ? 0 : Convert.ToInt64(value);
+        }
+
+        public static double ToDouble(object value)
+        {
+            return value == null ? 0 : Convert.ToDouble(value);
+        }
+
+        public static string ToStr(object value)
+        {
+            if (value is byte[])
+            {
+                return Encoding.UTF8.GetString((byte[])value);
+            }
+            return value == null ? null : Convert.ToString(value);
+        }
+
+        public static byte[] ToRaw(object value)
+        {
+            if (value is string)
+            {
+                return Encoding.UTF8.GetBytes((string)value);
+            }
+            return value as byte[];
+        }
+
+        public static Dictionary<string, object> ToMap(object value)
+        {
+            return value as Dictionary<string, object> ?? new Dictionary<string, object>();
+        }
+
+        public static List<T> ToList<T>(object value, Func<object, T> convert)
+        {
+            List<T> list = new List<T>();
+            IList arr = value as IList;
+            if (arr != null)
+            {
+                foreach (object sub in arr)
+                {
+                    list.Add(convert(sub));
+                }
+            }
+            return list;
+        }
+
+        public static object Arg(ProtoMessage message, int index)
+        {
+            return index < message.Args.Count ? message.Args[index] : null;
+        }
+    }
+
+    /// split the stream data to the messages, keep the incomplete tail
+    /// the frame failed to decode is skipped and reported to OnError
+    public class FrameReader
+    {
+        List<byte> buffer = new List<byte>();
+        public Action<Exception, byte[]> OnError;
+
+        public List<ProtoMessage> Push(byte[] data, int offset, int count)
+        {
+            for (int i = 0; i < count; i++)
+            {
+                buffer.Add(data[offset + i]);
+            }
+            List<ProtoMessage> messages = new List<ProtoMessage>();
+            while (true)
+            {
+                byte[] bytes = buffer.ToArray();
+                NetMsgHead head = TunmProto.ReadHead(bytes, bytes.Length);
+                if (head == null || head.Length > bytes.Length)
+                {
+                    break;
+                }
+                int length = Math.Max((int)head.Length, TunmProto.HEAD_LEN);
+                byte[] frame = new byte[length];
+                Array.Copy(bytes, frame, length);
+                buffer.RemoveRange(0, length);
+                try
+                {
+                    messages.Add(TunmProto.DecodeMessage(frame));
+                }
+                catch (Exception err)
+                {
+                    if (OnError != null)
+                    {
+                        OnError(err, frame);
+                    }
+                }
+            }
+            return messages;
+        }
+    }
+
+    public static partial class TunmProto
+    {
+        static readonly Dictionary<string, string> FieldPatterns = new Dictionary<string, string>
+        {
+            { "avatar", "raw" },
+            { "gold", "u32" },
+            { "items", "map[]" },
+            { "lv", "u8" },
+            { "rate", "float" },
+            { "rid", "str" },
+            { "tags", "str[]" },
+        };
+
+        public static readonly Dictionary<string, string[]> ProtoArgs = new Dictionary<string, string[]>
+        {
+            { "cmd_login", new string[] { "str", "u32", "map" } },
+            { "cmd_numbers", new string[] { "u8", "i8", "u16", "i16", "i32", "float" } },
+            { "msg_any", new string[] { "raw", "none" } },
+            { "msg_arrays", new string[] { "u8[]", "str[]", "map[]" } },
+        };
+    }
+
+    /// cmd_login, msg_type logic, args [str, u32, map]
+    public sealed class CmdLogin
+    {
+        public const string Name = "cmd_login";
+        public string Arg1;
+        public uint Arg2;
+        public Dictionary<string, object> Arg3;
+
+        public byte[] Encode(NetMsgHead head = null)
+        {
+            return TunmProto.EncodeMessage(Name, new List<object> { Arg1, Arg2, Arg3 }, TunmProto.ProtoArgs[Name], head);
+        }
+
+        public static CmdLogin From(ProtoMessage message)
+        {
+            CmdLogin msg = new CmdLogin();
+            msg.Arg1 = TunmProto.ToStr(TunmProto.Arg(message, 0));
+            msg.Arg2 = (uint)TunmProto.ToLong(TunmProto.Arg(message, 1));
+            msg.Arg3 = TunmProto.ToMap(TunmProto.Arg(message, 2));
+            return msg;
+        }
+    }
+
+    /// cmd_numbers, msg_type logic, args [u8, i8, u16, i16, i32, float]
+    public sealed class CmdNumbers
+    {
+        public const string Name = "cmd_numbers";
+        public byte Arg1;
+        public sbyte Arg2;
+        public ushort Arg3;
+        public short Arg4;
+        public int Arg5;
+        public float Arg6;
+
+        public byte[] Encode(NetMsgHead head = null)
+        {
+            return TunmProto.EncodeMessage(Name, new List<object> { Arg1, Arg2, Arg3, Arg4, Arg5, Arg6 }, TunmProto.ProtoArgs[Name], head);
+        }
+
+        public static CmdNumbers From(ProtoMessage message)
+        {
+            CmdNumbers msg = new CmdNumbers();
+            msg.Arg1 = (byte)TunmProto.ToLong(TunmProto.Arg(message, 0));
+            msg.Arg2 = (sbyte)TunmProto.ToLong(TunmProto.Arg(message, 1));
+            msg.Arg3 = (ushort)TunmProto.ToLong(TunmProto.Arg(message, 2));
+            msg.Arg4 = (short)TunmProto.ToLong(TunmProto.Arg(message, 3));
+            msg.Arg5 = (int)TunmProto.ToLong(TunmProto.Arg(message, 4));
+            msg.Arg6 = (float)TunmProto.ToDouble(TunmProto.Arg(message, 5));
+            return msg;
+        }
+    }
+
+    /// msg_any, msg_type client, args [raw, none]
+    public sealed class MsgAny
+    {
+        public const string Name = "msg_any";
+        public byte[] Arg1;
+        public object Arg2;
+
+        public byte[] Encode(NetMsgHead head = null)
+        {
+            return TunmProto.EncodeMessage(Name, new List<object> { Arg1, Arg2 }, TunmProto.ProtoArgs[Name], head);
+        }
+
+        public static MsgAny From(ProtoMessage message)
+        {
+            MsgAny msg = new MsgAny();
+            msg.Arg1 = TunmProto.ToRaw(TunmProto.Arg(message, 0));
+            msg.Arg2 = TunmProto.Arg(message, 1);
+            return msg;
+        }
+    }
+
+    /// msg_arrays, msg_type client, args [u8[], str[], map[]]
+    public sealed class MsgArrays
+    {
+        public const string Name = "msg_arrays";
+        public List<byte> Arg1;
+        public List<string> Arg2;
+        public List<Dictionary<string, object>> Arg3;
+
+        public byte[] Encode(NetMsgHead head = null)
+        {
+            return TunmProto.EncodeMessage(Name, new List<object> { Arg1, Arg2, Arg3 }, TunmProto.ProtoArgs[Name], head);
+        }
+
+        public static MsgArrays From(ProtoMessage message)
+        {
+            MsgArrays msg = new MsgArrays();
+            msg.Arg1 = TunmProto.ToList(TunmProto.Arg(message, 0), v => (byte)TunmProto.ToLong(v));
+            msg.Arg2 = TunmProto.ToList(TunmProto.Arg(message, 1), v => TunmProto.ToStr(v));
+            msg.Arg3 = TunmProto.ToList(TunmProto.Arg(message, 2), v => TunmProto.ToMap(v));
+            return msg;
+        }
+    }
+}